@@ -1,25 +1,294 @@
-use graphic_handler::graphic_config::GraphicConfig;
+use clap::{Parser, Subcommand};
+use graphic_handler::audio;
+use graphic_handler::graphic_config::{GraphicConfig, MonitorSelector, RecordFormat, WindowMode};
 use graphic_handler::GraphicContext;
+use media_handler::database::DbConnection;
+use media_handler::embedding::{self, Embedding, OnnxEmbedder};
 use media_handler::frame::Frame;
+use media_handler::indexer::Indexer;
 use media_handler::media_config::MediaConfig;
-use media_handler::MediaHandler;
+use media_handler::perceptual;
+use media_handler::replay::MediaReplay;
+use media_handler::search::SearchQuery;
+use media_handler::session::{Session, CLUSTER_STREAM};
+use media_handler::similarity::{self, Feature, Seed};
+use media_handler::tagger::{OnnxTagger, TagWriter};
+use media_handler::{MediaCommand, MediaHandler};
 
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "CUDI, custom diaporama")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Window placement: windowed, borderless or exclusive
+    #[arg(long)]
+    window_mode: Option<String>,
+
+    /// Monitor index or name
+    #[arg(long)]
+    monitor: Option<String>,
+
+    /// Keep the window above the others, --always-on-top=false to disable it
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    always_on_top: Option<bool>,
+
+    /// Seconds of inactivity before hiding the cursor, 0 keep it shown
+    #[arg(long)]
+    hide_cursor_after: Option<f32>,
+
+    /// Record the show from the launch, F9 toggle it, --record=false to disable it
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    record: Option<bool>,
+
+    /// Record format: png or video
+    #[arg(long)]
+    record_format: Option<String>,
+
+    /// Seed of the show randomness, random by default
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Session event log, written in sessions by default, out of the data folder the index walk
+    #[arg(long)]
+    log: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Play a session again from its event log
+    Replay { log: PathBuf },
+    /// Add or update the images of a folder in the database, remove the missing ones
+    Index { folder: PathBuf },
+    /// Print the database media matching a search, e.g. tag:night -format:gif color:#1040ff
+    Search {
+        #[arg(required = true, allow_hyphen_values = true)]
+        query: Vec<String>,
+    },
+    /// Print the database media closest in colors to an image or hex colors, e.g. "#10204a #e0a030"
+    Similar {
+        seed: String,
+        /// Number of media returned
+        #[arg(short, default_value_t = 10)]
+        k: usize,
+    },
+    /// Print the groups of near-duplicate media of the database
+    Dedupe {
+        /// Differing hash bits out of 64 under which two media are near-duplicates
+        #[arg(short, default_value_t = 10)]
+        distance: u32,
+    },
+    /// Tag the database media with the labels of the configured ONNX model
+    Tag {
+        /// Tag again the media already tagged by a model
+        #[arg(long)]
+        retag: bool,
+    },
+    /// Store the embedding of the configured ONNX model for the database media
+    Embed {
+        /// Embed again the media already embedded
+        #[arg(long)]
+        reembed: bool,
+    },
+    /// Group the embedded media in clusters stored as vibe-* tags
+    Cluster {
+        /// Number of clusters
+        #[arg(short, default_value_t = 8)]
+        k: usize,
+    },
+    /// Print the embedded media closest to an indexed media or an image
+    Neighbors {
+        media: String,
+        /// Number of media returned
+        #[arg(short, default_value_t = 10)]
+        k: usize,
+    },
+}
+
+impl Args {
+    fn override_config(&self, config: &mut GraphicConfig) {
+        if let Some(mode) = &self.window_mode {
+            config.window.mode = WindowMode::new(mode);
+        }
+        if let Some(monitor) = &self.monitor {
+            config.window.monitor = Some(MonitorSelector::new(monitor));
+        }
+        if let Some(always_on_top) = self.always_on_top {
+            config.window.always_on_top = always_on_top;
+        }
+        if self.hide_cursor_after.is_some() {
+            config.window.hide_cursor_after = self.hide_cursor_after;
+        }
+        if let Some(record) = self.record {
+            config.record.start = record;
+        }
+        if let Some(format) = &self.record_format {
+            config.record.format = RecordFormat::new(format);
+        }
+    }
+}
 
 fn main() {
+    let args = Args::parse();
+
     // media to graphic communication
     let (tx_mg, rx_mg) = mpsc::channel::<Frame>();
     // graphic to media communication
     let (tx_gm, rx_gm) = mpsc::channel::<u8>();
 
     let media_config = MediaConfig::new("confs/media.yaml");
-    let mut media_handler = MediaHandler::new(media_config, tx_mg, rx_gm);
-    thread::spawn(move || {
-        media_handler.run();
-    });
-
-    let graphic_config = GraphicConfig::new("confs/graphic.yaml");
-    let g = GraphicContext::new(graphic_config);
-    g.launch_graphic(tx_gm, rx_mg);
+    let mut graphic_config = GraphicConfig::new("confs/graphic.yaml");
+    args.override_config(&mut graphic_config);
+
+    match &args.command {
+        Some(Command::Index { folder }) => {
+            let report = Indexer::new(&media_config).run(folder);
+            println!("Indexed {}: {}", folder.display(), report);
+        }
+        Some(Command::Search { query }) => {
+            let search = match SearchQuery::parse(&query.join(" ")) {
+                Ok(search) => search,
+                Err(e) => {
+                    eprintln!("Invalid search: {}", e);
+                    std::process::exit(2);
+                }
+            };
+            let results = search.run(&mut DbConnection::establish(&media_config.database_url));
+            for result in &results {
+                println!("{}", result);
+            }
+            println!("{} media found", results.len());
+        }
+        Some(Command::Similar { seed, k }) => {
+            let target = match Seed::new(seed).and_then(|s| s.histogram()) {
+                Ok(target) => target,
+                Err(e) => {
+                    eprintln!("Invalid seed: {}", e);
+                    std::process::exit(2);
+                }
+            };
+            // among the media of the configured query
+            let media = media_config
+                .query
+                .load(&mut DbConnection::establish(&media_config.database_url));
+            for (m, distance) in similarity::nearest(&target, &media, *k) {
+                println!("{}  {:.3}", m.url, distance);
+            }
+        }
+        Some(Command::Dedupe { distance }) => {
+            let media =
+                perceptual::load_hashed(&mut DbConnection::establish(&media_config.database_url));
+            let groups = perceptual::duplicate_groups(&media, *distance);
+            for group in &groups {
+                // distances to the first media of the group
+                let first = group[0].phash.unwrap();
+                for m in group {
+                    println!(
+                        "{}  {}",
+                        m.url,
+                        perceptual::hamming(first, m.phash.unwrap())
+                    );
+                }
+                println!();
+            }
+            println!("{} groups of near-duplicates", groups.len());
+        }
+        Some(Command::Tag { retag }) => {
+            let tagger = OnnxTagger::new(&media_config.tagger);
+            let report = TagWriter::new(&media_config).run(&tagger, *retag);
+            println!("{}", report);
+        }
+        Some(Command::Embed { reembed }) => {
+            let embedder = OnnxEmbedder::new(&media_config.embedder);
+            let (embedded, skipped) = embedding::embed_media(
+                &mut DbConnection::establish(&media_config.database_url),
+                &embedder,
+                *reembed,
+            );
+            println!("{} media embedded, {} skipped", embedded, skipped);
+        }
+        Some(Command::Cluster { k }) => {
+            let mut rng = Session::new(args.seed, None).rng(CLUSTER_STREAM);
+            let clusters = embedding::cluster(
+                &mut DbConnection::establish(&media_config.database_url),
+                *k,
+                &mut rng,
+            );
+            for (tag, count) in &clusters {
+                println!("{}  {} media", tag, count);
+            }
+        }
+        Some(Command::Neighbors { media: url, k }) => {
+            // media are indexed under their absolute path
+            let url = &std::fs::canonicalize(url)
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| url.clone());
+            let media =
+                embedding::load_embedded(&mut DbConnection::establish(&media_config.database_url));
+            // the stored embedding of an indexed media, the model otherwise
+            let target = match media.iter().find(|m| m.url == *url) {
+                Some(m) => Embedding::of(m).unwrap(),
+                None => {
+                    Embedding::of_file(&OnnxEmbedder::new(&media_config.embedder), Path::new(url))
+                }
+            };
+            let others = media.iter().filter(|m| m.url != *url);
+            for (m, distance) in similarity::nearest(&target, others, *k) {
+                println!("{}  {:.3}", m.url, distance);
+            }
+        }
+        Some(Command::Replay { log }) => {
+            let (seed, entries) = Session::read_log(log);
+            let session = Session::new(Some(seed), None);
+            let glitch = media_config.glitch.clone();
+            let mut media_replay =
+                MediaReplay::new(&media_config, &entries, &session, tx_mg, rx_gm);
+            thread::spawn(move || {
+                media_replay.run();
+            });
+
+            let g = GraphicContext::new(graphic_config, glitch, session);
+            g.launch_graphic(tx_gm, rx_mg, None, Some(entries), None);
+        }
+        None => {
+            let log = args.log.clone().unwrap_or_else(|| {
+                let stamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                PathBuf::from(format!("sessions/session_{}.jsonl", stamp))
+            });
+            let session = Session::new(args.seed, Some(&log));
+            let glitch = media_config.glitch.clone();
+            let mut media_handler = MediaHandler::new(media_config, tx_mg, rx_gm, session.clone());
+            media_handler.glitch.set_mode(graphic_config.mode.name());
+            let tx_command = media_handler.command_sender();
+            let tx_stdin = media_handler.command_sender();
+            thread::spawn(move || {
+                media_handler.run();
+            });
+            // media query and rating commands typed in the terminal
+            thread::spawn(move || {
+                for line in io::stdin().lines().map_while(Result::ok) {
+                    match MediaCommand::parse(&line) {
+                        Some(command) => tx_stdin.send(command).unwrap(),
+                        None => println!("Unknown media command: {}", line),
+                    }
+                }
+            });
+
+            let rx_audio = graphic_config
+                .audio
+                .as_ref()
+                .map(|a| audio::listen(a, graphic_config.frequency_strip.sample_rate));
+            let g = GraphicContext::new(graphic_config, glitch, session);
+            g.launch_graphic(tx_gm, rx_mg, rx_audio, None, Some(tx_command));
+        }
+    }
 }
@@ -0,0 +1,19 @@
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+// slideshow rendered in the framebuffer
+uniform sampler2D screenTexture;
+uniform sampler2D background;
+// white where the slideshow replace the background
+uniform sampler2D mask;
+
+void main()
+{
+    // media textures are stored top to bottom
+    vec2 uv = vec2(TexCoords.x, 1.0 - TexCoords.y);
+    float m = texture(mask, uv).r;
+    vec3 bg = texture(background, uv).rgb;
+    vec3 fg = texture(screenTexture, TexCoords).rgb;
+    FragColor = vec4(mix(bg, fg, m), 1.0);
+}
@@ -0,0 +1,45 @@
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D screenTexture;
+uniform sampler2D bezel;
+uniform bool hasBezel;
+uniform float time;
+uniform float curvature;
+uniform float scanlines;
+uniform float noise;
+
+float rand(vec2 co)
+{
+    return fract(sin(dot(co, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+void main()
+{
+    // barrel distortion of the tube
+    vec2 uv = TexCoords * 2.0 - 1.0;
+    uv += uv * (uv.yx * uv.yx) * curvature;
+    uv = uv * 0.5 + 0.5;
+
+    vec3 col = vec3(0.0);
+    if (uv.x >= 0.0 && uv.x <= 1.0 && uv.y >= 0.0 && uv.y <= 1.0) {
+        // black and white, scanlines and grain
+        float grey = dot(texture(screenTexture, uv).rgb, vec3(0.299, 0.587, 0.114));
+        float line = 0.5 + 0.5 * sin(uv.y * scanlines * 3.14159);
+        grey *= mix(1.0, line, 0.35);
+        grey += (rand(uv + fract(time)) - 0.5) * noise;
+        col = vec3(grey);
+    }
+
+    vec4 frame;
+    if (hasBezel) {
+        frame = texture(bezel, vec2(TexCoords.x, 1.0 - TexCoords.y));
+    } else {
+        // plain dark plastic with rounded corners around the tube
+        vec2 d = abs(TexCoords * 2.0 - 1.0) - vec2(0.86);
+        float outside = length(max(d, 0.0)) - 0.06;
+        frame = vec4(vec3(0.06), smoothstep(0.0, 0.01, outside));
+    }
+    FragColor = vec4(mix(col, frame.rgb, frame.a), 1.0);
+}
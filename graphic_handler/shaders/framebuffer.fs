@@ -3,9 +3,38 @@ out vec4 FragColor;
 in vec2 TexCoords;
 
 uniform sampler2D screenTexture;
+// theme grading profile
+uniform float gradeExposure;
+uniform float gradeContrast;
+uniform float gradeSaturation;
+uniform vec3 gradeTint;
+// 3D LUT color grading
+uniform sampler3D lut;
+uniform float lutSize;
+uniform float lutIntensity;
+// input range covered by the LUT, DOMAIN_MIN and DOMAIN_MAX or LUT_3D_INPUT_RANGE of the file
+uniform vec3 lutDomainMin;
+uniform vec3 lutDomainMax;
+
+vec3 grade(vec3 col)
+{
+    col *= pow(2.0, gradeExposure);
+    col = (col - 0.5) * gradeContrast + 0.5;
+    float l = dot(col, vec3(0.2126, 0.7152, 0.0722));
+    col = mix(vec3(l), col, gradeSaturation);
+    return clamp(col * gradeTint, 0.0, 1.0);
+}
+
+vec3 applyLut(vec3 col)
+{
+    vec3 x = clamp((col - lutDomainMin) / (lutDomainMax - lutDomainMin), 0.0, 1.0);
+    // sample the texel centers, the LUT edges are the domain bounds
+    vec3 coord = x * ((lutSize - 1.0) / lutSize) + 0.5 / lutSize;
+    return mix(col, texture(lut, coord).rgb, lutIntensity);
+}
 
 void main()
 {
     vec4 col = texture(screenTexture, TexCoords);
-    FragColor = vec4(col.rgb, 1.0);
-} 
\ No newline at end of file
+    FragColor = vec4(applyLut(grade(col.rgb)), 1.0);
+}
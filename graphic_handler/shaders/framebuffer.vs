@@ -3,8 +3,11 @@ layout (location = 1) in vec2 aTexCoords;
 
 out vec2 TexCoords;
 
+// part of the framebuffer shown on screen (x, y, width, height)
+uniform vec4 uvRect = vec4(0.0, 0.0, 1.0, 1.0);
+
 void main()
 {
-    TexCoords = aTexCoords;
+    TexCoords = uvRect.xy + aTexCoords * uvRect.zw;
     gl_Position = vec4(aPos.x, aPos.y, 0.0, 1.0); 
 }  
\ No newline at end of file
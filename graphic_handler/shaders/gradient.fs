@@ -0,0 +1,22 @@
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+// current image palette
+uniform vec3 palette[5];
+uniform float time;
+uniform float speed;
+uniform int bands;
+uniform vec2 direction;
+uniform float opacity;
+
+void main()
+{
+    // position along the sweep direction, scrolling with time
+    float d = dot(TexCoords - 0.5, normalize(direction)) + 0.5;
+    float p = fract(d - time * speed) * float(bands);
+    int i = int(p) % 5;
+    int j = (i + 1) % 5;
+    vec3 col = mix(palette[i], palette[j], smoothstep(0.0, 1.0, fract(p)));
+    FragColor = vec4(col, opacity);
+}
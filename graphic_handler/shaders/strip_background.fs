@@ -0,0 +1,15 @@
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+// current image palette
+uniform vec3 palette[5];
+uniform float time;
+
+void main()
+{
+    // slow dark waves of the first palette colors
+    float w = 0.5 + 0.5 * sin(TexCoords.x * 3.0 + time * 0.2) * cos(TexCoords.y * 2.0 - time * 0.13);
+    vec3 col = mix(palette[0], palette[1], w) * 0.2;
+    FragColor = vec4(col, 1.0);
+}
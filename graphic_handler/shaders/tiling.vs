@@ -0,0 +1,13 @@
+layout (location = 0) in vec3 aPos;
+layout (location = 1) in vec2 aTexCoord;
+
+out vec2 TexCoord;
+
+// one model matrix per tile, already in normalized device coordinates
+uniform mat4 models[64];
+
+void main()
+{
+    gl_Position = models[gl_InstanceID] * vec4(aPos, 1.0f);
+    TexCoord = vec2(aTexCoord.x, 1.0 - aTexCoord.y);
+}
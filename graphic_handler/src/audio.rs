@@ -0,0 +1,75 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::graphic_config::AudioSource;
+
+// samples per analysed window, about 23ms at 44.1kHz
+const WINDOW_SIZE: usize = 1024;
+
+pub fn samples(bytes: &[u8]) -> Vec<f32> {
+    // little endian 32 bits floats written by ffmpeg
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+pub fn listen(source: &AudioSource, sample_rate: f32) -> Receiver<Vec<f32>> {
+    /*
+        Mono sample windows of the audio source, decoded by ffmpeg in a thread
+        A file is played at its real speed and in loop, like a capture
+        The channel close when ffmpeg stop
+    */
+    let mut command = Command::new("ffmpeg");
+    command.args(["-loglevel", "error"]);
+    match source {
+        AudioSource::File(path) => {
+            command.args(["-re", "-stream_loop", "-1", "-i"]).arg(path);
+        }
+        AudioSource::Capture { format, device } => {
+            command.args(["-f", format, "-i", device]);
+        }
+    }
+    command
+        .args(["-f", "f32le", "-ac", "1"])
+        .args(["-ar", &(sample_rate as u32).to_string(), "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped());
+
+    let (tx, rx) = mpsc::channel();
+    match command.spawn() {
+        Ok(mut child) => {
+            thread::spawn(move || {
+                let mut stdout = child.stdout.take().unwrap();
+                let mut buffer = vec![0; WINDOW_SIZE * 4];
+                while stdout.read_exact(&mut buffer).is_ok() {
+                    if tx.send(samples(&buffer)).is_err() {
+                        break;
+                    }
+                }
+                let _ = child.kill();
+                let _ = child.wait();
+            });
+        }
+        Err(e) => println!("Unable to start ffmpeg, no audio analysed: {}", e),
+    }
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_from_bytes() {
+        let bytes: Vec<u8> = [0.5f32, -1., 0.25]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        assert_eq!(samples(&bytes), [0.5, -1., 0.25]);
+        // an incomplete sample is dropped
+        assert_eq!(samples(&bytes[..6]), [0.5]);
+    }
+}
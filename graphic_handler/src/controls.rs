@@ -1,23 +1,37 @@
 use iced_glow::Renderer;
-use iced_glutin::widget::Slider;
+use iced_glutin::widget::{Button, Checkbox, Slider};
 use iced_glutin::widget::{Column, Row, Text};
 use iced_glutin::{Alignment, Color, Command, Element, Length, Program};
 
 pub struct Controls {
     pub refresh: u8,
     pub background_color: Color,
+    // background color follow the current image palette
+    pub follow_palette: bool,
+    pub lut_intensity: f32,
+    pub recording: bool,
+    // rating of the last media shown, on top of the screen, waiting to be sent, 1 like and -1 dislike
+    pub rating: Option<i32>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     BackgroundColorChanged(Color),
+    FollowPaletteToggled(bool),
+    LutIntensityChanged(f32),
+    RecordingToggled(bool),
+    Rated(Option<i32>),
 }
 
 impl Controls {
-    pub fn new() -> Controls {
+    pub fn new(lut_intensity: f32, recording: bool) -> Controls {
         Controls {
             refresh: 0,
             background_color: Color::BLACK,
+            follow_palette: false,
+            lut_intensity,
+            recording,
+            rating: None,
         }
     }
 }
@@ -31,6 +45,18 @@ impl Program for Controls {
             Message::BackgroundColorChanged(color) => {
                 self.background_color = color;
             }
+            Message::FollowPaletteToggled(follow) => {
+                self.follow_palette = follow;
+            }
+            Message::LutIntensityChanged(intensity) => {
+                self.lut_intensity = intensity;
+            }
+            Message::RecordingToggled(recording) => {
+                self.recording = recording;
+            }
+            Message::Rated(rating) => {
+                self.rating = rating;
+            }
         }
 
         Command::none()
@@ -84,6 +110,38 @@ impl Program for Controls {
                             .spacing(10)
                             .push(Text::new("Background color").style(Color::WHITE))
                             .push(sliders)
+                            .push(Checkbox::new(
+                                "Follow image palette",
+                                self.follow_palette,
+                                Message::FollowPaletteToggled,
+                            ))
+                            .push(Text::new("LUT intensity").style(Color::WHITE))
+                            .push(
+                                Slider::new(
+                                    0.0..=1.0,
+                                    self.lut_intensity,
+                                    Message::LutIntensityChanged,
+                                )
+                                .step(0.01)
+                                .width(500),
+                            )
+                            .push(Checkbox::new(
+                                "Record (F9)",
+                                self.recording,
+                                Message::RecordingToggled,
+                            ))
+                            .push(
+                                Row::new()
+                                    .spacing(10)
+                                    .push(
+                                        Button::new(Text::new("Like (L)"))
+                                            .on_press(Message::Rated(Some(1))),
+                                    )
+                                    .push(
+                                        Button::new(Text::new("Dislike (D)"))
+                                            .on_press(Message::Rated(Some(-1))),
+                                    ),
+                            )
                             .push(
                                 Text::new(format!("{background_color:?}"))
                                     .size(14)
@@ -0,0 +1,117 @@
+use std::sync::mpsc::Receiver;
+
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use nalgebra_glm::{perspective, scale, translate, translation, vec3, vec4, TVec3};
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use crate::grading::ThemeGrading;
+use crate::graphic_config::{GraphicConfig, Mode};
+use media_handler::frame::Frame;
+
+pub struct CpuRenderer {
+    // scene kept between frames like the framebuffer texture
+    pub canvas: RgbaImage,
+    pub grading: ThemeGrading,
+    rng: StdRng,
+}
+
+impl CpuRenderer {
+    pub fn new(size: (u32, u32), config: &GraphicConfig, rng: StdRng) -> Self {
+        /*
+            Software rasterizer of the slideshow scene, used when no GL context exist
+            The other modes are drawn as a slideshow
+        */
+        if config.mode != Mode::Slideshow {
+            println!(
+                "The CPU renderer only draw the slideshow, {:?} mode is ignored",
+                config.mode
+            );
+        }
+        Self {
+            canvas: RgbaImage::from_pixel(size.0, size.1, Rgba([0, 0, 0, 255])),
+            grading: ThemeGrading::new(
+                config.grading.themes.clone(),
+                config.grading.blend_duration,
+            ),
+            rng,
+        }
+    }
+
+    fn screen_rect(&self, media_ratio: f32, position: TVec3<f32>) -> (i64, i64, u32, u32) {
+        /*
+            Same transforms as the slideshow renderers,
+            projection * view * model applied on the quad corners
+            Return the top left corner and the size in pixels
+        */
+        let (w, h) = self.canvas.dimensions();
+        let viewport_ratio = w as f32 / h as f32;
+        let projection = perspective(1., 45_f32.to_radians(), 0.1, 100.0);
+        let view = translation(&(vec3(0., 0., -3.).normalize()));
+        let mut model = translate(&translation(&vec3(0., 0., -3.)), &position);
+        model = scale(&model, &vec3(media_ratio * 0.1, viewport_ratio * 0.1, 1.));
+        let mvp = projection * view * model;
+
+        let corner = |x: f32, y: f32| {
+            let p = mvp * vec4(x, y, 0., 1.);
+            // normalized device coordinates to pixels, rows going down
+            (
+                (p.x / p.w + 1.) / 2. * w as f32,
+                (1. - p.y / p.w) / 2. * h as f32,
+            )
+        };
+        let (x0, y0) = corner(-1., 1.);
+        let (x1, y1) = corner(1., -1.);
+        (
+            x0.round() as i64,
+            y0.round() as i64,
+            (x1 - x0).round().max(1.) as u32,
+            (y1 - y0).round().max(1.) as u32,
+        )
+    }
+
+    fn draw_media(&mut self, media: &Frame) {
+        let position = vec3(
+            self.rng.gen_range(-1.2..1.2),
+            self.rng.gen_range(-1.2..1.2),
+            1.,
+        );
+        let (x, y, w, h) = self.screen_rect(media.ratio, position);
+        let resized = imageops::resize(&media.data, w, h, FilterType::Triangle);
+        imageops::overlay(&mut self.canvas, &resized, x, y);
+        self.grading.set_tags(&media.tags);
+    }
+
+    pub fn render(
+        &mut self,
+        rx: &Receiver<Frame>,
+        next_media: bool,
+        config: &GraphicConfig,
+    ) -> RgbaImage {
+        if next_media {
+            for _ in 0..config.media_per_tick() {
+                if let Ok(m) = rx.recv() {
+                    self.draw_media(&m);
+                }
+            }
+        }
+
+        // framebuffer pass: grading then LUT
+        let grading = self.grading.current();
+        let mut frame = self.canvas.clone();
+        for p in frame.pixels_mut() {
+            let color = [0, 1, 2].map(|i| p[i] as f32 / 255.);
+            let mut graded = grading.apply(color);
+            if let Some(lut) = &config.lut {
+                let looked_up = lut.sample(graded);
+                graded = [0, 1, 2]
+                    .map(|i| graded[i] + (looked_up[i] - graded[i]) * config.lut_intensity);
+            }
+            for i in 0..3 {
+                p[i] = (graded[i] * 255.).round().clamp(0., 255.) as u8;
+            }
+        }
+        frame
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
+use glow::*;
+use iced_glow::glow;
+use media_handler::palette::Palette;
+
+use crate::gl_engine::buffer_util::BufferUtil;
+use crate::gl_engine::palette_uniform::PaletteUniform;
+
+pub struct BackgroundRenderer {
+    pub vao: glow::VertexArray,
+    pub vbo: glow::NativeBuffer,
+    pub program: glow::Program,
+
+    start: Instant,
+    palette_uniform: PaletteUniform,
+    time_loc: Option<NativeUniformLocation>,
+}
+
+impl BufferUtil for BackgroundRenderer {}
+
+impl BackgroundRenderer {
+    pub fn new(gl: &glow::Context, vertex_path: &PathBuf, fragment_path: &PathBuf) -> Self {
+        /*
+            Full screen animated layer drawn in the framebuffer behind the media
+        */
+        let (program, vao, vbo) = Self::init_program_buffer(
+            gl,
+            vertex_path,
+            fragment_path,
+            &[2, 2],
+            &Self::get_screen_vertex_array(),
+        );
+
+        unsafe {
+            Self {
+                vao,
+                vbo,
+                program,
+                start: Instant::now(),
+                palette_uniform: PaletteUniform::new(gl, &program),
+                time_loc: gl.get_uniform_location(program, "time"),
+            }
+        }
+    }
+
+    pub fn draw(&self, gl: &glow::Context, palette: &Palette) {
+        unsafe {
+            gl.disable(glow::DEPTH_TEST);
+
+            gl.use_program(Some(self.program));
+            self.palette_uniform.update(gl, palette);
+            gl.uniform_1_f32(self.time_loc.as_ref(), self.start.elapsed().as_secs_f32());
+
+            gl.bind_vertex_array(Some(self.vao));
+            gl.draw_arrays(glow::TRIANGLES, 0, 6);
+            gl.bind_vertex_array(None);
+        }
+    }
+
+    pub fn cleanup(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_program(self.program);
+            gl.delete_vertex_array(self.vao);
+            gl.delete_buffer(self.vbo);
+        }
+    }
+}
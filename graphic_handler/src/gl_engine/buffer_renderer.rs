@@ -6,6 +6,7 @@ use iced_glow::Color;
 use nalgebra_glm::Vec3;
 
 use crate::gl_engine::buffer_util::BufferUtil;
+use crate::gl_engine::tiling::Pattern;
 use crate::scene::Scene;
 use nalgebra_glm::{scale, translate, translation, vec3, TMat4, TVec3};
 
@@ -94,6 +95,38 @@ impl BufferRenderer {
         }
     }
 
+    pub fn draw_instanced(
+        &mut self,
+        gl: &glow::Context,
+        texture: glow::NativeTexture,
+        models: &[TMat4<f32>],
+    ) {
+        /*
+            Draw the same quad once per model matrix,
+            in one call per batch of matrices fitting the shader uniform
+            Tiles are flat, depth test would hide the overlapping ones
+        */
+        unsafe {
+            gl.disable(glow::DEPTH_TEST);
+
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.use_program(Some(self.program));
+
+            self.scene.update_scene(gl);
+
+            gl.bind_vertex_array(Some(self.vao));
+            for batch in Pattern::batches(models) {
+                self.scene.update_models(gl, batch);
+                gl.draw_arrays_instanced(glow::TRIANGLES, 0, 6, batch.len() as i32);
+            }
+
+            gl.bind_vertex_array(None);
+            gl.bind_texture(glow::TEXTURE_2D, None);
+            // the scene and post passes drawn after expect it on
+            gl.enable(glow::DEPTH_TEST);
+        }
+    }
+
     pub fn cleanup(&self, gl: &glow::Context) {
         unsafe {
             gl.delete_program(self.program);
@@ -15,6 +15,14 @@ pub trait BufferUtil {
         ]
     }
 
+    fn get_screen_vertex_array() -> [f32; 24] {
+        [
+            // full screen quad, 2D positions and texture coordinates
+            -1.0, 1.0, 0.0, 1.0, -1.0, -1.0, 0.0, 0.0, 1.0, -1.0, 1.0, 0.0, -1.0, 1.0, 0.0, 1.0,
+            1.0, -1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0,
+        ]
+    }
+
     fn get_shader_from_file(shader_path: PathBuf) -> String {
         fs::read_to_string(shader_path).expect("Unable to read file")
     }
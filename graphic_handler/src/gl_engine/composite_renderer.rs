@@ -0,0 +1,264 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use glow::*;
+use iced_glow::glow;
+use image::{imageops, DynamicImage, GrayImage, Luma};
+use media_handler::frame::Frame;
+use media_handler::sequence::FrameSequence;
+
+use crate::gl_engine::buffer_util::BufferUtil;
+use crate::gl_engine::texture_util::TextureUtil;
+use crate::graphic_config::{AnonymisationConfig, MaskSource};
+
+// resolution of the masks generated from regions
+const REGION_MASK_SIZE: u32 = 512;
+
+pub struct CompositeRenderer {
+    pub vao: glow::VertexArray,
+    pub vbo: glow::NativeBuffer,
+    pub program: glow::Program,
+    // framebuffer shown, the window one if None
+    pub screen: Option<glow::NativeFramebuffer>,
+
+    background: FrameSequence,
+    mask_sequence: Option<FrameSequence>,
+    // regions file and its last modification loaded
+    regions_file: Option<(PathBuf, Option<SystemTime>)>,
+    feather: f32,
+    background_texture: glow::NativeTexture,
+    mask_texture: glow::NativeTexture,
+
+    screen_loc: Option<NativeUniformLocation>,
+    background_loc: Option<NativeUniformLocation>,
+    mask_loc: Option<NativeUniformLocation>,
+}
+
+impl BufferUtil for CompositeRenderer {}
+impl TextureUtil for CompositeRenderer {}
+
+impl CompositeRenderer {
+    pub fn region_mask(regions: &[[f32; 4]], feather: f32) -> Frame {
+        /*
+            White rectangles on black, regions are (x, y, width, height)
+            in screen fraction from the top left corner
+        */
+        let size = REGION_MASK_SIZE as f32;
+        let mut mask = GrayImage::new(REGION_MASK_SIZE, REGION_MASK_SIZE);
+        for [x, y, w, h] in regions {
+            let (x0, y0) = ((x * size) as u32, (y * size) as u32);
+            let (x1, y1) = (((x + w) * size) as u32, ((y + h) * size) as u32);
+            for py in y0..y1.min(REGION_MASK_SIZE) {
+                for px in x0..x1.min(REGION_MASK_SIZE) {
+                    mask.put_pixel(px, py, Luma([255]));
+                }
+            }
+        }
+        if feather > 0. {
+            mask = imageops::blur(&mask, feather * size);
+        }
+        Frame::from_image(PathBuf::from("regions"), DynamicImage::ImageLuma8(mask))
+    }
+
+    pub fn parse_regions(content: &str) -> Vec<[f32; 4]> {
+        // one "x y width height" per line, the incomplete lines of a file being written are skipped
+        content
+            .lines()
+            .filter_map(|line| {
+                let values: Vec<f32> = line
+                    .split_whitespace()
+                    .map(|v| v.parse().ok())
+                    .collect::<Option<_>>()?;
+                values.try_into().ok()
+            })
+            .collect()
+    }
+
+    pub fn new(
+        gl: &glow::Context,
+        vertex_path: &PathBuf,
+        fragment_path: &PathBuf,
+        config: &AnonymisationConfig,
+    ) -> Self {
+        /*
+            Post process pass of the anonymisation mode, graded by the framebuffer pass after it:
+            the background is shown everywhere except in the mask
+            Built by the first frame of the mode, its files are checked there
+        */
+        let mut files = vec![&config.background];
+        if let MaskSource::Image(p) | MaskSource::Sequence(p) = &config.mask {
+            files.push(p);
+        }
+        for f in files {
+            if !f.exists() {
+                panic!("Anonymisation file {:?} doesn't exist", f);
+            }
+        }
+        let (program, vao, vbo) = Self::init_program_buffer(
+            gl,
+            vertex_path,
+            fragment_path,
+            &[2, 2],
+            &Self::get_screen_vertex_array(),
+        );
+
+        let mut background = FrameSequence::new(&config.background);
+        let background_texture = Self::init_texture(gl);
+        Self::generate_texture(gl, background_texture, &background.wait_frame());
+
+        let mask_texture = Self::init_texture(gl);
+        let mut mask_sequence = None;
+        let mut regions_file = None;
+        match &config.mask {
+            MaskSource::Image(p) => {
+                Self::generate_texture(gl, mask_texture, &Frame::new(p.clone()))
+            }
+            MaskSource::Sequence(p) => {
+                let mut sequence = FrameSequence::new(p);
+                Self::generate_texture(gl, mask_texture, &sequence.wait_frame());
+                mask_sequence = Some(sequence);
+            }
+            MaskSource::Regions(r) => {
+                Self::generate_texture(gl, mask_texture, &Self::region_mask(r, config.feather))
+            }
+            MaskSource::RegionsFile(p) => {
+                // nothing hidden until the detector write the file
+                Self::generate_texture(gl, mask_texture, &Self::region_mask(&[], config.feather));
+                regions_file = Some((p.clone(), None));
+            }
+        }
+
+        unsafe {
+            Self {
+                vao,
+                vbo,
+                program,
+                screen: None,
+                background,
+                mask_sequence,
+                regions_file,
+                feather: config.feather,
+                background_texture,
+                mask_texture,
+                screen_loc: gl.get_uniform_location(program, "screenTexture"),
+                background_loc: gl.get_uniform_location(program, "background"),
+                mask_loc: gl.get_uniform_location(program, "mask"),
+            }
+        }
+    }
+
+    pub fn next_frame(&mut self, gl: &glow::Context) {
+        /*
+            Background and mask videos move together,
+            a frame still decoding keep the previous one on screen
+        */
+        if let Some(frame) = self.background.next_frame() {
+            Self::generate_texture(gl, self.background_texture, &frame);
+        }
+        if let Some(frame) = self.mask_sequence.as_mut().and_then(|s| s.next_frame()) {
+            Self::generate_texture(gl, self.mask_texture, &frame);
+        }
+    }
+
+    pub fn update_regions(&mut self, gl: &glow::Context) {
+        // reload the regions file when the detector rewrite it
+        let (path, loaded) = match &mut self.regions_file {
+            Some(f) => f,
+            None => return,
+        };
+        let modified = fs::metadata(&*path).and_then(|m| m.modified()).ok();
+        if modified.is_none() || modified == *loaded {
+            return;
+        }
+        *loaded = modified;
+        let regions = match fs::read_to_string(&*path) {
+            Ok(content) => Self::parse_regions(&content),
+            Err(_) => return,
+        };
+        let feather = self.feather;
+        self.set_regions(gl, &regions, feather);
+    }
+
+    pub fn set_regions(&mut self, gl: &glow::Context, regions: &[[f32; 4]], feather: f32) {
+        // regions coming from a detector replace the current mask
+        self.mask_sequence = None;
+        Self::generate_texture(gl, self.mask_texture, &Self::region_mask(regions, feather));
+    }
+
+    pub fn draw(&self, gl: &glow::Context, texture: glow::NativeTexture) {
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, self.screen);
+            gl.disable(glow::DEPTH_TEST);
+
+            gl.use_program(Some(self.program));
+            gl.uniform_1_i32(self.screen_loc.as_ref(), 0);
+            gl.uniform_1_i32(self.background_loc.as_ref(), 1);
+            gl.uniform_1_i32(self.mask_loc.as_ref(), 2);
+
+            gl.active_texture(glow::TEXTURE1);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.background_texture));
+            gl.active_texture(glow::TEXTURE2);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.mask_texture));
+            gl.active_texture(glow::TEXTURE0);
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+
+            gl.bind_vertex_array(Some(self.vao));
+            gl.draw_arrays(glow::TRIANGLES, 0, 6);
+            gl.bind_vertex_array(None);
+        }
+    }
+
+    pub fn cleanup(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_program(self.program);
+            gl.delete_vertex_array(self.vao);
+            gl.delete_buffer(self.vbo);
+            gl.delete_texture(self.background_texture);
+            gl.delete_texture(self.mask_texture);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_the_regions() {
+        let content = "0.1 0.2 0.3 0.4\n0.5 x 0.1 0.1\n1 2 3\n\n 0 0  1 1 \n0.5 0.5 0.1";
+        assert_eq!(
+            CompositeRenderer::parse_regions(content),
+            [[0.1, 0.2, 0.3, 0.4], [0., 0., 1., 1.]]
+        );
+        assert!(CompositeRenderer::parse_regions("").is_empty());
+    }
+
+    #[test]
+    fn mask_of_the_regions() {
+        let mask = CompositeRenderer::region_mask(&[[0.25, 0.5, 0.5, 0.25]], 0.);
+        let mask = mask.data.to_luma8();
+        assert_eq!(mask.dimensions(), (REGION_MASK_SIZE, REGION_MASK_SIZE));
+        let at = |x: f32, y: f32| {
+            let size = REGION_MASK_SIZE as f32;
+            mask.get_pixel((x * size) as u32, (y * size) as u32)[0]
+        };
+        assert_eq!(at(0.5, 0.6), 255);
+        assert_eq!(at(0.25, 0.5), 255);
+        assert_eq!(at(0.74, 0.74), 255);
+        assert_eq!(at(0.75, 0.6), 0);
+        assert_eq!(at(0.5, 0.4), 0);
+        assert_eq!(at(0.1, 0.9), 0);
+
+        // regions past the border are cut, the feather soften the edges
+        let mask = CompositeRenderer::region_mask(&[[0.5, 0.5, 1., 1.]], 0.02);
+        let mask = mask.data.to_luma8();
+        let edge = mask.get_pixel(REGION_MASK_SIZE / 2, REGION_MASK_SIZE * 3 / 4)[0];
+        assert!(0 < edge && edge < 255);
+        assert_eq!(
+            mask.get_pixel(REGION_MASK_SIZE - 1, REGION_MASK_SIZE - 1)[0],
+            255
+        );
+        assert_eq!(mask.get_pixel(0, 0)[0], 0);
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
+use glow::*;
+use iced_glow::glow;
+
+use crate::gl_engine::buffer_util::BufferUtil;
+use crate::gl_engine::jump_cut::JumpCut;
+use crate::gl_engine::texture_util::TextureUtil;
+use crate::graphic_config::TvScreenConfig;
+
+pub struct CrtRenderer {
+    pub vao: glow::VertexArray,
+    pub vbo: glow::NativeBuffer,
+    pub program: glow::Program,
+    // framebuffer shown, the window one if None
+    pub screen: Option<glow::NativeFramebuffer>,
+
+    // cuts and playback speed
+    pub jump_cut: JumpCut,
+    bezel: Option<glow::NativeTexture>,
+    start: Instant,
+
+    screen_loc: Option<NativeUniformLocation>,
+    bezel_loc: Option<NativeUniformLocation>,
+    has_bezel_loc: Option<NativeUniformLocation>,
+    time_loc: Option<NativeUniformLocation>,
+    curvature_loc: Option<NativeUniformLocation>,
+    scanlines_loc: Option<NativeUniformLocation>,
+    noise_loc: Option<NativeUniformLocation>,
+}
+
+impl BufferUtil for CrtRenderer {}
+impl TextureUtil for CrtRenderer {}
+
+impl CrtRenderer {
+    pub fn new(
+        gl: &glow::Context,
+        vertex_path: &PathBuf,
+        fragment_path: &PathBuf,
+        config: &TvScreenConfig,
+    ) -> Self {
+        /*
+            Post process pass of the TV screen mode, graded by the framebuffer pass after it
+        */
+        let (program, vao, vbo) = Self::init_program_buffer(
+            gl,
+            vertex_path,
+            fragment_path,
+            &[2, 2],
+            &Self::get_screen_vertex_array(),
+        );
+        let bezel = config.bezel.as_ref().map(|b| {
+            let texture = Self::init_texture(gl);
+            Self::generate_texture(gl, texture, b);
+            texture
+        });
+
+        unsafe {
+            Self {
+                vao,
+                vbo,
+                program,
+                screen: None,
+                jump_cut: JumpCut::new(config),
+                bezel,
+                start: Instant::now(),
+                screen_loc: gl.get_uniform_location(program, "screenTexture"),
+                bezel_loc: gl.get_uniform_location(program, "bezel"),
+                has_bezel_loc: gl.get_uniform_location(program, "hasBezel"),
+                time_loc: gl.get_uniform_location(program, "time"),
+                curvature_loc: gl.get_uniform_location(program, "curvature"),
+                scanlines_loc: gl.get_uniform_location(program, "scanlines"),
+                noise_loc: gl.get_uniform_location(program, "noise"),
+            }
+        }
+    }
+
+    pub fn draw(&self, gl: &glow::Context, texture: glow::NativeTexture, config: &TvScreenConfig) {
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, self.screen);
+            gl.disable(glow::DEPTH_TEST);
+
+            gl.use_program(Some(self.program));
+            gl.uniform_1_i32(self.screen_loc.as_ref(), 0);
+            gl.uniform_1_i32(self.bezel_loc.as_ref(), 1);
+            gl.uniform_1_i32(self.has_bezel_loc.as_ref(), self.bezel.is_some() as i32);
+            gl.uniform_1_f32(self.time_loc.as_ref(), self.start.elapsed().as_secs_f32());
+            gl.uniform_1_f32(self.curvature_loc.as_ref(), config.curvature);
+            gl.uniform_1_f32(self.scanlines_loc.as_ref(), config.scanlines);
+            gl.uniform_1_f32(self.noise_loc.as_ref(), config.noise);
+
+            gl.active_texture(glow::TEXTURE1);
+            gl.bind_texture(glow::TEXTURE_2D, self.bezel);
+            gl.active_texture(glow::TEXTURE0);
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+
+            gl.bind_vertex_array(Some(self.vao));
+            gl.draw_arrays(glow::TRIANGLES, 0, 6);
+            gl.bind_vertex_array(None);
+        }
+    }
+
+    pub fn cleanup(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_program(self.program);
+            gl.delete_vertex_array(self.vao);
+            gl.delete_buffer(self.vbo);
+            if let Some(b) = self.bezel {
+                gl.delete_texture(b);
+            }
+        }
+    }
+}
@@ -5,44 +5,52 @@ use iced_glow::glow;
 use iced_glow::Color;
 
 use crate::gl_engine::buffer_util::BufferUtil;
+use crate::gl_engine::grading_uniform::GradingUniform;
 use crate::gl_engine::texture_util::TextureUtil;
+use crate::grading::GradingProfile;
+use crate::lut::Lut3D;
+
+// whole framebuffer, (x, y, width, height) in texture coordinates
+pub const FULL_RECT: [f32; 4] = [0., 0., 1., 1.];
 
 pub struct FramebufferRenderer {
     pub vao: glow::VertexArray,
     pub vbo: glow::NativeBuffer,
     pub program: glow::Program,
+    // framebuffer shown, the window one if None
+    pub screen: Option<glow::NativeFramebuffer>,
 
     pub fbo: glow::NativeFramebuffer,
     pub color_texture_buffer: glow::NativeTexture,
+    // output of the mode post process passes, graded and shown by this one
+    pub post_fbo: glow::NativeFramebuffer,
+    pub post_texture: glow::NativeTexture,
     pub bg_color: Color,
+    pub grading: GradingProfile,
+    // part of the texture drawn on screen, a window share of a spanned canvas
+    pub uv_rect: [f32; 4],
+    uv_rect_loc: Option<NativeUniformLocation>,
+    grading_uniform: GradingUniform,
+
+    lut: Option<(glow::NativeTexture, usize)>,
+    lut_intensity: f32,
+    // input range of the LUT, (min, max)
+    lut_domain: ([f32; 3], [f32; 3]),
+    lut_loc: Option<NativeUniformLocation>,
+    lut_size_loc: Option<NativeUniformLocation>,
+    lut_intensity_loc: Option<NativeUniformLocation>,
+    lut_domain_locs: (Option<NativeUniformLocation>, Option<NativeUniformLocation>),
 }
 
 impl BufferUtil for FramebufferRenderer {}
 impl TextureUtil for FramebufferRenderer {}
 
 impl FramebufferRenderer {
-    fn init_program_framebuffer(
+    fn init_framebuffer(
         gl: &glow::Context,
-        vertex_path: &PathBuf,
-        fragment_path: &PathBuf,
         win_size: (i32, i32),
-    ) -> (
-        glow::NativeProgram,
-        glow::NativeVertexArray,
-        glow::NativeBuffer,
-        glow::NativeFramebuffer,
-        NativeTexture,
-    ) {
-        let byte_sizes = [2, 2];
-        let vertices: [f32; 24] = [
-            -1.0, 1.0, 0.0, 1.0, -1.0, -1.0, 0.0, 0.0, 1.0, -1.0, 1.0, 0.0, -1.0, 1.0, 0.0, 1.0,
-            1.0, -1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0,
-        ];
-
+    ) -> (glow::NativeFramebuffer, NativeTexture) {
         unsafe {
-            let (program, vao, vbo) =
-                Self::init_program_buffer(gl, vertex_path, fragment_path, &byte_sizes, &vertices);
-
             let fbo = gl.create_framebuffer().expect("Cannot create framebuffer");
             gl.bind_framebuffer(glow::FRAMEBUFFER, Some(fbo));
 
@@ -89,7 +97,7 @@ impl FramebufferRenderer {
             }
             // render in main window
             gl.bind_framebuffer(glow::FRAMEBUFFER, None);
-            (program, vao, vbo, fbo, color_texture_buffer)
+            (fbo, color_texture_buffer)
         }
     }
 
@@ -102,28 +110,142 @@ impl FramebufferRenderer {
         /*
             Create main program where all the other program will render in
         */
-        let (program, vao, vbo, fbo, color_texture_buffer) =
-            Self::init_program_framebuffer(gl, vertex_path, fragment_path, win_size);
+        let (program, vao, vbo) = Self::init_program_buffer(
+            gl,
+            vertex_path,
+            fragment_path,
+            &[2, 2],
+            &Self::get_screen_vertex_array(),
+        );
+        let (fbo, color_texture_buffer) = Self::init_framebuffer(gl, win_size);
+        let (post_fbo, post_texture) = Self::init_framebuffer(gl, win_size);
+        let grading_uniform = GradingUniform::new(gl, &program);
+        let (lut_loc, lut_size_loc, lut_intensity_loc, lut_domain_locs, uv_rect_loc) = unsafe {
+            (
+                gl.get_uniform_location(program, "lut"),
+                gl.get_uniform_location(program, "lutSize"),
+                gl.get_uniform_location(program, "lutIntensity"),
+                (
+                    gl.get_uniform_location(program, "lutDomainMin"),
+                    gl.get_uniform_location(program, "lutDomainMax"),
+                ),
+                gl.get_uniform_location(program, "uvRect"),
+            )
+        };
 
         Self {
             program,
+            screen: None,
             vao,
             vbo,
             fbo,
             color_texture_buffer,
+            post_fbo,
+            post_texture,
             bg_color: Color::new(0., 0., 0., 1.),
+            grading: GradingProfile::neutral(),
+            uv_rect: FULL_RECT,
+            uv_rect_loc,
+            grading_uniform,
+            lut: None,
+            lut_intensity: 0.,
+            lut_domain: ([0., 0., 0.], [1., 1., 1.]),
+            lut_loc,
+            lut_size_loc,
+            lut_intensity_loc,
+            lut_domain_locs,
+        }
+    }
+
+    pub fn set_lut(&mut self, gl: &glow::Context, lut: &Lut3D, intensity: f32) {
+        /*
+            Upload the LUT in a 3D texture, linear filtering does the interpolation
+        */
+        let data: Vec<f32> = lut.data.concat();
+        let size = lut.size as i32;
+        unsafe {
+            let texture = match self.lut {
+                Some((t, _)) => t,
+                None => gl.create_texture().unwrap(),
+            };
+            gl.bind_texture(glow::TEXTURE_3D, Some(texture));
+            for wrap in [
+                glow::TEXTURE_WRAP_S,
+                glow::TEXTURE_WRAP_T,
+                glow::TEXTURE_WRAP_R,
+            ] {
+                gl.tex_parameter_i32(glow::TEXTURE_3D, wrap, glow::CLAMP_TO_EDGE as i32);
+            }
+            gl.tex_parameter_i32(
+                glow::TEXTURE_3D,
+                glow::TEXTURE_MIN_FILTER,
+                glow::LINEAR as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_3D,
+                glow::TEXTURE_MAG_FILTER,
+                glow::LINEAR as i32,
+            );
+            let (_, bytes, _) = data.align_to::<u8>();
+            gl.tex_image_3d(
+                glow::TEXTURE_3D,
+                0,
+                glow::RGB32F as i32,
+                size,
+                size,
+                size,
+                0,
+                glow::RGB,
+                glow::FLOAT,
+                Some(bytes),
+            );
+            gl.bind_texture(glow::TEXTURE_3D, None);
+            self.lut = Some((texture, lut.size));
         }
+        self.lut_domain = (lut.domain_min, lut.domain_max);
+        self.lut_intensity = intensity;
     }
 
-    pub fn draw(&self, gl: &glow::Context) {
+    pub fn set_lut_intensity(&mut self, intensity: f32) {
+        self.lut_intensity = intensity.clamp(0., 1.);
+    }
+
+    pub fn draw_texture(&self, gl: &glow::Context, texture: glow::NativeTexture) {
+        /*
+            Same pass with the texture of another renderer,
+            it must come from a context sharing its objects with this one
+        */
         unsafe {
             // 2. Bind default framebuffer, draw a plane and show the texture scene
-            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, self.screen);
             gl.disable(glow::DEPTH_TEST);
 
             gl.use_program(Some(self.program));
+            self.grading_uniform.update(gl, &self.grading);
+
+            // without LUT the intensity stay at 0 and the pass keep the colors
+            let (lut_texture, lut_size) = match self.lut {
+                Some((t, size)) => (Some(t), size),
+                None => (None, 2),
+            };
+            let intensity = if lut_texture.is_some() {
+                self.lut_intensity
+            } else {
+                0.
+            };
+            gl.uniform_1_i32(self.lut_loc.as_ref(), 1);
+            gl.uniform_1_f32(self.lut_size_loc.as_ref(), lut_size as f32);
+            gl.uniform_1_f32(self.lut_intensity_loc.as_ref(), intensity);
+            let ([r0, g0, b0], [r1, g1, b1]) = self.lut_domain;
+            gl.uniform_3_f32(self.lut_domain_locs.0.as_ref(), r0, g0, b0);
+            gl.uniform_3_f32(self.lut_domain_locs.1.as_ref(), r1, g1, b1);
+            let [x, y, w, h] = self.uv_rect;
+            gl.uniform_4_f32(self.uv_rect_loc.as_ref(), x, y, w, h);
+            gl.active_texture(glow::TEXTURE1);
+            gl.bind_texture(glow::TEXTURE_3D, lut_texture);
+            gl.active_texture(glow::TEXTURE0);
             gl.bind_vertex_array(Some(self.vao));
-            gl.bind_texture(glow::TEXTURE_2D, Some(self.color_texture_buffer));
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
             gl.draw_arrays(glow::TRIANGLES, 0, 6)
         }
     }
@@ -134,6 +256,11 @@ impl FramebufferRenderer {
             gl.delete_vertex_array(self.vao);
             gl.delete_buffer(self.vbo);
             gl.delete_framebuffer(self.fbo);
+            gl.delete_framebuffer(self.post_fbo);
+            gl.delete_texture(self.post_texture);
+            if let Some((t, _)) = self.lut {
+                gl.delete_texture(t);
+            }
         }
     }
 }
@@ -1,28 +1,128 @@
+use std::collections::VecDeque;
 use std::sync::mpsc::Receiver;
+use std::time::Instant;
 
+use rand::rngs::StdRng;
 use rand::Rng;
 
 use glow::*;
 use iced_glow::glow;
 
+use crate::gl_engine::background_renderer::BackgroundRenderer;
 use crate::gl_engine::buffer_renderer::BufferRenderer;
+use crate::gl_engine::composite_renderer::CompositeRenderer;
+use crate::gl_engine::crt_renderer::CrtRenderer;
 use crate::gl_engine::framebuffer_renderer::FramebufferRenderer;
+use crate::gl_engine::gradient_renderer::GradientRenderer;
+use crate::gl_engine::strip::FrequencyStrip;
 use crate::gl_engine::texture_util::TextureUtil;
-use crate::graphic_config::GraphicConfig;
+use crate::gl_engine::tiling::Pattern;
+use crate::grading::ThemeGrading;
+use crate::graphic_config::{GraphicConfig, Mode};
+use crate::snapshot::Placement;
 use media_handler::frame::Frame;
+use media_handler::palette::Palette;
 
 use nalgebra_glm::vec3;
 
+// media kept in the snapshot sidecar, the older ones are covered in the collage
+const MAX_PLACEMENTS: usize = 64;
+
 pub struct GlProgram {
     first_render: bool,
     texture: glow::NativeTexture,
+    // palette of the last media received
+    pub palette: Palette,
     pub main_renderers: Vec<BufferRenderer>,
     pub framebuffer_renderer: FramebufferRenderer,
+    pub gradient_renderer: GradientRenderer,
+    pub tiling_renderer: BufferRenderer,
+    pub crt_renderer: Option<CrtRenderer>,
+    pub background_renderer: BackgroundRenderer,
+    pub strip: FrequencyStrip,
+    // built on the first frame of the anonymisation mode
+    pub composite_renderer: Option<CompositeRenderer>,
+    pub grading: ThemeGrading,
+    // layout choices, seeded by the session
+    rng: StdRng,
+    // TV screen cuts, apart so they don't shift the layout
+    cut_rng: StdRng,
+    // media currently on screen
+    pub placements: VecDeque<Placement>,
+    // size of the scene framebuffer and of the window, different when spanning
+    canvas_size: (i32, i32),
+    screen_size: (i32, i32),
+    // target of the last pass, the window if None
+    screen: Option<glow::NativeFramebuffer>,
+    // animation clock of the moving modes
+    start: Instant,
 }
 impl TextureUtil for GlProgram {}
 
 impl GlProgram {
-    pub fn new(gl: &glow::Context, config: &GraphicConfig, win_size: (i32, i32)) -> Self {
+    fn new_tiling_renderer(gl: &glow::Context, config: &GraphicConfig) -> BufferRenderer {
+        BufferRenderer::new(
+            gl,
+            &config.kaleidoscope.vertex_path,
+            &config.kaleidoscope.fragment_path,
+            config.loading_media.ratio,
+            true,
+        )
+    }
+
+    fn new_crt_renderer(
+        gl: &glow::Context,
+        config: &GraphicConfig,
+        post_fbo: glow::NativeFramebuffer,
+    ) -> CrtRenderer {
+        let mut renderer = CrtRenderer::new(
+            gl,
+            &config.tv_screen.vertex_path,
+            &config.tv_screen.fragment_path,
+            &config.tv_screen,
+        );
+        renderer.screen = Some(post_fbo);
+        renderer
+    }
+
+    fn new_background_renderer(gl: &glow::Context, config: &GraphicConfig) -> BackgroundRenderer {
+        BackgroundRenderer::new(
+            gl,
+            &config.frequency_strip.vertex_path,
+            &config.frequency_strip.fragment_path,
+        )
+    }
+
+    fn new_composite_renderer(gl: &glow::Context, config: &GraphicConfig) -> CompositeRenderer {
+        CompositeRenderer::new(
+            gl,
+            &config.anonymisation.vertex_path,
+            &config.anonymisation.fragment_path,
+            &config.anonymisation,
+        )
+    }
+
+    fn composite_renderer(
+        &mut self,
+        gl: &glow::Context,
+        config: &GraphicConfig,
+    ) -> &mut CompositeRenderer {
+        // its background decoder and files are only needed by the anonymisation mode
+        let post_fbo = self.framebuffer_renderer.post_fbo;
+        self.composite_renderer.get_or_insert_with(|| {
+            let mut renderer = Self::new_composite_renderer(gl, config);
+            renderer.screen = Some(post_fbo);
+            renderer
+        })
+    }
+
+    pub fn new(
+        gl: &glow::Context,
+        config: &GraphicConfig,
+        win_size: (i32, i32),
+        rng: StdRng,
+        cut_rng: StdRng,
+    ) -> Self {
         unsafe {
             /*
                 -> The first render is trigger by `resize_buffer` due to iced
@@ -39,27 +139,60 @@ impl GlProgram {
                 &config.fbo_fragment_path,
                 (1, 1),
             );
+            let gradient_renderer = GradientRenderer::new(
+                gl,
+                &config.gradient_sweep.vertex_path,
+                &config.gradient_sweep.fragment_path,
+            );
+            let tiling_renderer = Self::new_tiling_renderer(gl, config);
+            let background_renderer = Self::new_background_renderer(gl, config);
+            let strip = FrequencyStrip::new(gl, &config.frequency_strip, &config.loading_media);
             let texture = Self::init_texture(gl);
 
             gl.use_program(None);
-            Self {
+            let mut program = Self {
                 first_render: false,
+                palette: Palette::default(),
                 main_renderers,
                 framebuffer_renderer,
+                gradient_renderer,
+                tiling_renderer,
+                crt_renderer: None,
+                background_renderer,
+                strip,
+                composite_renderer: None,
+                grading: ThemeGrading::new(
+                    config.grading.themes.clone(),
+                    config.grading.blend_duration,
+                ),
+                rng,
+                cut_rng,
+                placements: VecDeque::new(),
+                canvas_size: win_size,
+                screen_size: win_size,
+                screen: None,
+                start: Instant::now(),
                 texture,
-            }
+            };
+            program.set_screen(None);
+            program
         }
     }
 
-    pub fn draw(
+    fn place(&mut self, media: &Frame, position: [f32; 3], keep: usize) {
+        self.placements.push_back(Placement::new(media, position));
+        while self.placements.len() > keep {
+            self.placements.pop_front();
+        }
+    }
+
+    fn draw_slideshow(
         &mut self,
         gl: &glow::Context,
         rx: &Receiver<Frame>,
         next_media: bool,
         viewport_ratio: f32,
     ) {
-        let mut rng = rand::thread_rng();
-
         let mut ratio = 0.;
         for r in &mut self.main_renderers {
             // for each renderers, ask a different media
@@ -71,30 +204,255 @@ impl GlProgram {
             } else {
                 None
             };
+            // draw only for new media so a replay get the same layout
+            let mut position = r.scene.last_pos;
             if let Some(m) = media {
                 ratio = m.ratio;
+                position = vec3(
+                    self.rng.gen_range(-1.2..1.2),
+                    self.rng.gen_range(-1.2..1.2),
+                    1.,
+                );
+                self.placements
+                    .push_back(Placement::new(&m, position.into()));
+                self.palette = m.palette.clone();
+                self.gradient_renderer.restart(&m.palette);
+                self.grading.set_tags(&m.tags);
                 Self::generate_texture(gl, self.texture, &m);
             }
 
-            r.update_scene_data(
-                ratio,
-                vec3(rng.gen_range(-1.2..1.2), rng.gen_range(-1.2..1.2), 1.),
-            );
+            r.update_scene_data(ratio, position);
             unsafe {
                 gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer_renderer.fbo));
             }
             r.draw(gl, self.texture, viewport_ratio);
         }
-        self.framebuffer_renderer.draw(gl);
+        while self.placements.len() > MAX_PLACEMENTS {
+            self.placements.pop_front();
+        }
+    }
+
+    fn receive_single_media(&mut self, gl: &glow::Context, rx: &Receiver<Frame>, next_media: bool) {
+        // modes showing one media at a time on the whole screen
+        if next_media {
+            if let Ok(m) = rx.recv() {
+                self.show_single_media(gl, &m);
+            }
+        }
+    }
+
+    fn show_single_media(&mut self, gl: &glow::Context, m: &Frame) {
+        self.tiling_renderer.scene.ratio = m.ratio;
+        self.palette = m.palette.clone();
+        self.grading.set_tags(&m.tags);
+        Self::generate_texture(gl, self.texture, m);
+        self.place(m, [0., 0., 0.], 1);
+    }
+
+    fn draw_tiling(
+        &mut self,
+        gl: &glow::Context,
+        rx: &Receiver<Frame>,
+        next_media: bool,
+        viewport_ratio: f32,
+        config: &GraphicConfig,
+    ) {
+        /*
+            Repeat the same media over the whole screen
+            The pattern moves every frame so the framebuffer is cleared each time
+        */
+        self.receive_single_media(gl, rx, next_media);
+
+        let k = &config.kaleidoscope;
+        let angle = self.start.elapsed().as_secs_f32() * k.rotation_speed;
+        let models = k.pattern.instances(
+            k.order,
+            angle,
+            self.tiling_renderer.scene.ratio,
+            viewport_ratio,
+        );
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer_renderer.fbo));
+        }
+        self.clear(gl);
+        self.tiling_renderer
+            .draw_instanced(gl, self.texture, &models);
+    }
+
+    fn draw_tv_screen(
+        &mut self,
+        gl: &glow::Context,
+        rx: &Receiver<Frame>,
+        next_media: bool,
+        viewport_ratio: f32,
+        config: &GraphicConfig,
+    ) {
+        /*
+            Accelerated full screen media or clip, the picture is dropped at each cut
+            The cuts follow the ticks, a replay cut at the same ones
+            The CRT pass run before the framebuffer one
+        */
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer_renderer.fbo));
+        }
+        // its clip decoder and files are only needed by this mode
+        let post_fbo = self.framebuffer_renderer.post_fbo;
+        let jump_cut = &mut self
+            .crt_renderer
+            .get_or_insert_with(|| Self::new_crt_renderer(gl, config, post_fbo))
+            .jump_cut;
+        let seconds = jump_cut.interval(config.fps) as f32 / 1000.;
+        let cut = next_media && jump_cut.tick(&config.tv_screen, seconds, &mut self.cut_rng);
+        match jump_cut.clip_frame() {
+            // asked on the tick, the skipped frames are never decoded
+            Some(frame) => self.show_single_media(gl, &frame),
+            None if config.tv_screen.clip.is_none() => {
+                self.receive_single_media(gl, rx, next_media)
+            }
+            None => (),
+        }
+        self.clear(gl);
+        // a black frame mark the cut
+        if cut {
+            return;
+        }
+
+        let model = Pattern::cover(self.tiling_renderer.scene.ratio, viewport_ratio);
+        self.tiling_renderer
+            .draw_instanced(gl, self.texture, &[model]);
+    }
+
+    fn draw_frequency_strip(
+        &mut self,
+        gl: &glow::Context,
+        rx: &Receiver<Frame>,
+        next_media: bool,
+        viewport_ratio: f32,
+        config: &GraphicConfig,
+    ) {
+        /*
+            Dark animated background with a line of images on the top,
+            each image grow with the energy of its frequency band
+        */
+        if next_media {
+            if let Ok(m) = rx.recv() {
+                self.palette = m.palette.clone();
+                self.grading.set_tags(&m.tags);
+                self.strip.push_media(gl, &m);
+                self.place(&m, [0., 0., 0.], config.frequency_strip.bands);
+            }
+        }
+
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer_renderer.fbo));
+        }
+        self.clear(gl);
+        self.background_renderer.draw(gl, &self.palette);
+        let models = self.strip.models(&config.frequency_strip, viewport_ratio);
+        for (texture, model) in self.strip.textures.iter().zip(&models) {
+            self.tiling_renderer.draw_instanced(gl, *texture, &[*model]);
+        }
+    }
+
+    pub fn frame_interval(&self, config: &GraphicConfig) -> u128 {
+        // milliseconds between two media, accelerated in TV screen mode
+        match config.mode {
+            // the speed of the first cut before the TV screen renderer is built
+            Mode::TvScreen => match &self.crt_renderer {
+                Some(r) => r.jump_cut.interval(config.fps),
+                None => (config.fps as f32 / config.tv_screen.speed_range[0]) as u128,
+            },
+            _ => config.fps,
+        }
+    }
+
+    pub fn draw(
+        &mut self,
+        gl: &glow::Context,
+        rx: &Receiver<Frame>,
+        next_media: bool,
+        viewport_ratio: f32,
+        config: &GraphicConfig,
+    ) {
+        unsafe {
+            gl.viewport(0, 0, self.canvas_size.0, self.canvas_size.1);
+        }
+        match config.mode {
+            Mode::Kaleidoscope => self.draw_tiling(gl, rx, next_media, viewport_ratio, config),
+            Mode::TvScreen => self.draw_tv_screen(gl, rx, next_media, viewport_ratio, config),
+            Mode::FrequencyStrip => {
+                self.draw_frequency_strip(gl, rx, next_media, viewport_ratio, config)
+            }
+            Mode::Slideshow | Mode::GradientSweep | Mode::Anonymisation => {
+                self.draw_slideshow(gl, rx, next_media, viewport_ratio)
+            }
+        }
+
+        // the post process passes of a mode draw the scene in the post framebuffer
+        let scene = self.framebuffer_renderer.color_texture_buffer;
+        match config.mode {
+            Mode::TvScreen => {
+                // built by the TV screen draw
+                if let Some(r) = &self.crt_renderer {
+                    r.draw(gl, scene, &config.tv_screen);
+                }
+            }
+            Mode::Anonymisation => {
+                let composite_renderer = self.composite_renderer(gl, config);
+                if next_media {
+                    composite_renderer.next_frame(gl);
+                }
+                composite_renderer.update_regions(gl);
+                composite_renderer.draw(gl, scene);
+            }
+            _ => (),
+        }
+
+        // grading and LUT of every mode
+        unsafe {
+            gl.viewport(0, 0, self.screen_size.0, self.screen_size.1);
+        }
+        self.framebuffer_renderer.grading = self.grading.current();
+        self.framebuffer_renderer
+            .draw_texture(gl, self.shown_texture(config.mode));
+
+        if config.mode == Mode::GradientSweep {
+            self.gradient_renderer.draw(gl, &config.gradient_sweep);
+        }
+    }
+
+    pub fn shown_texture(&self, mode: Mode) -> glow::NativeTexture {
+        // texture given to the framebuffer pass, after the post process of the mode
+        match mode {
+            Mode::TvScreen | Mode::Anonymisation => self.framebuffer_renderer.post_texture,
+            _ => self.framebuffer_renderer.color_texture_buffer,
+        }
+    }
+
+    pub fn set_screen(&mut self, screen: Option<glow::NativeFramebuffer>) {
+        // offscreen rendering, every pass drawing on screen use this framebuffer
+        self.screen = screen;
+        self.framebuffer_renderer.screen = screen;
+        self.gradient_renderer.screen = screen;
+        // graded by the framebuffer pass before reaching the screen
+        if let Some(r) = &mut self.crt_renderer {
+            r.screen = Some(self.framebuffer_renderer.post_fbo);
+        }
+        if let Some(r) = &mut self.composite_renderer {
+            r.screen = Some(self.framebuffer_renderer.post_fbo);
+        }
     }
 
     pub fn resize_buffer(
         &mut self,
         gl: &glow::Context,
-        win_size: (i32, i32),
+        canvas_size: (i32, i32),
+        screen_size: (i32, i32),
         config: &GraphicConfig,
     ) {
         self.cleanup(gl);
+        self.canvas_size = canvas_size;
+        self.screen_size = screen_size;
         // doesn't work for static media if resize || it will be reset and not re render with the unique texture
         self.main_renderers = (0..config.renderer_size)
             .map(|_| {
@@ -113,9 +471,25 @@ impl GlProgram {
             gl,
             &config.fbo_vertex_path,
             &config.fbo_fragment_path,
-            win_size,
+            canvas_size,
+        );
+        if let Some(lut) = &config.lut {
+            self.framebuffer_renderer
+                .set_lut(gl, lut, config.lut_intensity);
+        }
+        self.gradient_renderer = GradientRenderer::new(
+            gl,
+            &config.gradient_sweep.vertex_path,
+            &config.gradient_sweep.fragment_path,
         );
+        self.tiling_renderer = Self::new_tiling_renderer(gl, config);
+        self.background_renderer = Self::new_background_renderer(gl, config);
+        self.strip = FrequencyStrip::new(gl, &config.frequency_strip, &config.loading_media);
+        // built again with the new config when the mode need it
+        self.crt_renderer = None;
+        self.composite_renderer = None;
         self.texture = Self::init_texture(gl);
+        self.set_screen(self.screen);
 
         // clear framebuffer that will be display
         unsafe {
@@ -142,5 +516,15 @@ impl GlProgram {
             r.cleanup(gl)
         }
         self.framebuffer_renderer.cleanup(gl);
+        self.gradient_renderer.cleanup(gl);
+        self.tiling_renderer.cleanup(gl);
+        if let Some(r) = &self.crt_renderer {
+            r.cleanup(gl);
+        }
+        self.background_renderer.cleanup(gl);
+        self.strip.cleanup(gl);
+        if let Some(r) = &self.composite_renderer {
+            r.cleanup(gl);
+        }
     }
 }
@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
+use glow::*;
+use iced_glow::glow;
+use media_handler::palette::Palette;
+
+use crate::gl_engine::buffer_util::BufferUtil;
+use crate::gl_engine::palette_uniform::PaletteUniform;
+use crate::graphic_config::GradientSweepConfig;
+
+pub struct GradientRenderer {
+    pub vao: glow::VertexArray,
+    pub vbo: glow::NativeBuffer,
+    pub program: glow::Program,
+    // framebuffer shown, the window one if None
+    pub screen: Option<glow::NativeFramebuffer>,
+
+    pub palette: Palette,
+    start: Instant,
+    palette_uniform: PaletteUniform,
+    time_loc: Option<NativeUniformLocation>,
+    speed_loc: Option<NativeUniformLocation>,
+    bands_loc: Option<NativeUniformLocation>,
+    direction_loc: Option<NativeUniformLocation>,
+    opacity_loc: Option<NativeUniformLocation>,
+}
+
+impl BufferUtil for GradientRenderer {}
+
+impl GradientRenderer {
+    pub fn new(gl: &glow::Context, vertex_path: &PathBuf, fragment_path: &PathBuf) -> Self {
+        /*
+            Full screen layer drawn over the framebuffer texture
+        */
+        let (program, vao, vbo) = Self::init_program_buffer(
+            gl,
+            vertex_path,
+            fragment_path,
+            &[2, 2],
+            &Self::get_screen_vertex_array(),
+        );
+
+        unsafe {
+            Self {
+                vao,
+                vbo,
+                program,
+                screen: None,
+                palette: Palette::default(),
+                start: Instant::now(),
+                palette_uniform: PaletteUniform::new(gl, &program),
+                time_loc: gl.get_uniform_location(program, "time"),
+                speed_loc: gl.get_uniform_location(program, "speed"),
+                bands_loc: gl.get_uniform_location(program, "bands"),
+                direction_loc: gl.get_uniform_location(program, "direction"),
+                opacity_loc: gl.get_uniform_location(program, "opacity"),
+            }
+        }
+    }
+
+    pub fn restart(&mut self, palette: &Palette) {
+        // a new media is shown, sweep its palette again
+        self.palette = palette.clone();
+        self.start = Instant::now();
+    }
+
+    pub fn draw(&self, gl: &glow::Context, config: &GradientSweepConfig) {
+        /*
+            The gradient cover the screen when a media arrive
+            then fade out to reveal the image during `duration` seconds
+        */
+        let time = self.start.elapsed().as_secs_f32();
+        let opacity = 1. - (time / config.duration).clamp(0., 1.);
+        if opacity <= 0. {
+            return;
+        }
+
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, self.screen);
+            gl.disable(glow::DEPTH_TEST);
+
+            gl.use_program(Some(self.program));
+            self.palette_uniform.update(gl, &self.palette);
+            gl.uniform_1_f32(self.time_loc.as_ref(), time);
+            gl.uniform_1_f32(self.speed_loc.as_ref(), config.speed);
+            gl.uniform_1_i32(self.bands_loc.as_ref(), config.bands as i32);
+            gl.uniform_2_f32(
+                self.direction_loc.as_ref(),
+                config.direction[0],
+                config.direction[1],
+            );
+            gl.uniform_1_f32(self.opacity_loc.as_ref(), opacity);
+
+            gl.bind_vertex_array(Some(self.vao));
+            gl.draw_arrays(glow::TRIANGLES, 0, 6);
+            gl.bind_vertex_array(None);
+        }
+    }
+
+    pub fn cleanup(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_program(self.program);
+            gl.delete_vertex_array(self.vao);
+            gl.delete_buffer(self.vbo);
+        }
+    }
+}
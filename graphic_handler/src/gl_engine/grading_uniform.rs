@@ -0,0 +1,34 @@
+use glow::*;
+use iced_glow::glow;
+
+use crate::grading::GradingProfile;
+
+pub struct GradingUniform {
+    exposure_loc: Option<NativeUniformLocation>,
+    contrast_loc: Option<NativeUniformLocation>,
+    saturation_loc: Option<NativeUniformLocation>,
+    tint_loc: Option<NativeUniformLocation>,
+}
+
+impl GradingUniform {
+    pub fn new(gl: &Context, program: &NativeProgram) -> Self {
+        unsafe {
+            Self {
+                exposure_loc: gl.get_uniform_location(*program, "gradeExposure"),
+                contrast_loc: gl.get_uniform_location(*program, "gradeContrast"),
+                saturation_loc: gl.get_uniform_location(*program, "gradeSaturation"),
+                tint_loc: gl.get_uniform_location(*program, "gradeTint"),
+            }
+        }
+    }
+
+    pub fn update(&self, gl: &Context, profile: &GradingProfile) {
+        // the program must be in use
+        unsafe {
+            gl.uniform_1_f32(self.exposure_loc.as_ref(), profile.exposure);
+            gl.uniform_1_f32(self.contrast_loc.as_ref(), profile.contrast);
+            gl.uniform_1_f32(self.saturation_loc.as_ref(), profile.saturation);
+            gl.uniform_3_f32_slice(self.tint_loc.as_ref(), &profile.tint);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+
+use media_handler::frame::Frame;
+use media_handler::sequence::{FrameSequence, VideoInfo};
+use rand::Rng;
+
+use crate::graphic_config::TvScreenConfig;
+
+enum Clip {
+    // numbered frames of a folder, asked by path
+    Frames(Vec<PathBuf>, Sender<PathBuf>),
+    // frame count of a video decoded by ffmpeg at `clip_fps`, asked by index
+    Video(usize, Sender<usize>),
+}
+
+impl Clip {
+    fn open(path: &Path, fps: f32) -> (Self, FrameSequence) {
+        if path.is_dir() {
+            let mut frames: Vec<PathBuf> = fs::read_dir(path)
+                .unwrap_or_else(|_| panic!("Unable to open the clip {:?}", path))
+                .map(|p| p.unwrap().path())
+                .filter(|f| Frame::is_image(f))
+                .collect();
+            frames.sort();
+            if frames.is_empty() {
+                panic!("Clip {:?} has no frame", path);
+            }
+            let (tx, rx) = mpsc::channel();
+            (Self::Frames(frames, tx), FrameSequence::on_request(rx))
+        } else {
+            let info = VideoInfo::probe(path, fps)
+                .unwrap_or_else(|| panic!("Clip {:?} isn't a video ffprobe can read", path));
+            let (tx, rx) = mpsc::channel();
+            let sequence = FrameSequence::video_on_request(path, fps, info, rx);
+            (Self::Video(info.frames, tx), sequence)
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Frames(frames, _) => frames.len(),
+            Self::Video(frames, _) => *frames,
+        }
+    }
+
+    fn request(&self, index: usize) {
+        // a stopped decoder only stop the clip
+        match self {
+            Self::Frames(frames, tx) => tx.send(frames[index].clone()).ok(),
+            Self::Video(_, tx) => tx.send(index).ok(),
+        };
+    }
+}
+
+pub struct JumpCut {
+    // playback speed of the current cut
+    pub speed: f32,
+    // clip and its frames decoded off the render thread, none when the media are played
+    clip: Option<(Clip, FrameSequence)>,
+    // show seconds since the last cut, counted in ticks so a replay cut at the same ones
+    since_cut: f32,
+    // clip frame reached, and the last one handed out
+    position: f32,
+    shown: Option<usize>,
+}
+
+impl JumpCut {
+    pub fn new(config: &TvScreenConfig) -> Self {
+        Self {
+            speed: config.speed_range[0],
+            clip: config
+                .clip
+                .as_ref()
+                .map(|path| Clip::open(path, config.clip_fps)),
+            since_cut: 0.,
+            position: 0.,
+            shown: None,
+        }
+    }
+
+    fn clip_len(&self) -> usize {
+        self.clip.as_ref().map_or(0, |(clip, _)| clip.len())
+    }
+
+    pub fn interval(&self, fps: u128) -> u128 {
+        // milliseconds between two ticks, the media are accelerated and the clip frames skipped
+        match self.clip {
+            None => (fps as f32 / self.speed) as u128,
+            Some(_) => fps,
+        }
+    }
+
+    pub fn tick<R: Rng>(&mut self, config: &TvScreenConfig, seconds: f32, rng: &mut R) -> bool {
+        /*
+            Move the show forward by the seconds of a tick
+            Hard jump cut every `cut_every`, with a new random speed and clip position
+            Return true when the cut happen so the screen can be flushed
+        */
+        self.since_cut += seconds;
+        self.position += seconds * self.speed * config.clip_fps;
+        let period = config.cut_seconds();
+        if self.since_cut < period {
+            return false;
+        }
+        // the remainder keep the cuts on the beats
+        self.since_cut %= period;
+        let [min, max] = config.speed_range;
+        self.speed = rng.gen_range(min..=max);
+        if self.clip.is_some() {
+            self.position = rng.gen_range(0..self.clip_len()) as f32;
+        }
+        true
+    }
+
+    pub fn next_clip_frame(&mut self) -> Option<usize> {
+        // index of the clip frame to show, none while it is the one already shown
+        let (clip, _) = self.clip.as_ref()?;
+        let index = self.position as usize % clip.len();
+        if self.shown == Some(index) {
+            return None;
+        }
+        self.shown = Some(index);
+        Some(index)
+    }
+
+    pub fn clip_frame(&mut self) -> Option<Frame> {
+        // last clip frame decoded, none while the next one is decoded
+        let index = self.next_clip_frame();
+        let (clip, sequence) = self.clip.as_mut()?;
+        if let Some(index) = index {
+            clip.request(index);
+        }
+        let mut frame = None;
+        while let Some(f) = sequence.next_frame() {
+            frame = Some(f);
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphic_config::CutUnit;
+    use image::{Rgb, RgbImage};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn config(cut_every: f32, cut_unit: CutUnit, clip: Option<PathBuf>) -> TvScreenConfig {
+        TvScreenConfig {
+            vertex_path: PathBuf::new(),
+            fragment_path: PathBuf::new(),
+            bezel: None,
+            clip,
+            clip_fps: 10.,
+            speed_range: [2., 8.],
+            cut_every,
+            cut_unit,
+            bpm: 120.,
+            curvature: 0.,
+            scanlines: 0.,
+            noise: 0.,
+        }
+    }
+
+    fn cuts(config: &TvScreenConfig, seed: u64) -> Vec<(usize, f32)> {
+        // tick and speed of each cut, ticks of 0.1 seconds
+        let mut jump_cut = JumpCut::new(config);
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..100)
+            .filter_map(|i| {
+                jump_cut
+                    .tick(config, 0.1, &mut rng)
+                    .then_some((i, jump_cut.speed))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cut_every_seconds_or_beats() {
+        let ticks = |config: &TvScreenConfig| {
+            let mut jump_cut = JumpCut::new(config);
+            let mut rng = StdRng::seed_from_u64(0);
+            (0..40)
+                .filter(|_| jump_cut.tick(config, 0.25, &mut rng))
+                .count()
+        };
+        // 10 seconds of ticks
+        assert_eq!(ticks(&config(1., CutUnit::Seconds, None)), 10);
+        // 4 beats at 120 bpm are 2 seconds
+        assert_eq!(ticks(&config(4., CutUnit::Beats, None)), 5);
+        assert_eq!(config(4., CutUnit::Beats, None).cut_seconds(), 2.);
+    }
+
+    #[test]
+    fn same_cuts_for_the_same_ticks() {
+        let config = config(1.5, CutUnit::Seconds, None);
+        let first = cuts(&config, 3);
+        assert_eq!(first.len(), 6);
+        assert_eq!(first, cuts(&config, 3));
+        assert!(first.iter().all(|(_, s)| (2. ..=8.).contains(s)));
+        assert_ne!(first, cuts(&config, 4));
+    }
+
+    #[test]
+    fn accelerated_media() {
+        let mut jump_cut = JumpCut::new(&config(1., CutUnit::Seconds, None));
+        assert_eq!(jump_cut.speed, 2.);
+        assert_eq!(jump_cut.interval(100), 50);
+        assert_eq!(jump_cut.next_clip_frame(), None);
+        jump_cut.speed = 4.;
+        assert_eq!(jump_cut.interval(100), 25);
+    }
+
+    #[test]
+    fn clip_played_at_speed() {
+        // one folder per run, removed by the test
+        let folder =
+            std::env::temp_dir().join(format!("cudi_jump_cut_clip_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        for i in 0..50 {
+            let image = RgbImage::from_pixel(2, 2, Rgb([i, 0, 0]));
+            image.save(folder.join(format!("{:03}.png", i))).unwrap();
+        }
+        let config = config(100., CutUnit::Seconds, Some(folder.clone()));
+        let mut jump_cut = JumpCut::new(&config);
+        let mut rng = StdRng::seed_from_u64(0);
+        // the tick interval doesn't depend on the speed
+        assert_eq!(jump_cut.interval(100), 100);
+
+        assert_eq!(jump_cut.next_clip_frame(), Some(0));
+        assert_eq!(jump_cut.next_clip_frame(), None);
+        // 0.1 second at 10 frames per second and speed 2
+        jump_cut.tick(&config, 0.1, &mut rng);
+        assert_eq!(jump_cut.next_clip_frame(), Some(2));
+        jump_cut.speed = 8.;
+        jump_cut.tick(&config, 0.1, &mut rng);
+        assert_eq!(jump_cut.next_clip_frame(), Some(10));
+        // the clip loop
+        for _ in 0..5 {
+            jump_cut.tick(&config, 0.1, &mut rng);
+        }
+        assert_eq!(jump_cut.next_clip_frame(), Some(0));
+        drop(jump_cut);
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
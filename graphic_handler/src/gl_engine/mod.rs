@@ -1,5 +1,14 @@
+pub mod background_renderer;
 pub mod buffer_renderer;
 pub mod buffer_util;
+pub mod composite_renderer;
+pub mod crt_renderer;
 pub mod framebuffer_renderer;
 pub mod gl_program;
+pub mod gradient_renderer;
+pub mod grading_uniform;
+pub mod jump_cut;
+pub mod palette_uniform;
+pub mod strip;
 pub mod texture_util;
+pub mod tiling;
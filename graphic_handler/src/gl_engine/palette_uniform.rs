@@ -0,0 +1,29 @@
+use glow::*;
+use iced_glow::glow;
+use media_handler::palette::Palette;
+
+pub struct PaletteUniform {
+    palette_loc: Option<NativeUniformLocation>,
+}
+
+impl PaletteUniform {
+    pub fn new(gl: &Context, program: &NativeProgram) -> Self {
+        /*
+            Shaders declaring the palette uniform:
+                uniform vec3 palette[5];
+        */
+        unsafe {
+            Self {
+                palette_loc: gl.get_uniform_location(*program, "palette"),
+            }
+        }
+    }
+
+    pub fn update(&self, gl: &Context, palette: &Palette) {
+        // the program must be in use
+        let colors: Vec<f32> = palette.normalized().concat();
+        unsafe {
+            gl.uniform_3_f32_slice(self.palette_loc.as_ref(), &colors);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use glow::*;
+use iced_glow::glow;
+use media_handler::frame::Frame;
+use nalgebra_glm::{scaling, translation, vec3, TMat4};
+
+use crate::gl_engine::texture_util::TextureUtil;
+use crate::graphic_config::FrequencyStripConfig;
+use crate::spectrum::Spectrum;
+
+pub struct FrequencyStrip {
+    // one texture per spectrum band, from the lowest to the highest frequencies
+    pub textures: Vec<glow::NativeTexture>,
+    pub ratios: Vec<f32>,
+    pub spectrum: Spectrum,
+    // next tile to refresh with a new media
+    next: usize,
+}
+
+impl TextureUtil for FrequencyStrip {}
+
+impl FrequencyStrip {
+    pub fn new(gl: &glow::Context, config: &FrequencyStripConfig, loading_media: &Frame) -> Self {
+        let textures: Vec<NativeTexture> = (0..config.bands)
+            .map(|_| {
+                let texture = Self::init_texture(gl);
+                Self::generate_texture(gl, texture, loading_media);
+                texture
+            })
+            .collect();
+
+        Self {
+            textures,
+            ratios: vec![loading_media.ratio; config.bands],
+            spectrum: Spectrum::new(
+                config.bands,
+                config.sample_rate,
+                config.attack,
+                config.release,
+            ),
+            next: 0,
+        }
+    }
+
+    pub fn push_media(&mut self, gl: &glow::Context, media: &Frame) {
+        // tiles are refreshed one by one, the strip slowly renew itself
+        Self::generate_texture(gl, self.textures[self.next], media);
+        self.ratios[self.next] = media.ratio;
+        self.next = (self.next + 1) % self.textures.len();
+    }
+
+    pub fn models(&self, config: &FrequencyStripConfig, viewport_ratio: f32) -> Vec<TMat4<f32>> {
+        /*
+            Tiles in line on the top of the screen, each one scaled by its band energy
+            Tiles hang from the top edge so they grow downward
+        */
+        let count = self.textures.len() as f32;
+        let cell = viewport_ratio / count;
+        let aspect = scaling(&vec3(1. / viewport_ratio, 1., 1.));
+
+        self.ratios
+            .iter()
+            .zip(&self.spectrum.bands)
+            .enumerate()
+            .map(|(i, (ratio, energy))| {
+                let size = cell.min(config.height) * (1. + config.grow * energy);
+                let (sx, sy) = if *ratio > 1. {
+                    (size, size / ratio)
+                } else {
+                    (size * ratio, size)
+                };
+                let x = -viewport_ratio + cell * (2 * i + 1) as f32;
+                aspect * translation(&vec3(x, 1. - sy, 0.)) * scaling(&vec3(sx, sy, 1.))
+            })
+            .collect()
+    }
+
+    pub fn cleanup(&self, gl: &glow::Context) {
+        unsafe {
+            for t in &self.textures {
+                gl.delete_texture(*t);
+            }
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use glow::*;
 use iced_glow::glow;
+use image::imageops;
+use image::RgbaImage;
 use media_handler::frame::Frame;
 
 pub trait TextureUtil {
@@ -40,4 +42,30 @@ pub trait TextureUtil {
             gl.generate_mipmap(glow::TEXTURE_2D);
         }
     }
+
+    fn read_framebuffer(
+        gl: &glow::Context,
+        framebuffer: Option<NativeFramebuffer>,
+        size: (u32, u32),
+    ) -> RgbaImage {
+        // framebuffer content as RGBA, the window one if None
+        let mut data = vec![0; (size.0 * size.1 * 4) as usize];
+        unsafe {
+            gl.bind_framebuffer(glow::READ_FRAMEBUFFER, framebuffer);
+            gl.read_pixels(
+                0,
+                0,
+                size.0 as i32,
+                size.1 as i32,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                glow::PixelPackData::Slice(&mut data),
+            );
+            gl.bind_framebuffer(glow::READ_FRAMEBUFFER, None);
+        }
+        let mut image = RgbaImage::from_raw(size.0, size.1, data).unwrap();
+        // OpenGL rows start from the bottom
+        imageops::flip_vertical_in_place(&mut image);
+        image
+    }
 }
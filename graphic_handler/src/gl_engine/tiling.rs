@@ -0,0 +1,250 @@
+use std::f32::consts::{PI, TAU};
+
+use nalgebra_glm::{rotation, scaling, translation, vec3, TMat4};
+
+// must match the `models` uniform size of the tiling shader, bigger patterns are drawn in batches
+// 64 mat4 is the minimum vertex uniform storage guaranteed by OpenGL 4.1
+pub const MAX_INSTANCES: usize = 64;
+const SPIRAL_TILES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    MirroredGrid,
+    Hexagonal,
+    Radial,
+    Spiral,
+}
+
+struct Tile {
+    position: [f32; 2],
+    angle: f32,
+    // half height of the tile in screen units
+    size: f32,
+    mirror: [bool; 2],
+}
+
+impl Tile {
+    fn new(position: [f32; 2], angle: f32, size: f32) -> Self {
+        Self {
+            position,
+            angle,
+            size,
+            mirror: [false, false],
+        }
+    }
+
+    fn distance(&self) -> f32 {
+        self.position[0].hypot(self.position[1])
+    }
+}
+
+impl Pattern {
+    pub fn new(name: &str) -> Self {
+        match name {
+            "mirrored_grid" => Self::MirroredGrid,
+            "hexagonal" => Self::Hexagonal,
+            "radial" => Self::Radial,
+            "spiral" => Self::Spiral,
+            _ => panic!("Unknown tiling pattern '{}'", name),
+        }
+    }
+
+    fn mirrored_grid(order: u32, viewport_ratio: f32) -> Vec<Tile> {
+        // `order` rows, as many columns as needed to fill the width
+        let rows = order as i32;
+        let size = 1. / order as f32;
+        let cols = (rows as f32 * viewport_ratio).ceil() as i32;
+
+        let mut tiles = vec![];
+        for row in 0..rows {
+            for col in 0..cols {
+                let x = -viewport_ratio + size * (2 * col + 1) as f32;
+                let y = -1. + size * (2 * row + 1) as f32;
+                let mut tile = Tile::new([x, y], 0., size);
+                tile.mirror = [col % 2 == 1, row % 2 == 1];
+                tiles.push(tile);
+            }
+        }
+        tiles
+    }
+
+    fn hexagonal(order: u32, viewport_ratio: f32) -> Vec<Tile> {
+        // pointy hexagon lattice, neighbours rotated by 60 degrees
+        let size = 1. / order as f32;
+        let dx = 3_f32.sqrt() * size;
+        let dy = 1.5 * size;
+        let rows = (1. / dy).ceil() as i32;
+        let cols = (viewport_ratio / dx).ceil() as i32;
+
+        let mut tiles = vec![];
+        for row in -rows..=rows {
+            let offset = if row % 2 == 0 { 0. } else { dx / 2. };
+            for col in -cols..=cols {
+                let angle = (col + row).rem_euclid(6) as f32 * PI / 3.;
+                tiles.push(Tile::new(
+                    [col as f32 * dx + offset, row as f32 * dy],
+                    angle,
+                    size,
+                ));
+            }
+        }
+        tiles
+    }
+
+    fn radial(order: u32) -> Vec<Tile> {
+        // rings of `order * ring` tiles facing the center, one every two mirrored
+        let mut tiles = vec![Tile::new([0., 0.], 0., 0.2)];
+        for ring in 1..=3 {
+            let radius = 0.4 * ring as f32;
+            let count = order * ring;
+            for i in 0..count {
+                let theta = TAU * i as f32 / count as f32;
+                let mut tile = Tile::new(
+                    [radius * theta.cos(), radius * theta.sin()],
+                    theta,
+                    0.12 * ring as f32,
+                );
+                tile.mirror = [i % 2 == 1, false];
+                tiles.push(tile);
+            }
+        }
+        tiles
+    }
+
+    fn spiral(order: u32) -> Vec<Tile> {
+        // `order` arms, tiles grow with the distance to the center
+        (0..SPIRAL_TILES)
+            .map(|i| {
+                let arm = (i as u32 % order) as f32;
+                let step = (i as u32 / order) as f32;
+                let theta = TAU * arm / order as f32 + step * 0.35;
+                let radius = 0.08 + step * 0.12;
+                Tile::new(
+                    [radius * theta.cos(), radius * theta.sin()],
+                    theta,
+                    0.04 + radius * 0.15,
+                )
+            })
+            .collect()
+    }
+
+    pub fn instances(
+        &self,
+        order: u32,
+        angle: f32,
+        media_ratio: f32,
+        viewport_ratio: f32,
+    ) -> Vec<TMat4<f32>> {
+        /*
+            Model matrices of each tile in normalized device coordinates
+            Grid patterns spin each tile, radial patterns spin as a whole
+        */
+        let order = order.max(1);
+        let (mut tiles, local, global) = match self {
+            Self::MirroredGrid => (Self::mirrored_grid(order, viewport_ratio), angle, 0.),
+            Self::Hexagonal => (Self::hexagonal(order, viewport_ratio), angle, 0.),
+            Self::Radial => (Self::radial(order), 0., angle),
+            Self::Spiral => (Self::spiral(order), 0., angle),
+        };
+
+        // keep the image ratio inside a square cell
+        let (fit_x, fit_y) = if media_ratio > 1. {
+            (1., 1. / media_ratio)
+        } else {
+            (media_ratio, 1.)
+        };
+        // from the center, the outer tiles are drawn over the inner ones
+        tiles.sort_by(|a, b| a.distance().total_cmp(&b.distance()));
+
+        let aspect = scaling(&vec3(1. / viewport_ratio, 1., 1.));
+        let spin = rotation(global, &vec3(0., 0., 1.));
+
+        tiles
+            .iter()
+            .map(|t| {
+                let sx = if t.mirror[0] { -t.size } else { t.size };
+                let sy = if t.mirror[1] { -t.size } else { t.size };
+                aspect
+                    * spin
+                    * translation(&vec3(t.position[0], t.position[1], 0.))
+                    * rotation(t.angle + local, &vec3(0., 0., 1.))
+                    * scaling(&vec3(sx * fit_x, sy * fit_y, 1.))
+            })
+            .collect()
+    }
+
+    pub fn batches(models: &[TMat4<f32>]) -> std::slice::Chunks<'_, TMat4<f32>> {
+        // one draw call per batch, in the drawing order
+        models.chunks(MAX_INSTANCES)
+    }
+
+    pub fn cover(media_ratio: f32, viewport_ratio: f32) -> TMat4<f32> {
+        // single tile filling the whole viewport, cropping the media if needed
+        let (sx, sy) = (media_ratio / viewport_ratio, 1.);
+        let fill = if sx < 1. { 1. / sx } else { 1. };
+        scaling(&vec3(sx * fill, sy * fill, 1.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flipped(model: &TMat4<f32>) -> bool {
+        // a single mirror axis turn the tile over, whatever its rotation
+        model[(0, 0)] * model[(1, 1)] - model[(0, 1)] * model[(1, 0)] < 0.
+    }
+
+    #[test]
+    fn instance_counts() {
+        let count = |pattern: Pattern, order: u32, ratio: f32| {
+            pattern.instances(order, 0.3, 1., ratio).len()
+        };
+        // 2 rows of 3 columns to fill a 1.5 wide viewport
+        assert_eq!(count(Pattern::MirroredGrid, 2, 1.5), 6);
+        assert_eq!(count(Pattern::MirroredGrid, 3, 1.), 9);
+        // 5 rows of 5 hexagons
+        assert_eq!(count(Pattern::Hexagonal, 2, 1.), 25);
+        // the center and rings of 4, 8 and 12 tiles
+        assert_eq!(count(Pattern::Radial, 4, 1.), 25);
+        assert_eq!(count(Pattern::Spiral, 3, 1.), SPIRAL_TILES);
+        // order 0 is drawn as order 1
+        assert_eq!(count(Pattern::MirroredGrid, 0, 1.), 1);
+    }
+
+    #[test]
+    fn batches_above_the_uniform_size() {
+        // 8 rows of 15 columns
+        let models = Pattern::MirroredGrid.instances(8, 0., 1., 16. / 9.);
+        assert_eq!(models.len(), 120);
+        let batches: Vec<&[TMat4<f32>]> = Pattern::batches(&models).collect();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].len(), MAX_INSTANCES);
+        assert_eq!(batches[1].len(), 120 - MAX_INSTANCES);
+        assert_eq!(batches.concat(), models);
+
+        let models = Pattern::Spiral.instances(4, 0., 1., 1.);
+        assert_eq!(Pattern::batches(&models).count(), 1);
+    }
+
+    #[test]
+    fn mirror_flags() {
+        // odd columns mirrored horizontally and odd rows vertically, from the bottom left
+        let models = Pattern::MirroredGrid.instances(2, 0., 1., 1.);
+        assert_eq!(models.len(), 4);
+        for m in &models {
+            let (x, y) = (m[(0, 3)], m[(1, 3)]);
+            assert_eq!(m[(0, 0)] < 0., x > 0.);
+            assert_eq!(m[(1, 1)] < 0., y > 0.);
+        }
+        // a spinning grid keep the flags of each tile
+        let spinning = Pattern::MirroredGrid.instances(2, 1., 1., 1.);
+        let flips = |models: &[TMat4<f32>]| models.iter().filter(|m| flipped(m)).count();
+        assert_eq!(flips(&spinning), 2);
+
+        // one ring tile every two
+        let models = Pattern::Radial.instances(4, 0.7, 1., 1.);
+        assert_eq!(flips(&models), 2 + 4 + 6);
+        assert_eq!(flips(&Pattern::Spiral.instances(4, 0.7, 1., 1.)), 0);
+    }
+}
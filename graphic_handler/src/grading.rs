@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct GradingProfile {
+    // exposure in stops
+    pub exposure: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+    // color multiplier
+    pub tint: [f32; 3],
+}
+
+impl GradingProfile {
+    pub fn neutral() -> Self {
+        Self {
+            exposure: 0.,
+            contrast: 1.,
+            saturation: 1.,
+            tint: [1., 1., 1.],
+        }
+    }
+
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            exposure: mix(self.exposure, other.exposure),
+            contrast: mix(self.contrast, other.contrast),
+            saturation: mix(self.saturation, other.saturation),
+            tint: [0, 1, 2].map(|i| mix(self.tint[i], other.tint[i])),
+        }
+    }
+
+    pub fn apply(&self, color: [f32; 3]) -> [f32; 3] {
+        // CPU reference of the `grade` function of the framebuffer shader
+        let exposure = 2_f32.powf(self.exposure);
+        let c = color.map(|v| (v * exposure - 0.5) * self.contrast + 0.5);
+        let l = 0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2];
+        [0, 1, 2].map(|i| ((l + (c[i] - l) * self.saturation) * self.tint[i]).clamp(0., 1.))
+    }
+
+    pub fn average(profiles: &[&Self]) -> Self {
+        // several themes active at once share the screen equally
+        if profiles.is_empty() {
+            return Self::neutral();
+        }
+        let n = profiles.len() as f32;
+        let sum = |f: &dyn Fn(&Self) -> f32| profiles.iter().map(|p| f(p)).sum::<f32>() / n;
+        Self {
+            exposure: sum(&|p| p.exposure),
+            contrast: sum(&|p| p.contrast),
+            saturation: sum(&|p| p.saturation),
+            tint: [0, 1, 2].map(|i| sum(&|p| p.tint[i])),
+        }
+    }
+}
+
+pub struct ThemeGrading {
+    // tag name to grading profile
+    pub profiles: HashMap<String, GradingProfile>,
+    // seconds to blend from a profile to the next one
+    pub blend_duration: f32,
+
+    tags: Vec<String>,
+    from: GradingProfile,
+    target: GradingProfile,
+    blend_start: Instant,
+}
+
+impl ThemeGrading {
+    pub fn new(profiles: HashMap<String, GradingProfile>, blend_duration: f32) -> Self {
+        Self {
+            profiles,
+            blend_duration,
+            tags: vec![],
+            from: GradingProfile::neutral(),
+            target: GradingProfile::neutral(),
+            blend_start: Instant::now(),
+        }
+    }
+
+    pub fn current(&self) -> GradingProfile {
+        let t = if self.blend_duration > 0. {
+            self.blend_start.elapsed().as_secs_f32() / self.blend_duration
+        } else {
+            1.
+        };
+        self.from.lerp(&self.target, t.clamp(0., 1.))
+    }
+
+    pub fn set_tags(&mut self, tags: &[String]) {
+        /*
+            When the tag filter change, blend from the profile currently shown
+            to the average profile of the new tags
+        */
+        if self.tags == tags {
+            return;
+        }
+        let active: Vec<&GradingProfile> =
+            tags.iter().filter_map(|t| self.profiles.get(t)).collect();
+
+        self.from = self.current();
+        self.target = GradingProfile::average(&active);
+        self.blend_start = Instant::now();
+        self.tags = tags.to_vec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn profile(exposure: f32, tint: [f32; 3]) -> GradingProfile {
+        GradingProfile {
+            exposure,
+            contrast: 1.,
+            saturation: 1.,
+            tint,
+        }
+    }
+
+    fn grading(blend_duration: f32) -> ThemeGrading {
+        let profiles = HashMap::from([
+            ("night".to_string(), profile(-1., [0.5, 0.5, 1.])),
+            ("sun".to_string(), profile(1., [1., 0.5, 0.])),
+            ("rain".to_string(), profile(-2., [0., 1., 1.])),
+        ]);
+        ThemeGrading::new(profiles, blend_duration)
+    }
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn mixed_tags_share_the_weights() {
+        let mut grading = grading(0.);
+        // tags without profile don't count in the average
+        grading.set_tags(&tags(&["night", "city", "sun"]));
+        assert_eq!(grading.current(), profile(0., [0.75, 0.5, 0.5]));
+
+        grading.set_tags(&tags(&["rain", "night", "sun", "city"]));
+        let current = grading.current();
+        assert!((current.exposure + 2. / 3.).abs() < 1e-6);
+        assert_eq!(current.tint, [0.5, 2. / 3., 2. / 3.]);
+    }
+
+    #[test]
+    fn neutral_without_matching_tag() {
+        let mut grading = grading(0.);
+        assert_eq!(grading.current(), GradingProfile::neutral());
+        grading.set_tags(&tags(&["night"]));
+        grading.set_tags(&tags(&["city", "sea"]));
+        assert_eq!(grading.current(), GradingProfile::neutral());
+        grading.set_tags(&[]);
+        assert_eq!(grading.current(), GradingProfile::neutral());
+    }
+
+    #[test]
+    fn blend_from_the_profile_shown() {
+        let mut grading = grading(2.);
+        grading.set_tags(&tags(&["sun"]));
+        // halfway of the blend from neutral
+        grading.blend_start = Instant::now() - Duration::from_secs(1);
+        let half = grading.current();
+        assert!((half.exposure - 0.5).abs() < 0.01);
+
+        // the next blend start where the previous one is
+        grading.set_tags(&tags(&["night"]));
+        assert!((grading.current().exposure - half.exposure).abs() < 0.01);
+        grading.blend_start = Instant::now() - Duration::from_secs(3);
+        let end = grading.current();
+        assert!((end.exposure + 1.).abs() < 1e-6);
+        assert_eq!(end.tint, [0.5, 0.5, 1.]);
+    }
+}
@@ -1,6 +1,453 @@
 use media_handler::frame::Frame;
-use std::{fs, path::PathBuf};
-use yaml_rust::YamlLoader;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::gl_engine::tiling::Pattern;
+use crate::grading::GradingProfile;
+use crate::lut::Lut3D;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Slideshow,
+    GradientSweep,
+    Kaleidoscope,
+    TvScreen,
+    // tiles scaled by the spectrum of the `audio` source
+    FrequencyStrip,
+    Anonymisation,
+}
+
+impl Mode {
+    pub fn new(name: &str) -> Self {
+        match name {
+            "slideshow" => Self::Slideshow,
+            "gradient_sweep" => Self::GradientSweep,
+            "kaleidoscope" => Self::Kaleidoscope,
+            "tv_screen" => Self::TvScreen,
+            "frequency_strip" => Self::FrequencyStrip,
+            "anonymisation" => Self::Anonymisation,
+            _ => panic!("Unknown mode '{}'", name),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Slideshow => "slideshow",
+            Self::GradientSweep => "gradient_sweep",
+            Self::Kaleidoscope => "kaleidoscope",
+            Self::TvScreen => "tv_screen",
+            Self::FrequencyStrip => "frequency_strip",
+            Self::Anonymisation => "anonymisation",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GradientSweepConfig {
+    pub vertex_path: PathBuf,
+    pub fragment_path: PathBuf,
+    // screen direction of the sweep
+    pub direction: [f32; 2],
+    // palette scroll per second
+    pub speed: f32,
+    pub bands: u32,
+    // seconds to fade into the image
+    pub duration: f32,
+}
+
+impl GradientSweepConfig {
+    fn direction(name: &str) -> [f32; 2] {
+        match name {
+            "left_to_right" => [1., 0.],
+            "right_to_left" => [-1., 0.],
+            "bottom_to_top" => [0., 1.],
+            "top_to_bottom" => [0., -1.],
+            _ => panic!("Unknown gradient direction '{}'", name),
+        }
+    }
+
+    fn new(cfg: &Yaml, root: &Path) -> Self {
+        Self {
+            vertex_path: GraphicConfig::file_exist(root, cfg["shader"][0].as_str().unwrap()),
+            fragment_path: GraphicConfig::file_exist(root, cfg["shader"][1].as_str().unwrap()),
+            direction: Self::direction(cfg["direction"].as_str().unwrap()),
+            speed: cfg["speed"].as_f64().unwrap() as f32,
+            bands: cfg["bands"].as_i64().unwrap() as u32,
+            duration: cfg["duration"].as_f64().unwrap() as f32,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct KaleidoscopeConfig {
+    pub vertex_path: PathBuf,
+    pub fragment_path: PathBuf,
+    pub pattern: Pattern,
+    // symmetry order: rows, tiles per ring or spiral arms
+    pub order: u32,
+    // radians per second
+    pub rotation_speed: f32,
+}
+
+impl KaleidoscopeConfig {
+    fn new(cfg: &Yaml, root: &Path) -> Self {
+        Self {
+            vertex_path: GraphicConfig::file_exist(root, cfg["shader"][0].as_str().unwrap()),
+            fragment_path: GraphicConfig::file_exist(root, cfg["shader"][1].as_str().unwrap()),
+            pattern: Pattern::new(cfg["pattern"].as_str().unwrap()),
+            order: cfg["order"].as_i64().unwrap() as u32,
+            rotation_speed: cfg["rotation_speed"].as_f64().unwrap() as f32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CutUnit {
+    Seconds,
+    // beats of the `bpm` tempo
+    Beats,
+}
+
+impl CutUnit {
+    pub fn new(name: &str) -> Self {
+        match name {
+            "seconds" => Self::Seconds,
+            "beats" => Self::Beats,
+            _ => panic!("Unknown cut unit '{}'", name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TvScreenConfig {
+    pub vertex_path: PathBuf,
+    pub fragment_path: PathBuf,
+    // TV frame drawn over the screen, the transparent part show the tube
+    pub bezel: Option<Frame>,
+    // video file or folder of numbered frames played instead of the media
+    pub clip: Option<PathBuf>,
+    // frames per second of the clip at speed 1
+    pub clip_fps: f32,
+    // playback speed multiplier, a new one is picked at each cut
+    pub speed_range: [f32; 2],
+    // time between two jump cuts, in `cut_unit`
+    pub cut_every: f32,
+    pub cut_unit: CutUnit,
+    pub bpm: f32,
+    pub curvature: f32,
+    // number of scanlines on the screen height
+    pub scanlines: f32,
+    pub noise: f32,
+}
+
+impl TvScreenConfig {
+    fn new(cfg: &Yaml, root: &Path) -> Self {
+        let speed_range = [
+            cfg["speed_range"][0].as_f64().unwrap() as f32,
+            cfg["speed_range"][1].as_f64().unwrap() as f32,
+        ];
+        if !(0. < speed_range[0] && speed_range[0] <= speed_range[1]) {
+            panic!("tv_screen speed_range must be [min, max] with 0 < min <= max");
+        }
+        let config = Self {
+            vertex_path: GraphicConfig::file_exist(root, cfg["shader"][0].as_str().unwrap()),
+            fragment_path: GraphicConfig::file_exist(root, cfg["shader"][1].as_str().unwrap()),
+            bezel: cfg["bezel"]
+                .as_str()
+                .map(|p| Frame::new(GraphicConfig::file_exist(root, p))),
+            clip: cfg["clip"]
+                .as_str()
+                .map(|p| GraphicConfig::file_exist(root, p)),
+            clip_fps: cfg["clip_fps"].as_f64().unwrap() as f32,
+            speed_range,
+            cut_every: cfg["cut_every"].as_f64().unwrap() as f32,
+            cut_unit: CutUnit::new(cfg["cut_unit"].as_str().unwrap()),
+            bpm: cfg["bpm"].as_f64().unwrap() as f32,
+            curvature: cfg["curvature"].as_f64().unwrap() as f32,
+            scanlines: cfg["scanlines"].as_f64().unwrap() as f32,
+            noise: cfg["noise"].as_f64().unwrap() as f32,
+        };
+        if config.cut_seconds() <= 0. {
+            panic!("tv_screen cut_every and bpm must be positive");
+        }
+        config
+    }
+
+    pub fn cut_seconds(&self) -> f32 {
+        match self.cut_unit {
+            CutUnit::Seconds => self.cut_every,
+            CutUnit::Beats => self.cut_every * 60. / self.bpm,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FrequencyStripConfig {
+    // slow dark background animation shader
+    pub vertex_path: PathBuf,
+    pub fragment_path: PathBuf,
+    // number of images, one per spectrum band
+    pub bands: usize,
+    // tile half height at rest, in screen units
+    pub height: f32,
+    // extra scale of a tile when its band is at full energy
+    pub grow: f32,
+    pub attack: f32,
+    pub release: f32,
+    pub sample_rate: f32,
+}
+
+impl FrequencyStripConfig {
+    fn new(cfg: &Yaml, root: &Path) -> Self {
+        let bands = cfg["bands"].as_i64().unwrap();
+        if bands < 1 {
+            panic!("frequency_strip.bands must be at least 1, found {}", bands);
+        }
+        Self {
+            vertex_path: GraphicConfig::file_exist(root, cfg["shader"][0].as_str().unwrap()),
+            fragment_path: GraphicConfig::file_exist(root, cfg["shader"][1].as_str().unwrap()),
+            bands: bands as usize,
+            height: cfg["height"].as_f64().unwrap() as f32,
+            grow: cfg["grow"].as_f64().unwrap() as f32,
+            attack: cfg["attack"].as_f64().unwrap() as f32,
+            release: cfg["release"].as_f64().unwrap() as f32,
+            sample_rate: cfg["sample_rate"].as_f64().unwrap() as f32,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AudioSource {
+    // played in loop at its real speed
+    File(PathBuf),
+    // input device, e.g. format "pulse" or "alsa" and device "default"
+    Capture { format: String, device: String },
+}
+
+impl AudioSource {
+    fn new(cfg: &Yaml, root: &Path) -> Option<Self> {
+        let path = || GraphicConfig::file_exist(root, cfg["path"].as_str().unwrap());
+        let source = match cfg["type"].as_str()? {
+            "file" => Self::File(path()),
+            "capture" => Self::Capture {
+                format: String::from(cfg["format"].as_str().unwrap()),
+                device: String::from(cfg["device"].as_str().unwrap()),
+            },
+            t => panic!("Unknown audio type '{}'", t),
+        };
+        Some(source)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskSource {
+    Image(PathBuf),
+    // folder of frames played with the background
+    Sequence(PathBuf),
+    // (x, y, width, height) in screen fraction, from the top left corner
+    Regions(Vec<[f32; 4]>),
+    // regions written by a detector, one "x y width height" per line, reloaded when it change
+    RegionsFile(PathBuf),
+}
+
+impl MaskSource {
+    fn new(cfg: &Yaml, root: &Path) -> Self {
+        // the files are checked by the anonymisation mode, the only one using them
+        let path = || root.join(cfg["path"].as_str().unwrap());
+        match cfg["type"].as_str().unwrap() {
+            "image" => Self::Image(path()),
+            "sequence" => Self::Sequence(path()),
+            "regions" => Self::Regions(
+                cfg["regions"]
+                    .as_vec()
+                    .unwrap()
+                    .iter()
+                    .map(|r| [0, 1, 2, 3].map(|i| r[i].as_f64().unwrap() as f32))
+                    .collect(),
+            ),
+            "regions_file" => Self::RegionsFile(path()),
+            t => panic!("Unknown mask type '{}'", t),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AnonymisationConfig {
+    pub vertex_path: PathBuf,
+    pub fragment_path: PathBuf,
+    // image or folder of frames played in loop behind the slideshow
+    pub background: PathBuf,
+    pub mask: MaskSource,
+    // blur radius of the region masks, in screen fraction
+    pub feather: f32,
+}
+
+impl AnonymisationConfig {
+    fn new(cfg: &Yaml, root: &Path) -> Self {
+        Self {
+            vertex_path: GraphicConfig::file_exist(root, cfg["shader"][0].as_str().unwrap()),
+            fragment_path: GraphicConfig::file_exist(root, cfg["shader"][1].as_str().unwrap()),
+            background: root.join(cfg["background"].as_str().unwrap()),
+            mask: MaskSource::new(&cfg["mask"], root),
+            feather: cfg["feather"].as_f64().unwrap() as f32,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GradingConfig {
+    // seconds to blend between two profiles
+    pub blend_duration: f32,
+    // tag name to grading profile
+    pub themes: HashMap<String, GradingProfile>,
+}
+
+impl GradingConfig {
+    fn profile(cfg: &Yaml) -> GradingProfile {
+        GradingProfile {
+            exposure: cfg["exposure"].as_f64().unwrap() as f32,
+            contrast: cfg["contrast"].as_f64().unwrap() as f32,
+            saturation: cfg["saturation"].as_f64().unwrap() as f32,
+            tint: [0, 1, 2].map(|i| cfg["tint"][i].as_f64().unwrap() as f32),
+        }
+    }
+
+    fn new(cfg: &Yaml) -> Self {
+        Self {
+            blend_duration: cfg["blend_duration"].as_f64().unwrap() as f32,
+            themes: cfg["themes"]
+                .as_hash()
+                .unwrap()
+                .iter()
+                .map(|(tag, profile)| (String::from(tag.as_str().unwrap()), Self::profile(profile)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowMode {
+    Windowed,
+    Borderless,
+    Exclusive,
+}
+
+impl WindowMode {
+    pub fn new(name: &str) -> Self {
+        match name {
+            "windowed" => Self::Windowed,
+            "borderless" => Self::Borderless,
+            "exclusive" => Self::Exclusive,
+            _ => panic!("Unknown window mode '{}'", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MonitorSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl MonitorSelector {
+    pub fn new(value: &str) -> Self {
+        // a number is an index, anything else a monitor name
+        match value.parse() {
+            Ok(i) => Self::Index(i),
+            Err(_) => Self::Name(String::from(value)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WindowConfig {
+    pub mode: WindowMode,
+    // primary monitor if None
+    pub monitor: Option<MonitorSelector>,
+    pub always_on_top: bool,
+    // seconds without mouse move before hiding the cursor, never hidden if None
+    pub hide_cursor_after: Option<f32>,
+}
+
+impl WindowConfig {
+    fn new(cfg: &Yaml) -> Self {
+        let monitor = match &cfg["monitor"] {
+            Yaml::Integer(i) => Some(MonitorSelector::Index(*i as usize)),
+            Yaml::String(name) => Some(MonitorSelector::new(name)),
+            _ => None,
+        };
+        Self {
+            mode: WindowMode::new(cfg["mode"].as_str().unwrap()),
+            monitor,
+            always_on_top: cfg["always_on_top"].as_bool().unwrap(),
+            hide_cursor_after: cfg["hide_cursor_after"].as_f64().map(|s| s as f32),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    PngSequence,
+    Video,
+}
+
+impl RecordFormat {
+    pub fn new(name: &str) -> Self {
+        match name {
+            "png" => Self::PngSequence,
+            "video" => Self::Video,
+            _ => panic!("Unknown record format '{}'", name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RecordConfig {
+    // record from the launch, F9 or the controls toggle it later
+    pub start: bool,
+    pub format: RecordFormat,
+    // folder receiving one sequence folder or video file per recording
+    pub output: PathBuf,
+    // output frame rate, the measured display frame rate if None
+    pub fps: Option<u32>,
+    // audio file muxed in the video, the `audio` source playing if None
+    pub audio: Option<PathBuf>,
+}
+
+impl RecordConfig {
+    fn new(cfg: &Yaml, root: &Path) -> Self {
+        Self {
+            start: cfg["start"].as_bool().unwrap(),
+            format: RecordFormat::new(cfg["format"].as_str().unwrap()),
+            output: root.join(cfg["output"].as_str().unwrap()),
+            fps: cfg["fps"].as_i64().map(|f| f as u32),
+            audio: cfg["audio"].as_str().map(|p| root.join(p)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SpanConfig {
+    // one logical canvas shared by all the windows, from left to right
+    pub enabled: bool,
+    // pixels hidden behind the monitor frames between two windows
+    pub bezel: u32,
+}
+
+impl SpanConfig {
+    fn new(cfg: &Yaml) -> Self {
+        Self {
+            enabled: cfg["enabled"].as_bool().unwrap(),
+            bezel: cfg["bezel"].as_i64().unwrap() as u32,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct GraphicConfig {
@@ -9,6 +456,10 @@ pub struct GraphicConfig {
     pub width: u32,
     pub height: u32,
     pub app_name: String,
+    pub window: WindowConfig,
+    // extra windows sharing the same media
+    pub outputs: Vec<WindowConfig>,
+    pub span: SpanConfig,
     pub loading_media: Frame,
     pub vertex_path: PathBuf,
     pub fragment_path: PathBuf,
@@ -16,22 +467,55 @@ pub struct GraphicConfig {
     pub fbo_fragment_path: PathBuf,
 
     pub renderer_size: u8,
+    pub mode: Mode,
+    pub gradient_sweep: GradientSweepConfig,
+    pub kaleidoscope: KaleidoscopeConfig,
+    pub tv_screen: TvScreenConfig,
+    pub frequency_strip: FrequencyStripConfig,
+    // sound analysed by the frequency strip, none if empty
+    pub audio: Option<AudioSource>,
+    pub anonymisation: AnonymisationConfig,
+    pub grading: GradingConfig,
+    // .cube 3D LUT applied on the whole screen
+    pub lut: Option<Lut3D>,
+    pub lut_intensity: f32,
+    pub record: RecordConfig,
+    // snapshots and their sidecar, F12 take one
+    pub snapshot_folder: PathBuf,
 }
 
 impl GraphicConfig {
-    fn file_exist(p: &str) -> PathBuf {
-        let path = PathBuf::from(p);
+    fn file_exist(root: &Path, p: &str) -> PathBuf {
+        let path = root.join(p);
         match path.try_exists() {
             Ok(_) => path,
             Err(_) => panic!("File doesn't exist. Check file path or use default."),
         }
     }
 
+    pub fn media_per_tick(&self) -> u8 {
+        // number of media requested to the media handler at each refresh
+        match self.mode {
+            Mode::Slideshow | Mode::GradientSweep | Mode::Anonymisation => self.renderer_size,
+            // a clip replace the media
+            Mode::TvScreen => self.tv_screen.clip.is_none() as u8,
+            Mode::Kaleidoscope | Mode::FrequencyStrip => 1,
+        }
+    }
+
+    pub fn windows(&self) -> Vec<&WindowConfig> {
+        // main window first, it holds the controls
+        let mut windows = vec![&self.window];
+        windows.extend(self.outputs.iter());
+        windows
+    }
+
     pub fn new(config_file_path: &str) -> Self {
-        /*
-            could use an arg call "window_config" with floating or fullscreen
-            to set the window size
-        */
+        // paths of the config are relative to the working directory
+        Self::load(Path::new(config_file_path), Path::new(""))
+    }
+
+    pub fn load(config_file_path: &Path, root: &Path) -> Self {
         let raw_cfg =
             fs::read_to_string(config_file_path).expect("Unable to read graphic config file");
         let cfg = &YamlLoader::load_from_str(&raw_cfg).unwrap()[0];
@@ -41,12 +525,38 @@ impl GraphicConfig {
             width: cfg["width"].as_i64().unwrap() as u32,
             height: cfg["height"].as_i64().unwrap() as u32,
             app_name: String::from(cfg["window_name"].as_str().unwrap()),
-            loading_media: Frame::new(Self::file_exist(cfg["loading_media"].as_str().unwrap())),
-            vertex_path: Self::file_exist(cfg["engine_shader"][0].as_str().unwrap()),
-            fragment_path: Self::file_exist(cfg["engine_shader"][1].as_str().unwrap()),
-            fbo_vertex_path: Self::file_exist(cfg["framebuffer_shader"][0].as_str().unwrap()),
-            fbo_fragment_path: Self::file_exist(cfg["framebuffer_shader"][1].as_str().unwrap()),
+            window: WindowConfig::new(&cfg["window"]),
+            outputs: cfg["outputs"]
+                .as_vec()
+                .map(|v| v.iter().map(WindowConfig::new).collect())
+                .unwrap_or_default(),
+            span: SpanConfig::new(&cfg["span"]),
+            loading_media: Frame::new(Self::file_exist(
+                root,
+                cfg["loading_media"].as_str().unwrap(),
+            )),
+            vertex_path: Self::file_exist(root, cfg["engine_shader"][0].as_str().unwrap()),
+            fragment_path: Self::file_exist(root, cfg["engine_shader"][1].as_str().unwrap()),
+            fbo_vertex_path: Self::file_exist(root, cfg["framebuffer_shader"][0].as_str().unwrap()),
+            fbo_fragment_path: Self::file_exist(
+                root,
+                cfg["framebuffer_shader"][1].as_str().unwrap(),
+            ),
             renderer_size: cfg["renderer_size"].as_i64().unwrap() as u8,
+            mode: Mode::new(cfg["mode"].as_str().unwrap()),
+            gradient_sweep: GradientSweepConfig::new(&cfg["gradient_sweep"], root),
+            kaleidoscope: KaleidoscopeConfig::new(&cfg["kaleidoscope"], root),
+            tv_screen: TvScreenConfig::new(&cfg["tv_screen"], root),
+            frequency_strip: FrequencyStripConfig::new(&cfg["frequency_strip"], root),
+            audio: AudioSource::new(&cfg["audio"], root),
+            anonymisation: AnonymisationConfig::new(&cfg["anonymisation"], root),
+            grading: GradingConfig::new(&cfg["grading"]),
+            lut: cfg["lut"]["path"]
+                .as_str()
+                .map(|p| Lut3D::load(&Self::file_exist(root, p))),
+            lut_intensity: cfg["lut"]["intensity"].as_f64().unwrap() as f32,
+            record: RecordConfig::new(&cfg["record"], root),
+            snapshot_folder: root.join(cfg["snapshot_folder"].as_str().unwrap()),
         }
     }
 }
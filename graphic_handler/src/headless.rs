@@ -0,0 +1,390 @@
+use std::panic;
+use std::sync::mpsc::Receiver;
+
+use glow::*;
+use iced_glow::glow;
+use image::RgbaImage;
+
+use iced_glutin::glutin;
+use iced_glutin::glutin::dpi::PhysicalSize;
+use iced_glutin::glutin::event_loop::{EventLoop, EventLoopBuilder};
+use iced_glutin::glutin::PossiblyCurrent;
+use rand::rngs::StdRng;
+
+use crate::cpu_renderer::CpuRenderer;
+use crate::gl_engine::gl_program::GlProgram;
+use crate::gl_engine::texture_util::TextureUtil;
+use crate::graphic_config::GraphicConfig;
+use media_handler::frame::Frame;
+
+struct GlBackend {
+    // dropped before its event loop
+    _context: glutin::Context<PossiblyCurrent>,
+    gl: Context,
+    program: GlProgram,
+    // replace the window framebuffer
+    fbo: NativeFramebuffer,
+    color: NativeRenderbuffer,
+    _event_loop: EventLoop<()>,
+}
+
+impl TextureUtil for GlBackend {}
+
+impl GlBackend {
+    fn event_loop() -> EventLoop<()> {
+        let mut builder = EventLoopBuilder::new();
+        // tests render outside of the main thread
+        #[cfg(target_os = "linux")]
+        glutin::platform::unix::EventLoopBuilderExtUnix::with_any_thread(&mut builder, true);
+        builder.build()
+    }
+
+    fn new(
+        config: &GraphicConfig,
+        size: (u32, u32),
+        rng: StdRng,
+        cut_rng: StdRng,
+    ) -> Result<Self, String> {
+        /*
+            Headless context, a software one when the GL driver is Mesa llvmpipe
+            The last pass render in a sRGB renderbuffer like the window one
+        */
+        let event_loop = panic::catch_unwind(Self::event_loop)
+            .map_err(|_| String::from("no display server available"))?;
+        let context = glutin::ContextBuilder::new()
+            .build_headless(&event_loop, PhysicalSize::new(size.0, size.1))
+            .map_err(|e| e.to_string())?;
+        let context = unsafe { context.make_current() }.map_err(|(_, e)| e.to_string())?;
+
+        let (w, h) = (size.0 as i32, size.1 as i32);
+        let (gl, fbo, color) = unsafe {
+            let gl =
+                glow::Context::from_loader_function(|s| context.get_proc_address(s) as *const _);
+            gl.enable(glow::FRAMEBUFFER_SRGB);
+            gl.enable(glow::BLEND);
+            gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
+            gl.disable(glow::MULTISAMPLE);
+            gl.enable(glow::DEPTH_TEST);
+
+            let fbo = gl.create_framebuffer()?;
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(fbo));
+            let color = gl.create_renderbuffer()?;
+            gl.bind_renderbuffer(glow::RENDERBUFFER, Some(color));
+            gl.renderbuffer_storage(glow::RENDERBUFFER, glow::SRGB8_ALPHA8, w, h);
+            gl.bind_renderbuffer(glow::RENDERBUFFER, None);
+            gl.framebuffer_renderbuffer(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT0,
+                glow::RENDERBUFFER,
+                Some(color),
+            );
+            if gl.check_framebuffer_status(glow::FRAMEBUFFER) != glow::FRAMEBUFFER_COMPLETE {
+                return Err(String::from("incomplete offscreen framebuffer"));
+            }
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+            gl.viewport(0, 0, w, h);
+            (gl, fbo, color)
+        };
+
+        let mut program = GlProgram::new(&gl, config, (w, h), rng, cut_rng);
+        program.set_screen(Some(fbo));
+        program.resize_buffer(&gl, (w, h), (w, h), config);
+        Ok(Self {
+            _context: context,
+            gl,
+            program,
+            fbo,
+            color,
+            _event_loop: event_loop,
+        })
+    }
+
+    fn render(
+        &mut self,
+        rx: &Receiver<Frame>,
+        next_media: bool,
+        config: &GraphicConfig,
+        size: (u32, u32),
+    ) -> RgbaImage {
+        let ratio = size.0 as f32 / size.1 as f32;
+        self.program.draw(&self.gl, rx, next_media, ratio, config);
+
+        Self::read_framebuffer(&self.gl, Some(self.fbo), size)
+    }
+
+    fn cleanup(&self) {
+        self.program.cleanup(&self.gl);
+        unsafe {
+            self.gl.delete_framebuffer(self.fbo);
+            self.gl.delete_renderbuffer(self.color);
+        }
+    }
+}
+
+enum Backend {
+    Gl(Box<GlBackend>),
+    Cpu(Box<CpuRenderer>),
+}
+
+pub struct Headless {
+    pub config: GraphicConfig,
+    pub size: (u32, u32),
+    backend: Backend,
+}
+
+impl Headless {
+    pub fn new(config: GraphicConfig, size: (u32, u32), rng: StdRng, cut_rng: StdRng) -> Self {
+        /*
+            Render the show without window, frames are read back as RGBA
+            GL first, the CPU rasterizer if no context can be created
+        */
+        let backend = match GlBackend::new(&config, size, rng.clone(), cut_rng) {
+            Ok(b) => Backend::Gl(Box::new(b)),
+            Err(e) => {
+                log::warn!("No headless GL context ({}), using the CPU renderer", e);
+                Backend::Cpu(Box::new(CpuRenderer::new(size, &config, rng)))
+            }
+        };
+        Self {
+            config,
+            size,
+            backend,
+        }
+    }
+
+    pub fn cpu(config: GraphicConfig, size: (u32, u32), rng: StdRng) -> Self {
+        // same pixels on every machine, for the regression tests
+        Self {
+            backend: Backend::Cpu(Box::new(CpuRenderer::new(size, &config, rng))),
+            config,
+            size,
+        }
+    }
+
+    pub fn is_gl(&self) -> bool {
+        matches!(self.backend, Backend::Gl(_))
+    }
+
+    pub fn set_config(&mut self, config: GraphicConfig) {
+        // the GL renderers are built again for the new mode or LUT
+        if let Backend::Gl(b) = &mut self.backend {
+            let size = (self.size.0 as i32, self.size.1 as i32);
+            b.program.resize_buffer(&b.gl, size, size, &config);
+        }
+        self.config = config;
+    }
+
+    pub fn render(&mut self, rx: &Receiver<Frame>, next_media: bool) -> RgbaImage {
+        match &mut self.backend {
+            Backend::Gl(b) => b.render(rx, next_media, &self.config, self.size),
+            Backend::Cpu(c) => c.render(rx, next_media, &self.config),
+        }
+    }
+}
+
+impl Drop for Headless {
+    fn drop(&mut self) {
+        if let Backend::Gl(b) = &self.backend {
+            b.cleanup();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc;
+
+    use image::{DynamicImage, Rgba};
+    use rand::SeedableRng;
+
+    use crate::grading::GradingProfile;
+    use crate::graphic_config::Mode;
+    use crate::lut::Lut3D;
+
+    const SIZE: (u32, u32) = (64, 48);
+
+    const ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+
+    fn config() -> GraphicConfig {
+        // paths of the config are relative to the repository root
+        let root = Path::new(ROOT);
+        let mut config = GraphicConfig::load(&root.join("confs/graphic.yaml"), root);
+        config.mode = Mode::Slideshow;
+        config.grading.blend_duration = 0.;
+        config.lut = None;
+        config
+    }
+
+    fn headless(config: GraphicConfig) -> Headless {
+        Headless::cpu(config, SIZE, StdRng::seed_from_u64(7))
+    }
+
+    fn fill(headless: &mut Headless, color: [u8; 3]) {
+        if let Backend::Cpu(c) = &mut headless.backend {
+            let [r, g, b] = color;
+            c.canvas = RgbaImage::from_pixel(SIZE.0, SIZE.1, Rgba([r, g, b, 255]));
+        }
+    }
+
+    fn solid(color: [u8; 3], tags: &[&str]) -> Frame {
+        let [r, g, b] = color;
+        let image = RgbaImage::from_pixel(32, 32, Rgba([r, g, b, 255]));
+        let mut frame = Frame::from_image(PathBuf::from("solid"), DynamicImage::ImageRgba8(image));
+        frame.tags = tags.iter().map(|t| t.to_string()).collect();
+        frame
+    }
+
+    fn render(headless: &mut Headless, media: Vec<Frame>) -> RgbaImage {
+        // without sender left the renderer stop waiting for media
+        let (tx, rx) = mpsc::channel();
+        let next_media = !media.is_empty();
+        for m in media {
+            tx.send(m).unwrap();
+        }
+        drop(tx);
+        headless.render(&rx, next_media)
+    }
+
+    fn colors(frame: &RgbaImage) -> Vec<[u8; 3]> {
+        let mut colors: Vec<[u8; 3]> = frame.pixels().map(|p| [p[0], p[1], p[2]]).collect();
+        colors.sort();
+        colors.dedup();
+        colors
+    }
+
+    #[test]
+    fn cpu_keep_the_colors_without_filter() {
+        let mut headless = headless(config());
+        fill(&mut headless, [200, 100, 50]);
+        assert_eq!(colors(&render(&mut headless, vec![])), [[200, 100, 50]]);
+    }
+
+    #[test]
+    fn cpu_grade_the_frame() {
+        let mut config = config();
+        gray_theme(&mut config);
+        let profile = config.grading.themes["gray"];
+        let mut headless = headless(config);
+
+        // luminance of pure red
+        assert_eq!(profile.apply([1., 0., 0.]), [0.2126; 3]);
+        let frame = render(&mut headless, vec![solid([255, 0, 0], &["gray"])]);
+        assert_eq!(colors(&frame), [[0, 0, 0], [54, 54, 54]]);
+    }
+
+    fn inverse_lut() -> Lut3D {
+        let mut content = String::from("LUT_3D_SIZE 2\n");
+        for i in 0..8 {
+            let inv = |bit: i32| 1 - ((i >> bit) & 1);
+            content += &format!("{} {} {}\n", inv(0), inv(1), inv(2));
+        }
+        Lut3D::parse(&content).unwrap()
+    }
+
+    fn gray_theme(config: &mut GraphicConfig) {
+        let profile = GradingProfile {
+            exposure: 0.,
+            contrast: 1.,
+            saturation: 0.,
+            tint: [1., 1., 1.],
+        };
+        config.grading.themes.insert(String::from("gray"), profile);
+    }
+
+    #[test]
+    fn cpu_apply_the_lut() {
+        let mut config = config();
+        config.lut = Some(inverse_lut());
+        config.lut_intensity = 0.5;
+        let mut headless = headless(config);
+
+        fill(&mut headless, [0, 255, 0]);
+        assert_eq!(colors(&render(&mut headless, vec![])), [[128, 128, 128]]);
+        headless.config.lut_intensity = 1.;
+        assert_eq!(colors(&render(&mut headless, vec![])), [[255, 0, 255]]);
+    }
+
+    #[test]
+    fn cpu_same_seed_same_frame() {
+        let media = || {
+            vec![
+                solid([255, 0, 0], &[]),
+                solid([0, 255, 0], &[]),
+                solid([0, 0, 255], &[]),
+            ]
+        };
+        let first = render(&mut headless(config()), media());
+        assert_eq!(first, render(&mut headless(config()), media()));
+        assert_eq!(colors(&first).len(), 4);
+    }
+
+    #[test]
+    fn gl_pipeline() {
+        /*
+            Frames of GlProgram, one GL context per process so every check share it
+            Pure colors and uniform frames don't depend on the sRGB conversion
+            Skipped without GL driver
+        */
+        let mut gray_config = config();
+        gray_theme(&mut gray_config);
+        let mut headless = Headless::new(
+            gray_config,
+            SIZE,
+            StdRng::seed_from_u64(7),
+            StdRng::seed_from_u64(8),
+        );
+        if !headless.is_gl() {
+            println!("No GL context, the GL pipeline is not tested");
+            return;
+        }
+        let red = |count: u8, tags: &[&str]| {
+            (0..count)
+                .map(|_| solid([255, 0, 0], tags))
+                .collect::<Vec<Frame>>()
+        };
+        let per_tick = headless.config.media_per_tick();
+
+        // the slideshow draw its media over the background
+        let mut frame = render(&mut headless, vec![]);
+        for _ in 0..3 {
+            frame = render(&mut headless, red(per_tick, &[]));
+        }
+        assert_eq!(frame.dimensions(), SIZE);
+        assert!(frame.pixels().any(|p| p[0] > 128 && p[1] < 64 && p[2] < 64));
+
+        // the gray theme of the media drop the colors of the whole screen
+        let frame = render(&mut headless, red(per_tick, &["gray"]));
+        assert!(frame.pixels().all(|p| p[0] == p[1] && p[1] == p[2]));
+        assert!(frame.pixels().any(|p| p[0] > 0));
+
+        // half of an inverse LUT turn every color in the same gray
+        let mut lut_config = config();
+        lut_config.lut = Some(inverse_lut());
+        lut_config.lut_intensity = 0.5;
+        headless.set_config(lut_config);
+        let frame = render(&mut headless, red(per_tick, &[]));
+        let gray = colors(&frame);
+        assert_eq!(gray.len(), 1);
+        assert!(gray[0][0] == gray[0][1] && gray[0][1] == gray[0][2]);
+
+        // every mode draw a full frame
+        for mode in [
+            Mode::GradientSweep,
+            Mode::Kaleidoscope,
+            Mode::TvScreen,
+            Mode::FrequencyStrip,
+            Mode::Anonymisation,
+        ] {
+            let mut mode_config = config();
+            mode_config.mode = mode;
+            let per_tick = mode_config.media_per_tick();
+            headless.set_config(mode_config);
+            for _ in 0..2 {
+                let frame = render(&mut headless, red(per_tick, &[]));
+                assert_eq!(frame.dimensions(), SIZE, "{:?}", mode);
+            }
+        }
+    }
+}
@@ -1,17 +1,34 @@
+pub mod audio;
 mod controls;
+mod cpu_renderer;
 mod gl_engine;
+mod grading;
 pub mod graphic_config;
+pub mod headless;
+mod lut;
+mod output;
+mod recorder;
 mod scene;
+mod snapshot;
+mod spectrum;
+mod window;
 
 use crate::gl_engine::gl_program::GlProgram;
-use controls::Controls;
+use crate::gl_engine::texture_util::TextureUtil;
+use controls::{Controls, Message};
 use graphic_config::GraphicConfig;
 use media_handler::frame::Frame;
+use media_handler::glitch::GlitchConfig;
+use media_handler::session::{Entry, Event, Session, CUT_STREAM, LAYOUT_STREAM};
+use media_handler::MediaCommand;
+use output::Output;
+use recorder::Recorder;
+use snapshot::{Filters, Snapshot};
 
+use std::collections::VecDeque;
 use std::sync::mpsc::{Receiver, Sender};
 use std::time::Instant;
 
-use glow::*;
 use iced_glow::*;
 
 use iced_glutin::glutin;
@@ -20,148 +37,467 @@ use iced_glutin::*;
 pub struct GraphicContext {
     config: GraphicConfig,
 
-    gl: Context,
-    windowed_context: glutin::ContextWrapper<glutin::PossiblyCurrent, glutin::window::Window>,
-    event_loop: glutin::event_loop::EventLoop<()>,
+    // main window first, the controls are drawn on it
+    outputs: Vec<Output>,
+    event_loop: Option<glutin::event_loop::EventLoop<()>>,
 
     viewport: Viewport,
-    program: GlProgram,
     renderer: iced_glow::Renderer,
 
     state: program::State<Controls>,
     cursor_position: glutin::dpi::PhysicalPosition<f64>,
     modifiers: glutin::event::ModifiersState,
     clipboard: Clipboard,
-    resized: bool,
+    recorder: Recorder,
+    // seconds of the audio source played, muxed in the recordings, none without audio
+    audio_played: Option<f64>,
+    snapshot_requested: bool,
+    // glitch config of the media side, described in the snapshots
+    glitch: GlitchConfig,
+    session: Session,
+    // lut intensity, follow palette and background last written to the session log
+    logged_controls: (f32, bool, [f32; 4]),
     debug: Debug,
 }
 
 impl GraphicContext {
-    pub fn new(config: GraphicConfig) -> Self {
+    pub fn new(config: GraphicConfig, glitch: GlitchConfig, session: Session) -> Self {
         env_logger::init();
 
         let event_loop = glutin::event_loop::EventLoop::new();
 
-        let (gl, windowed_context) = {
-            // TODO https://github.com/rust-windowing/winit/blob/master/examples/fullscreen.rs
-            let wb = glutin::window::WindowBuilder::new()
-                .with_title(&config.app_name)
-                .with_inner_size(glutin::dpi::LogicalSize::new(config.width, config.height));
-
-            let windowed_context = glutin::ContextBuilder::new()
-                .with_vsync(true)
-                .build_windowed(wb, &event_loop)
-                .unwrap();
-
-            unsafe {
-                let windowed_context = windowed_context.make_current().unwrap();
-
-                let gl = glow::Context::from_loader_function(|s| {
-                    windowed_context.get_proc_address(s) as *const _
-                });
-
-                // Enable auto-conversion from/to sRGB
-                gl.enable(glow::FRAMEBUFFER_SRGB);
-
-                // Enable alpha blending
-                gl.enable(glow::BLEND);
-                gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
-
-                // Disable multisampling by default
-                gl.disable(glow::MULTISAMPLE);
-
-                gl.enable(glow::DEPTH_TEST);
-                (gl, windowed_context)
-            }
-        };
+        let mut outputs: Vec<Output> = vec![];
+        for (i, window_config) in config.windows().into_iter().enumerate() {
+            let shared = outputs.first().map(|o| &**o.context());
+            let rng = session.rng(LAYOUT_STREAM + i as u64);
+            let cut_rng = session.rng(CUT_STREAM + i as u64);
+            let output = Output::new(&event_loop, &config, window_config, shared, rng, cut_rng);
+            outputs.push(output);
+        }
+        // iced is drawn with the main context
+        outputs[0].make_current();
+        let main = &outputs[0];
 
-        let physical_size = windowed_context.window().inner_size();
+        let physical_size = main.size();
         let viewport = Viewport::with_physical_size(
             Size::new(physical_size.width, physical_size.height),
-            windowed_context.window().scale_factor(),
+            main.window().scale_factor(),
         );
 
         let mut debug = Debug::new();
-        let controls = Controls::new();
+        let controls = Controls::new(config.lut_intensity, config.record.start);
         let modifiers = glutin::event::ModifiersState::default();
-        let program = GlProgram::new(
-            &gl,
-            &config,
-            (physical_size.width as i32, physical_size.height as i32),
-        );
         let cursor_position = glutin::dpi::PhysicalPosition::new(-1.0, -1.0);
-        let clipboard = Clipboard::connect(windowed_context.window());
+        let clipboard = Clipboard::connect(main.window());
         let mut renderer =
-            iced_glow::Renderer::new(Backend::new(&gl, iced_glow::Settings::default()));
+            iced_glow::Renderer::new(Backend::new(&main.gl, iced_glow::Settings::default()));
         let state =
             program::State::new(controls, viewport.logical_size(), &mut renderer, &mut debug);
 
         Self {
-            config,
-            gl,
-            windowed_context,
-            event_loop,
+            outputs,
+            event_loop: Some(event_loop),
             viewport,
-            program,
             renderer,
             state,
             cursor_position,
             modifiers,
             clipboard,
-            resized: false,
+            recorder: Recorder::default(),
+            audio_played: None,
+            snapshot_requested: false,
+            glitch,
+            logged_controls: (config.lut_intensity, false, Color::BLACK.into_linear()),
+            session,
+            config,
             debug,
         }
     }
 
+    fn output_index(&self, window_id: glutin::window::WindowId) -> Option<usize> {
+        self.outputs
+            .iter()
+            .position(|o| o.window().id() == window_id)
+    }
+
+    fn media_outputs(&self) -> usize {
+        // when spanning only the main window render the scene
+        if self.config.span.enabled {
+            1
+        } else {
+            self.outputs.len()
+        }
+    }
+
+    fn span_canvas(&mut self) -> Option<(i32, i32)> {
+        if !self.config.span.enabled {
+            return None;
+        }
+        let sizes: Vec<(u32, u32)> = self.outputs.iter().map(|o| o.size().into()).collect();
+        let (canvas, rects) = window::span_layout(&sizes, self.config.span.bezel);
+        for (o, rect) in self.outputs.iter_mut().zip(rects) {
+            o.span_rect = Some(rect);
+        }
+        Some(canvas)
+    }
+
+    fn record(&mut self) {
+        /*
+            Follow the controls toggle and read back the main window,
+            before the controls are drawn on it
+        */
+        let size = self.outputs[0].size();
+        self.recorder.displayed();
+        let recording = self.state.program().recording;
+        let fps = self.config.record.fps.or(self.recorder.display_fps());
+        if recording != self.recorder.is_recording() {
+            match (recording, fps) {
+                (false, _) => self.recorder.stop(),
+                // without a configured frame rate, wait for the display one to be measured
+                (true, None) => (),
+                (true, Some(fps)) => {
+                    let audio = self.config.audio.as_ref().zip(self.audio_played);
+                    if !self
+                        .recorder
+                        .start(&self.config.record, size.into(), fps, audio)
+                    {
+                        self.state.queue_message(Message::RecordingToggled(false));
+                    }
+                }
+            }
+        }
+        if self.recorder.wants_frame() {
+            let frame = GlProgram::read_framebuffer(&self.outputs[0].gl, None, size.into());
+            self.recorder.push(frame);
+            // a failed frame end the recording
+            if !self.recorder.is_recording() {
+                self.state.queue_message(Message::RecordingToggled(false));
+            }
+        }
+    }
+
+    fn snapshot(&mut self) {
+        // main window without the controls, with the media that compose it
+        self.snapshot_requested = false;
+        let main = &self.outputs[0];
+        let size = main.size().into();
+        let frame = GlProgram::read_framebuffer(&main.gl, None, size);
+        let filters = Filters::new(
+            &self.config,
+            &self.glitch,
+            main.program.grading.current(),
+            self.state.program().lut_intensity,
+            main.program.crt_renderer.as_ref().map(|r| r.jump_cut.speed),
+        );
+        let media = main.program.placements.iter().cloned().collect();
+        Snapshot::new(&self.config, self.session.seed, size, filters, media).save(frame);
+    }
+
+    fn log_controls(&mut self) {
+        // parameter changes of the controls, for the replay
+        let controls = self.state.program();
+        let (lut_intensity, follow_palette, background) = self.logged_controls;
+        if controls.lut_intensity != lut_intensity {
+            self.session.log(Event::Param {
+                name: "lut_intensity".to_string(),
+                value: controls.lut_intensity,
+            });
+        }
+        if controls.follow_palette != follow_palette {
+            self.session.log(Event::Param {
+                name: "follow_palette".to_string(),
+                value: controls.follow_palette as u8 as f32,
+            });
+        }
+        let color = controls.background_color.into_linear();
+        if color != background {
+            self.session.log(Event::Background { color });
+        }
+        self.logged_controls = (controls.lut_intensity, controls.follow_palette, color);
+    }
+
+    fn next_media(&mut self, count: u8, tx: &Sender<u8>) {
+        // every window showing its own media consume a batch
+        let media_outputs = self.media_outputs();
+        for o in self.outputs.iter_mut().take(media_outputs) {
+            o.next_media = true;
+        }
+        self.session.log(Event::Tick { count });
+        // a clip replace the media, none is asked
+        if count > 0 {
+            // the replay media handler stop at the end of the log
+            tx.send(count).ok();
+        }
+    }
+
+    fn replay(&mut self, entries: &mut VecDeque<Entry>, tx: &Sender<u8>) {
+        /*
+            Apply the logged events that are due,
+            at the pace they were recorded
+        */
+        while entries
+            .front()
+            .is_some_and(|e| e.t <= self.session.elapsed())
+        {
+            match entries.pop_front().unwrap().event {
+                Event::Tick { count } => self.next_media(count, tx),
+                Event::Param { name, value } => match name.as_str() {
+                    "lut_intensity" => self
+                        .state
+                        .queue_message(Message::LutIntensityChanged(value)),
+                    "follow_palette" => self
+                        .state
+                        .queue_message(Message::FollowPaletteToggled(value != 0.)),
+                    _ => println!("Unknown parameter in the session log: {}", name),
+                },
+                Event::Background { color } => self
+                    .state
+                    .queue_message(Message::BackgroundColorChanged(Color::from(color))),
+                Event::Audio { energies } => {
+                    for o in &mut self.outputs {
+                        o.program.strip.spectrum.update(&energies);
+                    }
+                }
+                // the logged media already follow the commands
+                Event::Start { .. } | Event::Media { .. } | Event::Command { .. } => (),
+            }
+        }
+    }
+
+    fn redraw(&mut self, i: usize, rx: &Receiver<Frame>) {
+        self.outputs[i].make_current();
+        if self.outputs[i].resized {
+            let canvas = self.span_canvas();
+            // only the main window render the canvas, the others show a part of it
+            let canvas = canvas.filter(|_| i == 0);
+            self.outputs[i].resize(&self.config, canvas);
+        }
+
+        if i == 0 && self.state.program().follow_palette {
+            let [r, g, b] = self.outputs[0].program.palette.dominant();
+            let c = Color::from_rgb8(r, g, b);
+            if c != self.state.program().background_color {
+                self.state.queue_message(Message::BackgroundColorChanged(c));
+            }
+        }
+
+        let spanned = self.config.span.enabled && i > 0;
+        let main_frame = {
+            let main = &self.outputs[0].program;
+            (main.shown_texture(self.config.mode), main.grading.current())
+        };
+        let canvas_ratio = match self.outputs[0].span_rect {
+            Some([_, _, w, h]) if self.config.span.enabled => {
+                let size = self.outputs[0].size();
+                (size.width as f32 / w) / (size.height as f32 / h)
+            }
+            _ => {
+                let size = self.outputs[i].size();
+                size.width as f32 / size.height as f32
+            }
+        };
+
+        let output = &mut self.outputs[i];
+        output
+            .program
+            .framebuffer_renderer
+            .set_lut_intensity(self.state.program().lut_intensity);
+        output.clear(self.state.program().background_color);
+        if spanned {
+            // show the main window scene, shared between the contexts
+            let (texture, grading) = main_frame;
+            let fbo = &mut output.program.framebuffer_renderer;
+            fbo.grading = grading;
+            fbo.draw_texture(&output.gl, texture);
+        } else {
+            output.draw(rx, canvas_ratio, &self.config);
+        }
+
+        if i == 0 {
+            self.record();
+            if self.snapshot_requested {
+                self.snapshot();
+            }
+
+            // And then iced on top
+            let gl = &self.outputs[0].gl;
+            self.renderer.with_primitives(|backend, primitive| {
+                backend.present(gl, primitive, &self.viewport, &self.debug.overlay());
+            });
+
+            // Update the mouse cursor
+            self.outputs[0]
+                .window()
+                .set_cursor_icon(iced_winit::conversion::mouse_interaction(
+                    self.state.mouse_interaction(),
+                ));
+        }
+        self.outputs[i].context().swap_buffers().unwrap();
+    }
+
     pub fn launch_graphic(
         mut self,
         // mut media_handler: MediaHandler,
         tx: Sender<u8>,
         rx: Receiver<Frame>,
+        // mono audio sample windows, feed the spectrum bands
+        rx_audio: Option<Receiver<Vec<f32>>>,
+        // events of a session log, replayed instead of the timer and the inputs
+        replay: Option<Vec<Entry>>,
+        // ratings of the media on screen and audio level, none when replaying
+        tx_command: Option<Sender<MediaCommand>>,
     ) {
-        let mut need_clear: u8 = 1;
-        let mut next_media = false;
         let mut current_time = Instant::now();
-        let mut viewport_size = self.windowed_context.window().inner_size();
-        let mut viewport_ratio = viewport_size.width as f32 / viewport_size.height as f32;
+        let mut replay = replay.map(VecDeque::from);
+        self.audio_played = rx_audio.as_ref().map(|_| 0.);
+        let sample_rate = self.config.frequency_strip.sample_rate as f64;
+        let event_loop = self.event_loop.take().unwrap();
 
-        self.event_loop.run(move |event, _, control_flow| {
+        event_loop.run(move |event, _, control_flow| {
             *control_flow = glutin::event_loop::ControlFlow::Poll;
 
             match event {
-                glutin::event::Event::WindowEvent { event, .. } => {
+                glutin::event::Event::WindowEvent { window_id, event } => {
+                    let i = match self.output_index(window_id) {
+                        Some(i) => i,
+                        None => return,
+                    };
                     match event {
                         glutin::event::WindowEvent::CursorMoved { position, .. } => {
-                            self.cursor_position = position;
+                            if i == 0 {
+                                self.cursor_position = position;
+                            }
+                            self.outputs[i].cursor_moved();
+                        }
+                        glutin::event::WindowEvent::KeyboardInput {
+                            input:
+                                glutin::event::KeyboardInput {
+                                    virtual_keycode: Some(glutin::event::VirtualKeyCode::F11),
+                                    state: glutin::event::ElementState::Pressed,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            window::toggle_fullscreen(
+                                self.outputs[i].window(),
+                                self.config.windows()[i],
+                            );
+                        }
+                        glutin::event::WindowEvent::KeyboardInput {
+                            input:
+                                glutin::event::KeyboardInput {
+                                    virtual_keycode: Some(glutin::event::VirtualKeyCode::F9),
+                                    state: glutin::event::ElementState::Pressed,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            let recording = self.state.program().recording;
+                            self.state
+                                .queue_message(Message::RecordingToggled(!recording));
+                        }
+                        glutin::event::WindowEvent::KeyboardInput {
+                            input:
+                                glutin::event::KeyboardInput {
+                                    virtual_keycode:
+                                        Some(
+                                            key @ (glutin::event::VirtualKeyCode::L
+                                            | glutin::event::VirtualKeyCode::D),
+                                        ),
+                                    state: glutin::event::ElementState::Pressed,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            let rating = if key == glutin::event::VirtualKeyCode::L {
+                                1
+                            } else {
+                                -1
+                            };
+                            self.state.queue_message(Message::Rated(Some(rating)));
+                        }
+                        glutin::event::WindowEvent::KeyboardInput {
+                            input:
+                                glutin::event::KeyboardInput {
+                                    virtual_keycode: Some(glutin::event::VirtualKeyCode::F12),
+                                    state: glutin::event::ElementState::Pressed,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            self.snapshot_requested = true;
                         }
                         glutin::event::WindowEvent::ModifiersChanged(new_modifiers) => {
                             self.modifiers = new_modifiers;
                         }
                         glutin::event::WindowEvent::Resized(physical_size) => {
-                            self.viewport = Viewport::with_physical_size(
-                                Size::new(physical_size.width, physical_size.height),
-                                self.windowed_context.window().scale_factor(),
-                            );
-                            need_clear = 2;
-                            self.resized = true;
+                            if i == 0 {
+                                self.viewport = Viewport::with_physical_size(
+                                    Size::new(physical_size.width, physical_size.height),
+                                    self.outputs[0].window().scale_factor(),
+                                );
+                            }
+                            // the spanned canvas change with any of its windows
+                            for (j, o) in self.outputs.iter_mut().enumerate() {
+                                if j == i || self.config.span.enabled {
+                                    o.resized = true;
+                                }
+                            }
                         }
                         glutin::event::WindowEvent::CloseRequested => {
-                            self.program.cleanup(&self.gl);
+                            self.recorder.stop();
+                            for o in &mut self.outputs {
+                                o.cleanup();
+                            }
                             *control_flow = glutin::event_loop::ControlFlow::Exit
                         }
                         _ => (),
                     }
 
-                    // Map window event to iced event
+                    // Map window event to iced event, the controls live in the main window
+                    if i != 0 {
+                        return;
+                    }
                     if let Some(event) = iced_winit::conversion::window_event(
                         &event,
-                        self.windowed_context.window().scale_factor(),
+                        self.outputs[0].window().scale_factor(),
                         self.modifiers,
                     ) {
                         self.state.queue_event(event);
                     }
                 }
                 glutin::event::Event::MainEventsCleared => {
+                    for o in &mut self.outputs {
+                        o.update_cursor();
+                    }
+                    if let Some(entries) = &mut replay {
+                        self.replay(entries, &tx);
+                    } else if let Some(rx_audio) = &rx_audio {
+                        let mut heard = false;
+                        while let Ok(samples) = rx_audio.try_recv() {
+                            heard = true;
+                            if let Some(played) = &mut self.audio_played {
+                                *played += samples.len() as f64 / sample_rate;
+                            }
+                            let energies = self.outputs[0]
+                                .program
+                                .strip
+                                .spectrum
+                                .band_energies(&samples);
+                            self.session.log(Event::Audio {
+                                energies: energies.clone(),
+                            });
+                            for o in &mut self.outputs {
+                                o.program.strip.spectrum.update(&energies);
+                            }
+                        }
+                        // the audio raise the glitch of the next media
+                        if let (true, Some(tx_command)) = (heard, &tx_command) {
+                            let level = self.outputs[0].program.strip.spectrum.level();
+                            tx_command.send(MediaCommand::AudioLevel(level)).ok();
+                        }
+                    }
+
                     // If there are events pending
                     if !self.state.is_queue_empty() {
                         self.state.update(
@@ -178,54 +514,32 @@ impl GraphicContext {
                             &mut self.clipboard,
                             &mut self.debug,
                         );
+                        self.log_controls();
+                        if let Some(rating) = self.state.program().rating {
+                            if let Some(tx_command) = &tx_command {
+                                tx_command.send(MediaCommand::Rate(rating)).ok();
+                            }
+                            self.state.queue_message(Message::Rated(None));
+                        }
                     }
 
-                    if current_time.elapsed().as_millis() > self.config.fps {
+                    if replay.is_none()
+                        && current_time.elapsed().as_millis()
+                            > self.outputs[0].program.frame_interval(&self.config)
+                    {
                         println!("fps: {}", 1000 / current_time.elapsed().as_millis());
                         current_time = Instant::now();
-                        next_media = true;
-                        tx.send(self.config.renderer_size).unwrap();
+                        let count = self.config.media_per_tick() * self.media_outputs() as u8;
+                        self.next_media(count, &tx);
                     }
-                    self.windowed_context.window().request_redraw();
-                }
-                glutin::event::Event::RedrawRequested(_) => {
-                    if self.resized {
-                        viewport_size = self.windowed_context.window().inner_size();
-                        viewport_ratio = viewport_size.width as f32 / viewport_size.height as f32;
-                        unsafe {
-                            self.gl.viewport(
-                                0,
-                                0,
-                                viewport_size.width as i32,
-                                viewport_size.height as i32,
-                            );
-                        }
-                        self.program
-                            .resize_buffer(&self.gl, viewport_size.into(), &self.config);
-                        self.resized = false;
-                        need_clear = 2;
+                    for o in &self.outputs {
+                        o.window().request_redraw();
                     }
-
-                    // double buffer need 2 clear
-                    let p = self.state.program().background_color;
-                    if need_clear > 0 || p != self.program.framebuffer_renderer.bg_color {
-                        self.program.framebuffer_renderer.bg_color = p;
-                        self.program.clear(&self.gl);
-                        need_clear -= 1;
+                }
+                glutin::event::Event::RedrawRequested(window_id) => {
+                    if let Some(i) = self.output_index(window_id) {
+                        self.redraw(i, &rx);
                     }
-                    self.program.draw(&self.gl, &rx, next_media, viewport_ratio);
-                    next_media = false;
-
-                    // And then iced on top
-                    self.renderer.with_primitives(|backend, primitive| {
-                        backend.present(&self.gl, primitive, &self.viewport, &self.debug.overlay());
-                    });
-
-                    // Update the mouse cursor
-                    self.windowed_context.window().set_cursor_icon(
-                        iced_winit::conversion::mouse_interaction(self.state.mouse_interaction()),
-                    );
-                    self.windowed_context.swap_buffers().unwrap();
                 }
                 _ => (),
             }
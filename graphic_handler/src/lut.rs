@@ -0,0 +1,249 @@
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3D {
+    pub title: String,
+    // number of samples on each axis
+    pub size: usize,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    // size^3 output colors, red index varying the fastest
+    pub data: Vec<[f32; 3]>,
+}
+
+impl Lut3D {
+    fn parse_triplet(values: &[&str], line: usize) -> Result<[f32; 3], String> {
+        if values.len() != 3 {
+            return Err(format!("line {}: expected 3 values", line));
+        }
+        let mut triplet = [0.; 3];
+        for (i, v) in values.iter().enumerate() {
+            triplet[i] = v
+                .parse()
+                .map_err(|_| format!("line {}: invalid number '{}'", line, v))?;
+        }
+        Ok(triplet)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        /*
+            Adobe .cube format:
+                TITLE "name"
+                LUT_3D_SIZE N
+                DOMAIN_MIN r g b (optional, 0 0 0)
+                DOMAIN_MAX r g b (optional, 1 1 1)
+                LUT_3D_INPUT_RANGE min max (optional, the same domain for the 3 channels)
+                N^3 lines of "r g b"
+        */
+        let mut lut = Self {
+            title: String::new(),
+            size: 0,
+            domain_min: [0., 0., 0.],
+            domain_max: [1., 1., 1.],
+            data: vec![],
+        };
+
+        for (i, raw) in content.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens[0] {
+                "TITLE" => lut.title = line[5..].trim().trim_matches('"').to_string(),
+                "LUT_3D_SIZE" => {
+                    lut.size = tokens
+                        .get(1)
+                        .and_then(|s| s.parse().ok())
+                        .ok_or(format!("line {}: invalid LUT_3D_SIZE", i + 1))?
+                }
+                "DOMAIN_MIN" => lut.domain_min = Self::parse_triplet(&tokens[1..], i + 1)?,
+                "DOMAIN_MAX" => lut.domain_max = Self::parse_triplet(&tokens[1..], i + 1)?,
+                "LUT_3D_INPUT_RANGE" => {
+                    let range = match tokens[1..] {
+                        [min, max] => min.parse().ok().zip(max.parse().ok()),
+                        _ => None,
+                    };
+                    let (min, max) =
+                        range.ok_or(format!("line {}: invalid LUT_3D_INPUT_RANGE", i + 1))?;
+                    lut.domain_min = [min; 3];
+                    lut.domain_max = [max; 3];
+                }
+                "LUT_1D_SIZE" => return Err(String::from("1D LUT are not supported")),
+                _ => lut.data.push(Self::parse_triplet(&tokens, i + 1)?),
+            }
+        }
+
+        if lut.size < 2 {
+            return Err(String::from("missing or invalid LUT_3D_SIZE"));
+        }
+        if (0..3).any(|c| lut.domain_max[c] <= lut.domain_min[c]) {
+            return Err(String::from("empty input domain"));
+        }
+        if lut.data.len() != lut.size.pow(3) {
+            return Err(format!(
+                "expected {} colors, found {}",
+                lut.size.pow(3),
+                lut.data.len()
+            ));
+        }
+        Ok(lut)
+    }
+
+    pub fn load(path: &PathBuf) -> Self {
+        let content = fs::read_to_string(path).expect("Unable to read LUT file");
+        Self::parse(&content).unwrap_or_else(|e| panic!("Invalid LUT {:?}: {}", path, e))
+    }
+
+    fn at(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.data[r + g * self.size + b * self.size * self.size]
+    }
+
+    pub fn sample(&self, color: [f32; 3]) -> [f32; 3] {
+        /*
+            CPU reference of the shader lookup: trilinear interpolation
+            between the 8 surrounding samples
+        */
+        let max = (self.size - 1) as f32;
+        let mut base = [0; 3];
+        let mut frac = [0.; 3];
+        for c in 0..3 {
+            let range = self.domain_max[c] - self.domain_min[c];
+            let x = ((color[c] - self.domain_min[c]) / range).clamp(0., 1.) * max;
+            base[c] = (x.floor() as usize).min(self.size - 2);
+            frac[c] = x - base[c] as f32;
+        }
+
+        let mut out = [0.; 3];
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let weight: f32 = (0..3)
+                .map(|c| {
+                    if offset[c] == 1 {
+                        frac[c]
+                    } else {
+                        1. - frac[c]
+                    }
+                })
+                .product();
+            let v = self.at(
+                base[0] + offset[0],
+                base[1] + offset[1],
+                base[2] + offset[2],
+            );
+            for c in 0..3 {
+                out[c] += v[c] * weight;
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // output = input, red varying the fastest
+    const IDENTITY: &str = "TITLE \"identity\"
+# comment
+LUT_3D_SIZE 2
+
+0 0 0
+1 0 0
+0 1 0
+1 1 0
+0 0 1
+1 0 1
+0 1 1
+1 1 1
+";
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for c in 0..3 {
+            assert!((a[c] - b[c]).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn parse_identity() {
+        let lut = Lut3D::parse(IDENTITY).unwrap();
+        assert_eq!(lut.title, "identity");
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.domain_min, [0., 0., 0.]);
+        assert_eq!(lut.domain_max, [1., 1., 1.]);
+        assert_eq!(lut.data[1], [1., 0., 0.]);
+        assert_eq!(lut.data[6], [0., 1., 1.]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let without = |line: &str| IDENTITY.replace(line, "");
+        assert!(Lut3D::parse(&without("LUT_3D_SIZE 2")).is_err());
+        assert!(Lut3D::parse(&without("1 1 1\n")).is_err());
+        assert!(Lut3D::parse(&IDENTITY.replace("0 1 1", "0 one 1")).is_err());
+        assert!(Lut3D::parse(&IDENTITY.replace("0 1 1", "0 1")).is_err());
+        assert!(Lut3D::parse(&IDENTITY.replace("LUT_3D_SIZE 2", "LUT_1D_SIZE 2")).is_err());
+    }
+
+    #[test]
+    fn sample_identity() {
+        let lut = Lut3D::parse(IDENTITY).unwrap();
+        for color in [
+            [0., 0., 0.],
+            [1., 1., 1.],
+            [0.25, 0.5, 0.75],
+            [0.9, 0.1, 0.3],
+        ] {
+            assert_close(lut.sample(color), color);
+        }
+        // out of the domain values are clamped
+        assert_close(lut.sample([-1., 0.5, 2.]), [0., 0.5, 1.]);
+    }
+
+    #[test]
+    fn sample_interpolate() {
+        // inverted colors, 3 samples per axis
+        let mut content = String::from("LUT_3D_SIZE 3\n");
+        for b in 0..3 {
+            for g in 0..3 {
+                for r in 0..3 {
+                    let inv = |v: i32| 1. - v as f32 / 2.;
+                    content += &format!("{} {} {}\n", inv(r), inv(g), inv(b));
+                }
+            }
+        }
+        let lut = Lut3D::parse(&content).unwrap();
+        assert_close(lut.sample([0., 0.5, 1.]), [1., 0.5, 0.]);
+        assert_close(lut.sample([0.2, 0.6, 0.95]), [0.8, 0.4, 0.05]);
+    }
+
+    #[test]
+    fn sample_domain() {
+        let lut = Lut3D::parse(&IDENTITY.replace(
+            "LUT_3D_SIZE 2",
+            "LUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 4",
+        ))
+        .unwrap();
+        assert_eq!(lut.domain_max, [2., 2., 4.]);
+        // the input is remapped to the domain before the lookup
+        assert_close(lut.sample([1., 2., 1.]), [0.5, 1., 0.25]);
+    }
+
+    #[test]
+    fn parse_input_range() {
+        let with = |range: &str| {
+            Lut3D::parse(&IDENTITY.replace("LUT_3D_SIZE 2", &format!("LUT_3D_SIZE 2\n{}", range)))
+        };
+        let lut = with("LUT_3D_INPUT_RANGE -0.5 1.5").unwrap();
+        assert_eq!(lut.domain_min, [-0.5; 3]);
+        assert_eq!(lut.domain_max, [1.5; 3]);
+        // fed into the same remap as the domain
+        assert_close(lut.sample([0.5, 1.5, 0.]), [0.5, 1., 0.25]);
+
+        assert!(with("LUT_3D_INPUT_RANGE 0").is_err());
+        assert!(with("LUT_3D_INPUT_RANGE 0 high").is_err());
+        assert!(with("LUT_3D_INPUT_RANGE 1 1").is_err());
+        assert!(with("DOMAIN_MIN 0 2 0").is_err());
+    }
+}
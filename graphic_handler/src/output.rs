@@ -0,0 +1,164 @@
+use std::sync::mpsc::Receiver;
+
+use glow::*;
+use iced_glow::glow;
+use iced_glow::Color;
+
+use iced_glutin::glutin;
+use iced_glutin::glutin::dpi::PhysicalSize;
+use iced_glutin::glutin::event_loop::EventLoop;
+use iced_glutin::glutin::window::Window;
+use iced_glutin::glutin::{ContextWrapper, PossiblyCurrent};
+
+use crate::gl_engine::framebuffer_renderer::FULL_RECT;
+use crate::gl_engine::gl_program::GlProgram;
+use crate::graphic_config::{GraphicConfig, WindowConfig};
+use crate::window::{self, CursorHider};
+use media_handler::frame::Frame;
+use rand::rngs::StdRng;
+
+pub struct Output {
+    pub gl: Context,
+    // taken and put back each time the context is made current
+    context: Option<ContextWrapper<PossiblyCurrent, Window>>,
+    pub program: GlProgram,
+    pub cursor_hider: CursorHider,
+    pub resized: bool,
+    pub need_clear: u8,
+    pub next_media: bool,
+    // part of the spanned canvas shown by this window
+    pub span_rect: Option<[f32; 4]>,
+}
+
+impl Output {
+    pub fn new(
+        event_loop: &EventLoop<()>,
+        config: &GraphicConfig,
+        window_config: &WindowConfig,
+        shared: Option<&glutin::Context<PossiblyCurrent>>,
+        rng: StdRng,
+        cut_rng: StdRng,
+    ) -> Self {
+        /*
+            Extra windows share the objects of the main context,
+            so they can show its framebuffer texture when spanning
+            Only the main window wait for the vsync
+        */
+        let wb = window::builder(event_loop, config, window_config);
+        let context = match shared {
+            Some(s) => glutin::ContextBuilder::new()
+                .with_shared_lists(s)
+                .build_windowed(wb, event_loop),
+            None => glutin::ContextBuilder::new()
+                .with_vsync(true)
+                .build_windowed(wb, event_loop),
+        }
+        .unwrap();
+
+        let (gl, context) = unsafe {
+            let context = context.make_current().unwrap();
+
+            let gl =
+                glow::Context::from_loader_function(|s| context.get_proc_address(s) as *const _);
+
+            // Enable auto-conversion from/to sRGB
+            gl.enable(glow::FRAMEBUFFER_SRGB);
+
+            // Enable alpha blending
+            gl.enable(glow::BLEND);
+            gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
+
+            // Disable multisampling by default
+            gl.disable(glow::MULTISAMPLE);
+
+            gl.enable(glow::DEPTH_TEST);
+            (gl, context)
+        };
+
+        let size = context.window().inner_size();
+        let program = GlProgram::new(
+            &gl,
+            config,
+            (size.width as i32, size.height as i32),
+            rng,
+            cut_rng,
+        );
+        Self {
+            gl,
+            context: Some(context),
+            program,
+            cursor_hider: CursorHider::new(window_config.hide_cursor_after),
+            // first render init the program buffers
+            resized: true,
+            need_clear: 1,
+            next_media: false,
+            span_rect: None,
+        }
+    }
+
+    pub fn context(&self) -> &ContextWrapper<PossiblyCurrent, Window> {
+        self.context.as_ref().unwrap()
+    }
+
+    pub fn window(&self) -> &Window {
+        self.context().window()
+    }
+
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.window().inner_size()
+    }
+
+    pub fn cursor_moved(&mut self) {
+        self.cursor_hider
+            .moved(self.context.as_ref().unwrap().window());
+    }
+
+    pub fn update_cursor(&mut self) {
+        self.cursor_hider
+            .update(self.context.as_ref().unwrap().window());
+    }
+
+    pub fn make_current(&mut self) {
+        let context = self.context.take().unwrap();
+        let context = unsafe { context.make_current().map_err(|(_, e)| e).unwrap() };
+        self.context = Some(context);
+    }
+
+    pub fn resize(&mut self, config: &GraphicConfig, canvas_size: Option<(i32, i32)>) {
+        // the scene is rendered at the size of the spanned canvas if any
+        let size = self.size();
+        let screen_size = (size.width as i32, size.height as i32);
+        unsafe {
+            self.gl.viewport(0, 0, screen_size.0, screen_size.1);
+        }
+        self.program.resize_buffer(
+            &self.gl,
+            canvas_size.unwrap_or(screen_size),
+            screen_size,
+            config,
+        );
+        self.program.framebuffer_renderer.uv_rect = self.span_rect.unwrap_or(FULL_RECT);
+        self.resized = false;
+        self.need_clear = 2;
+    }
+
+    pub fn clear(&mut self, background_color: Color) {
+        // double buffer need 2 clear
+        if self.need_clear > 0 || background_color != self.program.framebuffer_renderer.bg_color {
+            self.program.framebuffer_renderer.bg_color = background_color;
+            self.program.clear(&self.gl);
+            self.need_clear = self.need_clear.saturating_sub(1);
+        }
+    }
+
+    pub fn draw(&mut self, rx: &Receiver<Frame>, viewport_ratio: f32, config: &GraphicConfig) {
+        self.program
+            .draw(&self.gl, rx, self.next_media, viewport_ratio, config);
+        self.next_media = false;
+    }
+
+    pub fn cleanup(&mut self) {
+        self.make_current();
+        self.program.cleanup(&self.gl);
+    }
+}
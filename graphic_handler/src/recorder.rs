@@ -0,0 +1,319 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use chrono::Local;
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+
+use crate::graphic_config::{AudioSource, RecordConfig, RecordFormat};
+
+// displayed frames measuring the display frame rate
+const MEASURED_FRAMES: usize = 30;
+// frames waiting to be written, the display slows down past it
+const QUEUE_SIZE: usize = 16;
+
+fn audio_args(config: &RecordConfig, playing: Option<(&AudioSource, f64)>) -> Vec<OsString> {
+    /*
+        ffmpeg input of the audio muxed in the video: the configured file,
+        or else the audio source playing, a file from the seconds already played
+        The audio stop the video when shorter
+    */
+    let mut args: Vec<OsString> = vec![];
+    match (&config.audio, playing) {
+        (Some(file), _) => args.extend(["-i".into(), file.into()]),
+        (None, Some((AudioSource::File(path), played))) => {
+            // the file loop like it is played, cut where the listening is
+            args.extend(["-stream_loop", "-1", "-i"].map(OsString::from));
+            args.push(path.into());
+            args.push("-af".into());
+            args.push(format!("atrim=start={:.3},asetpts=PTS-STARTPTS", played).into());
+        }
+        (None, Some((AudioSource::Capture { format, device }, _))) => {
+            args.extend(["-f", format.as_str(), "-i", device.as_str()].map(OsString::from))
+        }
+        (None, None) => return args,
+    }
+    args.extend(["-c:a", "aac", "-shortest"].map(OsString::from));
+    args
+}
+
+enum Sink {
+    Png(PathBuf),
+    Video(Child),
+}
+
+impl Sink {
+    fn new(
+        config: &RecordConfig,
+        size: (u32, u32),
+        fps: u32,
+        audio: Option<(&AudioSource, f64)>,
+    ) -> Option<Self> {
+        // milliseconds so two recordings of the same second don't overwrite each other
+        let name = Local::now()
+            .format("record_%Y_%m_%d_%H_%M_%S_%3f")
+            .to_string();
+        fs::create_dir_all(&config.output).expect("Unable to create the record folder");
+
+        match config.format {
+            RecordFormat::PngSequence => {
+                let folder = config.output.join(&name);
+                fs::create_dir_all(&folder).expect("Unable to create the record folder");
+                Some(Self::Png(folder))
+            }
+            RecordFormat::Video => {
+                // raw RGBA frames piped to ffmpeg, padded to even sizes for yuv420p
+                let mut command = Command::new("ffmpeg");
+                command
+                    .args(["-y", "-loglevel", "error", "-f", "rawvideo"])
+                    .args(["-pixel_format", "rgba"])
+                    .args(["-video_size", &format!("{}x{}", size.0, size.1)])
+                    .args(["-framerate", &fps.to_string(), "-i", "-"])
+                    .args(audio_args(config, audio))
+                    .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+                    .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+                    .arg(config.output.join(format!("{}.mp4", name)))
+                    .stdin(Stdio::piped());
+                match command.spawn() {
+                    Ok(child) => Some(Self::Video(child)),
+                    Err(e) => {
+                        println!("Unable to start ffmpeg: {}", e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, frame: &RgbaImage, index: u64) -> Result<(), String> {
+        match self {
+            Self::Png(folder) => frame
+                .save(folder.join(format!("frame_{:06}.png", index)))
+                .map_err(|e| format!("Unable to write the record frame: {}", e)),
+            Self::Video(child) => child
+                .stdin
+                .as_mut()
+                .unwrap()
+                .write_all(frame.as_raw())
+                .map_err(|e| format!("ffmpeg stopped during the recording: {}", e)),
+        }
+    }
+
+    fn finish(self) {
+        if let Self::Video(mut child) = self {
+            // closing the input end the video
+            drop(child.stdin.take());
+            match child.wait() {
+                Ok(status) if !status.success() => println!("ffmpeg failed: {}", status),
+                Ok(_) => (),
+                Err(e) => println!("ffmpeg didn't stop: {}", e),
+            }
+        }
+    }
+}
+
+struct Recording {
+    tx: SyncSender<RgbaImage>,
+    writer: JoinHandle<()>,
+    size: (u32, u32),
+    fps: u32,
+    start: Instant,
+    written: u64,
+}
+
+#[derive(Default)]
+pub struct Recorder {
+    recording: Option<Recording>,
+    // when the last frames were displayed
+    displayed: VecDeque<Instant>,
+}
+
+impl Recorder {
+    pub fn displayed(&mut self) {
+        self.displayed.push_back(Instant::now());
+        if self.displayed.len() > MEASURED_FRAMES + 1 {
+            self.displayed.pop_front();
+        }
+    }
+
+    pub fn display_fps(&self) -> Option<u32> {
+        // frame rate over the last displayed frames, none until enough were displayed
+        if self.displayed.len() <= MEASURED_FRAMES {
+            return None;
+        }
+        let span = self.displayed[MEASURED_FRAMES].duration_since(self.displayed[0]);
+        Some((MEASURED_FRAMES as f64 / span.as_secs_f64().max(1e-3)).round() as u32)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn start(
+        &mut self,
+        config: &RecordConfig,
+        size: (u32, u32),
+        fps: u32,
+        audio: Option<(&AudioSource, f64)>,
+    ) -> bool {
+        /*
+            Frames are encoded by a writer thread so the display keep its pace,
+            it stop at the first failed frame and the recording end with it
+            `audio` is the source playing and its seconds already played
+            Return false when the recording can't start
+        */
+        self.stop();
+        let mut sink = match Sink::new(config, size, fps, audio) {
+            Some(s) => s,
+            None => return false,
+        };
+        let (tx, rx) = mpsc::sync_channel::<RgbaImage>(QUEUE_SIZE);
+        let writer = thread::spawn(move || {
+            for (index, frame) in rx.iter().enumerate() {
+                if let Err(e) = sink.write(&frame, index as u64) {
+                    println!("{}", e);
+                    break;
+                }
+            }
+            sink.finish();
+        });
+        println!("Recording started");
+        self.recording = Some(Recording {
+            tx,
+            writer,
+            size,
+            fps,
+            start: Instant::now(),
+            written: 0,
+        });
+        true
+    }
+
+    fn missing_frames(recording: &Recording) -> u64 {
+        // output frames due at the frame rate, a frame is repeated or dropped to keep it
+        let due = (recording.start.elapsed().as_secs_f64() * recording.fps as f64) as u64 + 1;
+        due.saturating_sub(recording.written)
+    }
+
+    pub fn wants_frame(&self) -> bool {
+        // avoid the framebuffer read back when ahead of the output frame rate
+        match &self.recording {
+            Some(r) => Self::missing_frames(r) > 0,
+            None => false,
+        }
+    }
+
+    pub fn push(&mut self, frame: RgbaImage) {
+        let recording = match &mut self.recording {
+            Some(r) => r,
+            None => return,
+        };
+        // the recording keep its size when the window is resized
+        let frame = if frame.dimensions() != recording.size {
+            let (w, h) = recording.size;
+            imageops::resize(&frame, w, h, FilterType::Triangle)
+        } else {
+            frame
+        };
+        for _ in 0..Self::missing_frames(recording) {
+            // the writer is gone after a failed frame
+            if recording.tx.send(frame.clone()).is_err() {
+                self.stop();
+                return;
+            }
+            recording.written += 1;
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(r) = self.recording.take() {
+            drop(r.tx);
+            if r.writer.join().is_err() {
+                println!("Record writer crashed");
+            }
+            println!("Recording stopped, {} frames", r.written);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config(audio: Option<&str>) -> RecordConfig {
+        RecordConfig {
+            start: false,
+            format: RecordFormat::Video,
+            output: PathBuf::new(),
+            fps: None,
+            audio: audio.map(PathBuf::from),
+        }
+    }
+
+    #[test]
+    fn measure_the_display_rate() {
+        let mut recorder = Recorder::default();
+        let now = Instant::now();
+        for i in 0..MEASURED_FRAMES as u64 {
+            recorder
+                .displayed
+                .push_back(now + Duration::from_millis(i * 20));
+        }
+        assert_eq!(recorder.display_fps(), None);
+        // 50 frames per second, the oldest frames are forgotten
+        recorder
+            .displayed
+            .push_front(now - Duration::from_millis(20));
+        assert_eq!(recorder.display_fps(), Some(50));
+        recorder.displayed();
+        assert_eq!(recorder.displayed.len(), MEASURED_FRAMES + 1);
+    }
+
+    #[test]
+    fn mux_the_audio_playing() {
+        let file = AudioSource::File(PathBuf::from("loop.wav"));
+        let capture = AudioSource::Capture {
+            format: "pulse".to_string(),
+            device: "default".to_string(),
+        };
+        let args = |config: &RecordConfig, audio| -> Vec<String> {
+            audio_args(config, audio)
+                .iter()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect()
+        };
+        assert!(args(&config(None), None).is_empty());
+        assert_eq!(
+            args(&config(None), Some((&file, 12.5))),
+            [
+                "-stream_loop",
+                "-1",
+                "-i",
+                "loop.wav",
+                "-af",
+                "atrim=start=12.500,asetpts=PTS-STARTPTS",
+                "-c:a",
+                "aac",
+                "-shortest"
+            ]
+        );
+        assert_eq!(
+            args(&config(None), Some((&capture, 3.))),
+            ["-f", "pulse", "-i", "default", "-c:a", "aac", "-shortest"]
+        );
+        // the configured file first
+        let config = config(Some("music.mp3"));
+        assert_eq!(
+            args(&config, Some((&capture, 3.)))[..2],
+            ["-i", "music.mp3"]
+        );
+    }
+}
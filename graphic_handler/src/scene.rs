@@ -14,6 +14,7 @@ pub struct Scene {
     model_loc: Option<NativeUniformLocation>,
     view_loc: Option<NativeUniformLocation>,
     projection_loc: Option<NativeUniformLocation>,
+    models_loc: Option<NativeUniformLocation>,
 }
 
 impl Scene {
@@ -28,6 +29,7 @@ impl Scene {
                 model_loc: gl.get_uniform_location(*program, "model"),
                 view_loc: gl.get_uniform_location(*program, "view"),
                 projection_loc: gl.get_uniform_location(*program, "projection"),
+                models_loc: gl.get_uniform_location(*program, "models"),
             }
         }
     }
@@ -38,6 +40,14 @@ impl Scene {
         }
     }
 
+    pub fn update_models(&self, gl: &Context, models: &[TMat4<f32>]) {
+        // per instance model matrices, for the instanced draws
+        let data: Vec<f32> = models.iter().flat_map(|m| m.as_slice().to_vec()).collect();
+        unsafe {
+            gl.uniform_matrix_4_f32_slice(self.models_loc.as_ref(), false, &data);
+        }
+    }
+
     pub fn update_scene(&self, gl: &Context) {
         unsafe {
             self.update_model(gl, self.model);
@@ -0,0 +1,253 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+
+use chrono::Local;
+use image::{DynamicImage, RgbaImage};
+use serde::Serialize;
+
+use crate::grading::GradingProfile;
+use crate::graphic_config::{GraphicConfig, MaskSource, Mode};
+use media_handler::frame::Frame;
+use media_handler::glitch::GlitchConfig;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Placement {
+    pub path: PathBuf,
+    pub tags: Vec<String>,
+    // scene position of the media center, 0 for the full screen modes
+    pub position: [f32; 3],
+    pub ratio: f32,
+    pub glitch: Option<f32>,
+}
+
+impl Placement {
+    pub fn new(media: &Frame, position: [f32; 3]) -> Self {
+        Self {
+            path: media.path.clone(),
+            tags: media.tags.clone(),
+            position,
+            ratio: media.ratio,
+            glitch: media.glitch,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostPass {
+    Crt {
+        curvature: f32,
+        scanlines: f32,
+        noise: f32,
+        clip: Option<PathBuf>,
+        // playback speed of the current cut
+        speed: f32,
+    },
+    Composite {
+        background: PathBuf,
+        mask: MaskSource,
+        feather: f32,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct GradientSweep {
+    pub direction: [f32; 2],
+    pub speed: f32,
+    pub bands: u32,
+    pub duration: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Filters {
+    // glitch of the mode and its overrides, the one applied is in each placement
+    pub glitch_intensity: f32,
+    pub glitch: GlitchConfig,
+    // pass of the mode between the scene and the grading
+    pub post_pass: Option<PostPass>,
+    pub gradient_sweep: Option<GradientSweep>,
+    pub grading: GradingProfile,
+    pub lut: Option<String>,
+    pub lut_intensity: f32,
+}
+
+impl Filters {
+    pub fn new(
+        config: &GraphicConfig,
+        glitch: &GlitchConfig,
+        grading: GradingProfile,
+        lut_intensity: f32,
+        cut_speed: Option<f32>,
+    ) -> Self {
+        /*
+            Every pass the collage went through, in the drawing order
+            `cut_speed` of the TV screen, its first one before any cut
+        */
+        let post_pass = match config.mode {
+            Mode::TvScreen => {
+                let tv = &config.tv_screen;
+                Some(PostPass::Crt {
+                    curvature: tv.curvature,
+                    scanlines: tv.scanlines,
+                    noise: tv.noise,
+                    clip: tv.clip.clone(),
+                    speed: cut_speed.unwrap_or(tv.speed_range[0]),
+                })
+            }
+            Mode::Anonymisation => {
+                let anonymisation = &config.anonymisation;
+                Some(PostPass::Composite {
+                    background: anonymisation.background.clone(),
+                    mask: anonymisation.mask.clone(),
+                    feather: anonymisation.feather,
+                })
+            }
+            _ => None,
+        };
+        let gradient_sweep = (config.mode == Mode::GradientSweep).then(|| {
+            let sweep = &config.gradient_sweep;
+            GradientSweep {
+                direction: sweep.direction,
+                speed: sweep.speed,
+                bands: sweep.bands,
+                duration: sweep.duration,
+            }
+        });
+        Self {
+            glitch_intensity: glitch.mode_intensity(config.mode.name()),
+            glitch: glitch.clone(),
+            post_pass,
+            gradient_sweep,
+            grading,
+            lut: config.lut.as_ref().map(|l| l.title.clone()),
+            lut_intensity,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub image: PathBuf,
+    pub taken_at: String,
+    pub size: (u32, u32),
+    pub mode: String,
+    // seed of the session random generator
+    pub seed: u64,
+    pub filters: Filters,
+    // media on screen, the oldest first
+    pub media: Vec<Placement>,
+}
+
+impl Snapshot {
+    pub fn new(
+        config: &GraphicConfig,
+        seed: u64,
+        size: (u32, u32),
+        filters: Filters,
+        media: Vec<Placement>,
+    ) -> Self {
+        let now = Local::now();
+        // milliseconds so two snapshots of the same second don't overwrite each other
+        let name = now
+            .format("snapshot_%Y_%m_%d_%H_%M_%S_%3f.jpeg")
+            .to_string();
+        Self {
+            image: config.snapshot_folder.join(name),
+            taken_at: now.to_rfc3339(),
+            size,
+            mode: config.mode.name().to_string(),
+            seed,
+            filters,
+            media,
+        }
+    }
+
+    pub fn sidecar(&self) -> PathBuf {
+        self.image.with_extension("json")
+    }
+
+    pub fn save(self, frame: RgbaImage) {
+        /*
+            JPEG next to a JSON sidecar of the same name,
+            written out of the render loop
+        */
+        thread::spawn(move || {
+            fs::create_dir_all(self.image.parent().unwrap())
+                .expect("Unable to create the snapshot folder");
+            DynamicImage::ImageRgba8(frame)
+                .to_rgb8()
+                .save(&self.image)
+                .expect("Unable to write the snapshot");
+            let sidecar = serde_json::to_string_pretty(&self).unwrap();
+            fs::write(self.sidecar(), sidecar).expect("Unable to write the snapshot sidecar");
+            println!("Snapshot saved: {}", self.image.display());
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+
+    fn config(mode: Mode) -> GraphicConfig {
+        let root = Path::new(ROOT);
+        let mut config = GraphicConfig::load(&root.join("confs/graphic.yaml"), root);
+        config.mode = mode;
+        config
+    }
+
+    fn sidecar(config: &GraphicConfig, glitch: &GlitchConfig) -> (Snapshot, serde_json::Value) {
+        let filters = Filters::new(config, glitch, GradingProfile::neutral(), 0.5, Some(4.));
+        let media = Frame::from_image(PathBuf::from("sea.png"), DynamicImage::new_rgb8(4, 2));
+        let mut placement = Placement::new(&media, [0.5, -1., 0.]);
+        placement.tags = vec![String::from("sea")];
+        let snapshot = Snapshot::new(config, 42, (64, 48), filters, vec![placement]);
+        let json = serde_json::to_value(&snapshot).unwrap();
+        (snapshot, json)
+    }
+
+    #[test]
+    fn sidecar_describe_the_collage() {
+        let mut glitch = GlitchConfig::default();
+        glitch.modes.insert(String::from("slideshow"), 0.3);
+        let (snapshot, json) = sidecar(&config(Mode::Slideshow), &glitch);
+
+        assert_eq!(snapshot.sidecar().extension().unwrap(), "json");
+        assert_eq!(snapshot.sidecar().file_stem(), snapshot.image.file_stem());
+        assert_eq!(json["mode"], "slideshow");
+        assert_eq!(json["seed"], 42);
+        assert_eq!(json["media"][0]["path"], "sea.png");
+        assert_eq!(
+            json["media"][0]["position"],
+            serde_json::json!([0.5, -1., 0.])
+        );
+        assert_eq!(json["media"][0]["tags"], serde_json::json!(["sea"]));
+        let filters = &json["filters"];
+        assert_eq!(filters["glitch_intensity"].as_f64().unwrap() as f32, 0.3);
+        assert_eq!(
+            filters["glitch"]["modes"]["slideshow"].as_f64().unwrap() as f32,
+            0.3
+        );
+        assert_eq!(filters["lut_intensity"], 0.5);
+        assert!(filters["post_pass"].is_null());
+        assert!(filters["gradient_sweep"].is_null());
+    }
+
+    #[test]
+    fn sidecar_describe_the_mode_passes() {
+        let glitch = GlitchConfig::default();
+        let (_, json) = sidecar(&config(Mode::TvScreen), &glitch);
+        assert_eq!(json["filters"]["post_pass"]["crt"]["speed"], 4.);
+
+        let (_, json) = sidecar(&config(Mode::Anonymisation), &glitch);
+        assert!(json["filters"]["post_pass"]["composite"]["feather"].is_number());
+
+        let (_, json) = sidecar(&config(Mode::GradientSweep), &glitch);
+        assert!(json["filters"]["gradient_sweep"]["bands"].is_number());
+        assert!(json["filters"]["post_pass"].is_null());
+    }
+}
@@ -0,0 +1,194 @@
+use std::f32::consts::TAU;
+
+// lowest and highest frequencies mapped to the bands
+const MIN_FREQUENCY: f32 = 40.;
+const MAX_FREQUENCY: f32 = 16000.;
+// slow decay of the per band peak used to normalize the energies
+const PEAK_DECAY: f32 = 0.995;
+
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    /*
+        In place radix-2 Cooley-Tukey, the length is a power of two
+        Samples are put in bit reversed order then merged by pairs of halves
+    */
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -TAU / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+pub struct Spectrum {
+    pub sample_rate: f32,
+    // smoothed and normalized energy of each band, between 0 and 1
+    pub bands: Vec<f32>,
+    // how fast a band follow a rising or falling energy, between 0 and 1
+    pub attack: f32,
+    pub release: f32,
+
+    peaks: Vec<f32>,
+}
+
+impl Spectrum {
+    pub fn new(band_count: usize, sample_rate: f32, attack: f32, release: f32) -> Self {
+        Self {
+            sample_rate,
+            bands: vec![0.; band_count],
+            attack,
+            release,
+            peaks: vec![f32::EPSILON; band_count],
+        }
+    }
+
+    fn band_of(&self, frequency: f32) -> Option<usize> {
+        // bands are spaced on a log scale like the ear
+        if !(MIN_FREQUENCY..MAX_FREQUENCY).contains(&frequency) {
+            return None;
+        }
+        let position = (frequency / MIN_FREQUENCY).ln() / (MAX_FREQUENCY / MIN_FREQUENCY).ln();
+        Some(((position * self.bands.len() as f32) as usize).min(self.bands.len() - 1))
+    }
+
+    pub fn band_energies(&self, samples: &[f32]) -> Vec<f32> {
+        /*
+            Raw energy per band of a mono sample window,
+            zero padded to a power of two for the FFT
+        */
+        let n = samples.len();
+        let size = n.next_power_of_two();
+        let mut re = vec![0.; size];
+        let mut im = vec![0.; size];
+        for (i, s) in samples.iter().enumerate() {
+            // Hann window to limit the leakage between bands
+            re[i] = s * (0.5 - 0.5 * (TAU * i as f32 / n as f32).cos());
+        }
+        fft(&mut re, &mut im);
+
+        let mut energies = vec![0.; self.bands.len()];
+        for k in 1..size / 2 {
+            if let Some(band) = self.band_of(k as f32 * self.sample_rate / size as f32) {
+                energies[band] += re[k] * re[k] + im[k] * im[k];
+            }
+        }
+        energies
+    }
+
+    pub fn level(&self) -> f32 {
+        // overall loudness, mean of the smoothed bands
+        self.bands.iter().sum::<f32>() / self.bands.len() as f32
+    }
+
+    pub fn update(&mut self, energies: &[f32]) {
+        /*
+            Normalize each band by its recent peak then smooth it:
+            fast attack when a band become active, slow release to settle back
+        */
+        for (i, e) in energies.iter().enumerate().take(self.bands.len()) {
+            self.peaks[i] = (self.peaks[i] * PEAK_DECAY).max(*e).max(f32::EPSILON);
+            let target = e / self.peaks[i];
+            let speed = if target > self.bands[i] {
+                self.attack
+            } else {
+                self.release
+            };
+            self.bands[i] += (target - self.bands[i]) * speed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.;
+
+    fn sine(frequency: f32, n: usize) -> Vec<f32> {
+        (0..n)
+            .map(|i| (TAU * frequency * i as f32 / SAMPLE_RATE).sin())
+            .collect()
+    }
+
+    fn loudest(energies: &[f32]) -> usize {
+        (0..energies.len())
+            .max_by(|a, b| energies[*a].total_cmp(&energies[*b]))
+            .unwrap()
+    }
+
+    #[test]
+    fn fft_match_the_dft() {
+        let samples = [0.3, -0.7, 0.1, 0.9, -0.2, 0.4, 0.0, -0.5];
+        let (mut re, mut im) = (samples.to_vec(), vec![0.; 8]);
+        fft(&mut re, &mut im);
+        for k in 0..8 {
+            let (mut dre, mut dim) = (0., 0.);
+            for (i, s) in samples.iter().enumerate() {
+                let phase = TAU * (k * i) as f32 / 8.;
+                dre += s * phase.cos();
+                dim -= s * phase.sin();
+            }
+            assert!((re[k] - dre).abs() < 1e-5 && (im[k] - dim).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn sine_in_its_band() {
+        let spectrum = Spectrum::new(8, SAMPLE_RATE, 0.5, 0.1);
+        for frequency in [100., 1000., 8000.] {
+            let energies = spectrum.band_energies(&sine(frequency, 1024));
+            assert_eq!(Some(loudest(&energies)), spectrum.band_of(frequency));
+        }
+        // padded windows keep the frequencies
+        let energies = spectrum.band_energies(&sine(1000., 1000));
+        assert_eq!(Some(loudest(&energies)), spectrum.band_of(1000.));
+    }
+
+    #[test]
+    fn silence_has_no_energy() {
+        let spectrum = Spectrum::new(8, SAMPLE_RATE, 0.5, 0.1);
+        assert!(spectrum.band_energies(&[0.; 1024]).iter().all(|e| *e == 0.));
+    }
+
+    #[test]
+    fn update_attack_and_release() {
+        let mut spectrum = Spectrum::new(2, SAMPLE_RATE, 0.5, 0.1);
+        spectrum.update(&[4., 0.]);
+        // normalized by the peak then half way with the attack
+        assert!((spectrum.bands[0] - 0.5).abs() < 1e-6);
+        assert_eq!(spectrum.bands[1], 0.);
+        spectrum.update(&[4., 0.]);
+        assert!((spectrum.bands[0] - 0.75).abs() < 1e-6);
+        spectrum.update(&[0., 0.]);
+        assert!((spectrum.bands[0] - 0.675).abs() < 1e-6);
+        for _ in 0..100 {
+            spectrum.update(&[1000., 0.]);
+            assert!(spectrum.bands[0] <= 1.);
+        }
+        assert!((spectrum.level() - spectrum.bands[0] / 2.).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::Instant;
+
+use iced_glutin::glutin::dpi::LogicalSize;
+use iced_glutin::glutin::event_loop::EventLoop;
+use iced_glutin::glutin::monitor::MonitorHandle;
+use iced_glutin::glutin::window::{Fullscreen, Window, WindowBuilder};
+
+use crate::graphic_config::{GraphicConfig, MonitorSelector, WindowConfig, WindowMode};
+
+pub fn find_monitor(
+    mut monitors: impl Iterator<Item = MonitorHandle>,
+    primary: Option<MonitorHandle>,
+    selector: &Option<MonitorSelector>,
+) -> Option<MonitorHandle> {
+    /*
+        Monitor chosen by index or name, fallback on the primary one
+    */
+    let found = match selector {
+        Some(MonitorSelector::Index(i)) => monitors.nth(*i),
+        Some(MonitorSelector::Name(name)) => monitors.find(|m| m.name().as_ref() == Some(name)),
+        None => None,
+    };
+    if found.is_none() && selector.is_some() {
+        println!("Monitor {:?} not found, using the primary one", selector);
+    }
+    found.or(primary)
+}
+
+pub fn fullscreen(mode: WindowMode, monitor: Option<MonitorHandle>) -> Option<Fullscreen> {
+    match mode {
+        WindowMode::Windowed => None,
+        WindowMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+        WindowMode::Exclusive => {
+            // biggest video mode of the monitor
+            let monitor = match monitor {
+                Some(m) => m,
+                None => {
+                    println!(
+                        "No monitor found for the exclusive mode, using borderless fullscreen"
+                    );
+                    return Some(Fullscreen::Borderless(None));
+                }
+            };
+            let video_mode = monitor
+                .video_modes()
+                .max_by_key(|m| (m.size().width, m.size().height, m.bit_depth()));
+            match video_mode {
+                Some(v) => Some(Fullscreen::Exclusive(v)),
+                None => {
+                    println!("No exclusive video mode available, using borderless fullscreen");
+                    Some(Fullscreen::Borderless(None))
+                }
+            }
+        }
+    }
+}
+
+pub fn builder(
+    event_loop: &EventLoop<()>,
+    config: &GraphicConfig,
+    window: &WindowConfig,
+) -> WindowBuilder {
+    let monitor = find_monitor(
+        event_loop.available_monitors(),
+        event_loop.primary_monitor(),
+        &window.monitor,
+    );
+    let mut wb = WindowBuilder::new()
+        .with_title(&config.app_name)
+        .with_inner_size(LogicalSize::new(config.width, config.height))
+        .with_fullscreen(fullscreen(window.mode, monitor.clone()))
+        .with_always_on_top(window.always_on_top);
+    if let Some(m) = monitor {
+        // windowed mode open on the chosen monitor too
+        wb = wb.with_position(m.position());
+    }
+    wb
+}
+
+pub fn span_layout(sizes: &[(u32, u32)], bezel: u32) -> ((i32, i32), Vec<[f32; 4]>) {
+    /*
+        Canvas holding all the windows side by side with a `bezel` gap between them,
+        the part of the picture behind a bezel is hidden like through a real window
+
+        Each window gets its rectangle of the canvas in texture coordinates
+        (x, y, width, height), vertically centered when the heights differ
+    */
+    let gaps = bezel * (sizes.len() as u32).saturating_sub(1);
+    let width = (sizes.iter().map(|s| s.0).sum::<u32>() + gaps).max(1);
+    let height = sizes.iter().map(|s| s.1).max().unwrap_or(1).max(1);
+
+    let mut x = 0;
+    let rects = sizes
+        .iter()
+        .map(|&(w, h)| {
+            let rect = [
+                x as f32 / width as f32,
+                (height - h) as f32 / 2. / height as f32,
+                w as f32 / width as f32,
+                h as f32 / height as f32,
+            ];
+            x += w + bezel;
+            rect
+        })
+        .collect();
+    ((width as i32, height as i32), rects)
+}
+
+pub fn toggle_fullscreen(window: &Window, config: &WindowConfig) {
+    /*
+        Switch between windowed and the configured fullscreen,
+        borderless on the current monitor if the config is windowed
+    */
+    if window.fullscreen().is_some() {
+        window.set_fullscreen(None);
+        return;
+    }
+    let monitor = find_monitor(
+        window.available_monitors(),
+        window.current_monitor(),
+        &config.monitor,
+    );
+    let mode = match config.mode {
+        WindowMode::Windowed => WindowMode::Borderless,
+        m => m,
+    };
+    window.set_fullscreen(fullscreen(mode, monitor));
+}
+
+pub struct CursorHider {
+    pub delay: Option<f32>,
+    last_move: Instant,
+    hidden: bool,
+}
+
+impl CursorHider {
+    pub fn new(delay: Option<f32>) -> Self {
+        // no delay or 0 keep the cursor shown
+        Self {
+            delay: delay.filter(|d| *d > 0.),
+            last_move: Instant::now(),
+            hidden: false,
+        }
+    }
+
+    pub fn moved(&mut self, window: &Window) {
+        self.last_move = Instant::now();
+        if self.hidden {
+            window.set_cursor_visible(true);
+            self.hidden = false;
+        }
+    }
+
+    pub fn update(&mut self, window: &Window) {
+        // hide the cursor after `delay` seconds without move
+        if let Some(delay) = self.delay {
+            if !self.hidden && self.last_move.elapsed().as_secs_f32() > delay {
+                window.set_cursor_visible(false);
+                self.hidden = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,21 @@
+-- back to a single tag per media and a single media per format and tag,
+-- the other rows are lost
+ALTER TABLE media RENAME TO media_new;
+ALTER INDEX media_pkey RENAME TO media_new_pkey;
+ALTER INDEX media_url_key RENAME TO media_new_url_key;
+
+CREATE TABLE media (
+	url VARCHAR NOT NULL UNIQUE,
+	format_id INTEGER REFERENCES format(id),
+	tag_id INTEGER REFERENCES tag(id),
+	PRIMARY KEY(format_id, tag_id)
+);
+
+INSERT INTO media (url, format_id, tag_id)
+SELECT media_new.url, media_new.format_id, media_tag.tag_id
+FROM media_new JOIN media_tag ON media_tag.media_id = media_new.id
+ORDER BY media_new.id, media_tag.tag_id
+ON CONFLICT DO NOTHING;
+
+DROP TABLE media_tag;
+DROP TABLE media_new;
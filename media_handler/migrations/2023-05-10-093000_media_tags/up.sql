@@ -0,0 +1,33 @@
+-- one row per media, its tags in a join table
+ALTER TABLE media RENAME TO media_old;
+ALTER INDEX media_pkey RENAME TO media_old_pkey;
+ALTER INDEX media_url_key RENAME TO media_old_url_key;
+
+CREATE TABLE media (
+	id SERIAL PRIMARY KEY,
+	url VARCHAR NOT NULL UNIQUE,
+	format_id INTEGER NOT NULL REFERENCES format(id),
+	width INTEGER,
+	height INTEGER,
+	hash VARCHAR,
+	added_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE media_tag (
+	media_id INTEGER NOT NULL REFERENCES media(id) ON DELETE CASCADE,
+	tag_id INTEGER NOT NULL REFERENCES tag(id) ON DELETE CASCADE,
+	PRIMARY KEY(media_id, tag_id)
+);
+
+CREATE INDEX media_hash_idx ON media(hash);
+CREATE INDEX media_tag_tag_id_idx ON media_tag(tag_id);
+
+-- previous rows, sizes and hashes are filled by the indexer
+INSERT INTO media (url, format_id)
+SELECT url, format_id FROM media_old;
+
+INSERT INTO media_tag (media_id, tag_id)
+SELECT media.id, media_old.tag_id
+FROM media_old JOIN media ON media.url = media_old.url;
+
+DROP TABLE media_old;
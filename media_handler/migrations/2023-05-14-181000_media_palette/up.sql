@@ -0,0 +1,2 @@
+-- dominant colors of the media, hex codes separated by spaces
+ALTER TABLE media ADD COLUMN palette VARCHAR;
@@ -0,0 +1,2 @@
+-- 64 bits difference hash of the media, close values for similar images
+ALTER TABLE media ADD COLUMN phash BIGINT;
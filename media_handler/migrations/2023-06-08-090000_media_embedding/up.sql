@@ -0,0 +1,2 @@
+-- unit length image embedding of the media, little endian f32 values
+ALTER TABLE media ADD COLUMN embedding BYTEA;
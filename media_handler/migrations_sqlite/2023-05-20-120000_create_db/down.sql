@@ -0,0 +1,4 @@
+DROP TABLE media_tag;
+DROP TABLE media;
+DROP TABLE tag;
+DROP TABLE format;
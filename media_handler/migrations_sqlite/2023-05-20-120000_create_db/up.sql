@@ -0,0 +1,30 @@
+-- same schema as the PostgreSQL migrations, in one step
+CREATE TABLE format (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE tag (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE media (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	url TEXT NOT NULL UNIQUE,
+	format_id INTEGER NOT NULL REFERENCES format(id),
+	width INTEGER,
+	height INTEGER,
+	hash TEXT,
+	added_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	palette TEXT
+);
+
+CREATE TABLE media_tag (
+	media_id INTEGER NOT NULL REFERENCES media(id) ON DELETE CASCADE,
+	tag_id INTEGER NOT NULL REFERENCES tag(id) ON DELETE CASCADE,
+	PRIMARY KEY(media_id, tag_id)
+);
+
+CREATE INDEX media_hash_idx ON media(hash);
+CREATE INDEX media_tag_tag_id_idx ON media_tag(tag_id);
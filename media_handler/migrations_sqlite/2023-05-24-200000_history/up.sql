@@ -0,0 +1,11 @@
+-- every media shown, with its display duration in seconds and the user rating
+CREATE TABLE history (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	media_id INTEGER NOT NULL REFERENCES media(id) ON DELETE CASCADE,
+	shown_at TIMESTAMP NOT NULL,
+	duration REAL,
+	-- 1 liked, -1 disliked, 0 not rated
+	rating INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX history_media_id_idx ON history(media_id);
@@ -0,0 +1,2 @@
+-- normalized RGB histogram of the media, little endian f32 bins
+ALTER TABLE media ADD COLUMN histogram BLOB;
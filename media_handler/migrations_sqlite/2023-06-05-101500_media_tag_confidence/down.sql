@@ -0,0 +1 @@
+ALTER TABLE media_tag DROP COLUMN confidence;
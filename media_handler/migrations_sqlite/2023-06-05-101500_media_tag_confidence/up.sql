@@ -0,0 +1,2 @@
+-- confidence of the tagger for automatic tags, null for the ones set by hand
+ALTER TABLE media_tag ADD COLUMN confidence REAL;
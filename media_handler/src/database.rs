@@ -0,0 +1,85 @@
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+pub const PG_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_sqlite");
+
+pub enum DbConnection {
+    Postgres(PgConnection),
+    // embedded database, no server needed
+    Sqlite(SqliteConnection),
+}
+
+/*
+    Run the same Diesel code on the connection of any backend,
+    queries are built once per backend type:
+
+    with_connection!(connection, conn => media::table.load::<Media>(conn))
+*/
+macro_rules! with_connection {
+    ($connection:expr, $conn:ident => $body:expr) => {
+        match $connection {
+            $crate::database::DbConnection::Postgres($conn) => $body,
+            $crate::database::DbConnection::Sqlite($conn) => $body,
+        }
+    };
+}
+pub(crate) use with_connection;
+
+impl DbConnection {
+    pub fn establish(database_url: &str) -> Self {
+        /*
+            The backend follow the url scheme: sqlite://<file> or postgres://..
+            Pending migrations are applied on connection
+        */
+        let mut connection = match database_url.strip_prefix("sqlite://") {
+            Some(path) => {
+                let mut conn = SqliteConnection::establish(path)
+                    .unwrap_or_else(|_| panic!("Error opening {}", path));
+                // SQLite ignore the foreign keys, and their cascades, by default
+                diesel::sql_query("PRAGMA foreign_keys = ON")
+                    .execute(&mut conn)
+                    .expect("Failed request");
+                Self::Sqlite(conn)
+            }
+            None => Self::Postgres(
+                PgConnection::establish(database_url)
+                    .unwrap_or_else(|_| panic!("Error connecting to {}", database_url)),
+            ),
+        };
+        match &mut connection {
+            Self::Postgres(conn) => conn.run_pending_migrations(PG_MIGRATIONS).map(|_| ()),
+            Self::Sqlite(conn) => conn.run_pending_migrations(SQLITE_MIGRATIONS).map(|_| ()),
+        }
+        .expect("Unable to run the database migrations");
+        connection
+    }
+}
+
+#[cfg(test)]
+pub fn test_databases() -> Vec<DbConnection> {
+    /*
+        Empty databases of the tests: SQLite in memory,
+        and the PostgreSQL of CUDI_TEST_DATABASE_URL when it is set
+        Everything happen in a transaction never committed, the rows already there are kept
+    */
+    use crate::schema::{format, media, tag};
+
+    let mut urls = vec![String::from("sqlite://:memory:")];
+    urls.extend(std::env::var("CUDI_TEST_DATABASE_URL").ok());
+    urls.iter()
+        .map(|url| {
+            let mut connection = DbConnection::establish(url);
+            with_connection!(&mut connection, conn => {
+                conn.begin_test_transaction().unwrap();
+                // media rows cascade to their tags and history
+                diesel::delete(media::table).execute(conn).unwrap();
+                diesel::delete(tag::table).execute(conn).unwrap();
+                diesel::delete(format::table).execute(conn).unwrap();
+            });
+            connection
+        })
+        .collect()
+}
@@ -0,0 +1,567 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use diesel::dsl::not;
+use diesel::prelude::*;
+use image::DynamicImage;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use yaml_rust::Yaml;
+
+use crate::database::{with_connection, DbConnection};
+use crate::schema::*;
+use crate::similarity::{Feature, NeighborWalk};
+use crate::sql_models::*;
+use crate::tagger::OnnxModel;
+
+// k-means rounds when the clusters don't settle before
+const KMEANS_ROUNDS: usize = 50;
+// prefix of the cluster tags
+pub const CLUSTER_PREFIX: &str = "vibe-";
+// confidence marking the tags of a cluster, never one of a model,
+// they are all replaced at each clustering and the ones set by hand stay
+pub const CLUSTER_CONFIDENCE: f32 = -1.;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Embedding {
+    // unit length, the dot product of two embeddings is their cosine similarity
+    pub values: Vec<f32>,
+}
+
+impl Embedding {
+    pub fn new(mut values: Vec<f32>) -> Self {
+        let norm = values.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0. {
+            values.iter_mut().for_each(|v| *v /= norm);
+        }
+        Self { values }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.is_empty() || bytes.len() % 4 != 0 {
+            return None;
+        }
+        let values = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Some(Self { values })
+    }
+
+    pub fn of_file(embedder: &dyn Embedder, path: &Path) -> Self {
+        let image = image::open(path).unwrap_or_else(|_| panic!("Unable to open {:?}", path));
+        Self::new(embedder.embed(&image))
+    }
+
+    pub fn distance(&self, other: &Embedding) -> f32 {
+        // cosine distance, 0 for the same direction and 2 for the opposite
+        let dot: f32 = self
+            .values
+            .iter()
+            .zip(&other.values)
+            .map(|(a, b)| a * b)
+            .sum();
+        1. - dot
+    }
+
+    fn mean(embeddings: &[&Embedding], size: usize) -> Self {
+        let mut values = vec![0.; size];
+        for e in embeddings {
+            values.iter_mut().zip(&e.values).for_each(|(v, x)| *v += x);
+        }
+        Self::new(values)
+    }
+}
+
+pub trait Embedder {
+    // any vector, the ones of similar images pointing the same way
+    fn embed(&self, image: &DynamicImage) -> Vec<f32>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbedderConfig {
+    // ONNX model with a 1x3xSxS float input, its first output is the embedding
+    pub model: Option<PathBuf>,
+    pub input_size: u32,
+}
+
+impl EmbedderConfig {
+    pub fn new(cfg: &Yaml) -> Self {
+        Self {
+            model: cfg["model"].as_str().map(PathBuf::from),
+            input_size: cfg["input_size"].as_i64().unwrap() as u32,
+        }
+    }
+}
+
+pub struct OnnxEmbedder {
+    model: OnnxModel,
+}
+
+impl OnnxEmbedder {
+    pub fn new(config: &EmbedderConfig) -> Self {
+        let model = config
+            .model
+            .as_ref()
+            .expect("No embedder model in the media config");
+        Self {
+            model: OnnxModel::new(model, config.input_size),
+        }
+    }
+}
+
+impl Embedder for OnnxEmbedder {
+    fn embed(&self, image: &DynamicImage) -> Vec<f32> {
+        self.model.run(image)
+    }
+}
+
+pub fn embed_media(
+    connection: &mut DbConnection,
+    embedder: &dyn Embedder,
+    reembed: bool,
+) -> (usize, usize) {
+    /*
+        Store the embedding of the media without one, or of all of them with reembed
+        Return the embedded and skipped counts
+    */
+    let media: Vec<Media> = with_connection!(connection, conn => {
+        let mut query = media::table.select(Media::as_select()).into_boxed();
+        if !reembed {
+            query = query.filter(media::embedding.is_null());
+        }
+        query.load(conn).expect("Failed request")
+    });
+
+    let (mut embedded, mut skipped) = (0, 0);
+    for m in media {
+        let image = match image::open(&m.url) {
+            Ok(i) => i,
+            Err(_) => {
+                println!("Unable to open {}", m.url);
+                skipped += 1;
+                continue;
+            }
+        };
+        let bytes = Embedding::new(embedder.embed(&image)).to_bytes();
+        with_connection!(connection, conn => {
+            diesel::update(media::table.find(m.id))
+                .set(media::embedding.eq(bytes))
+                .execute(conn)
+                .expect("Failed request")
+        });
+        embedded += 1;
+    }
+    (embedded, skipped)
+}
+
+pub fn load_embedded(connection: &mut DbConnection) -> Vec<Media> {
+    // every media of the database with an embedding, whatever the query
+    with_connection!(connection, conn => {
+        media::table
+            .filter(media::embedding.is_not_null())
+            .select(Media::as_select())
+            .order(media::url)
+            .load(conn)
+            .expect("Failed request")
+    })
+}
+
+impl Feature for Embedding {
+    fn of(media: &Media) -> Option<Self> {
+        Self::from_bytes(media.embedding.as_deref()?)
+    }
+
+    fn distance(&self, other: &Self) -> f32 {
+        // embeddings of another model can't be compared
+        if self.values.len() != other.values.len() {
+            return f32::INFINITY;
+        }
+        Embedding::distance(self, other)
+    }
+}
+
+pub fn kmeans(points: &[Embedding], k: usize, rng: &mut StdRng) -> Vec<usize> {
+    /*
+        Cluster of each point, spherical k-means on the cosine distance
+        Centers start spread out by k-means++, a center losing all its points stays in place
+    */
+    let k = k.min(points.len());
+    if k == 0 {
+        return vec![0; points.len()];
+    }
+    let size = points[0].values.len();
+
+    let mut centers = vec![points.choose(rng).unwrap().clone()];
+    while centers.len() < k {
+        let weights: Vec<f32> = points
+            .iter()
+            .map(|p| {
+                let d = centers
+                    .iter()
+                    .map(|c| p.distance(c))
+                    .fold(f32::MAX, f32::min)
+                    .max(0.);
+                d * d
+            })
+            .collect();
+        let next = match WeightedIndex::new(&weights) {
+            Ok(w) => w.sample(rng),
+            // every point is already a center
+            Err(_) => rng.gen_range(0..points.len()),
+        };
+        centers.push(points[next].clone());
+    }
+
+    let mut assignments = vec![usize::MAX; points.len()];
+    for _ in 0..KMEANS_ROUNDS {
+        let closest: Vec<usize> = points
+            .iter()
+            .map(|p| {
+                (0..k)
+                    .min_by(|a, b| {
+                        p.distance(&centers[*a])
+                            .total_cmp(&p.distance(&centers[*b]))
+                    })
+                    .unwrap()
+            })
+            .collect();
+        if closest == assignments {
+            break;
+        }
+        assignments = closest;
+        for (i, center) in centers.iter_mut().enumerate() {
+            let members: Vec<&Embedding> = points
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, a)| **a == i)
+                .map(|(p, _)| p)
+                .collect();
+            if !members.is_empty() {
+                *center = Embedding::mean(&members, size);
+            }
+        }
+    }
+    assignments
+}
+
+pub fn cluster(connection: &mut DbConnection, k: usize, rng: &mut StdRng) -> Vec<(String, usize)> {
+    /*
+        Group the embedded media in K clusters stored as tags,
+        each named after the model tag most common among its media, or its number
+        Return the tags with their media count
+        Embeddings of another size than the most common one come from another model, left out
+    */
+    let embedded: Vec<(Media, Embedding)> = load_embedded(connection)
+        .into_iter()
+        .filter_map(|m| Embedding::of(&m).map(|e| (m, e)))
+        .collect();
+    let mut sizes: HashMap<usize, usize> = HashMap::new();
+    for (_, e) in &embedded {
+        *sizes.entry(e.values.len()).or_default() += 1;
+    }
+    // the smallest size for the ties
+    let size = sizes
+        .iter()
+        .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))
+        .map(|(s, _)| *s);
+    let (media, points): (Vec<Media>, Vec<Embedding>) = embedded
+        .into_iter()
+        .filter(|(_, e)| Some(e.values.len()) == size)
+        .unzip();
+    let left_out = sizes.values().sum::<usize>() - points.len();
+    if left_out > 0 {
+        println!(
+            "{} media with an embedding of another size left out, embed them again",
+            left_out
+        );
+    }
+    let assignments = kmeans(&points, k, rng);
+
+    let labels: Vec<(i32, String)> = with_connection!(connection, conn => {
+        media_tag::table
+            .inner_join(tag::table)
+            .filter(media_tag::confidence.ge(0f32))
+            .select((media_tag::media_id, tag::name))
+            .load(conn)
+            .expect("Failed request")
+    });
+    let mut names: Vec<String> = vec![];
+    let mut groups: Vec<(String, Vec<i32>)> = vec![];
+    for i in 0..k.min(points.len()) {
+        let ids: Vec<i32> = media
+            .iter()
+            .zip(&assignments)
+            .filter(|(_, a)| **a == i)
+            .map(|(m, _)| m.id)
+            .collect();
+        let mut counts: HashMap<&String, usize> = HashMap::new();
+        for (_, name) in labels.iter().filter(|(id, _)| ids.contains(id)) {
+            *counts.entry(name).or_default() += 1;
+        }
+        let mut counts: Vec<(&String, usize)> = counts.into_iter().collect();
+        // most common first, by name for the ties
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let name = counts
+            .iter()
+            .map(|(n, _)| format!("{}{}", CLUSTER_PREFIX, n))
+            .find(|n| !names.contains(n))
+            .unwrap_or_else(|| format!("{}{}", CLUSTER_PREFIX, i));
+        names.push(name.clone());
+        groups.push((name, ids));
+    }
+
+    with_connection!(connection, conn => {
+        // the tag rows of the previous clusters go with them, unless set by hand on a media
+        let previous: Vec<i32> = media_tag::table
+            .filter(media_tag::confidence.eq(CLUSTER_CONFIDENCE))
+            .select(media_tag::tag_id)
+            .distinct()
+            .load(conn)
+            .expect("Failed request");
+        diesel::delete(media_tag::table.filter(media_tag::confidence.eq(CLUSTER_CONFIDENCE)))
+            .execute(conn)
+            .expect("Failed request");
+        let used = media_tag::table.select(media_tag::tag_id);
+        diesel::delete(
+            tag::table
+                .filter(tag::id.eq_any(&previous))
+                .filter(not(tag::id.eq_any(used))),
+        )
+        .execute(conn)
+        .expect("Failed request");
+        for (name, ids) in &groups {
+            diesel::insert_into(tag::table)
+                .values(tag::name.eq(name))
+                .on_conflict(tag::name)
+                .do_nothing()
+                .execute(conn)
+                .expect("Failed request");
+            let tag_id: i32 = tag::table
+                .filter(tag::name.eq(name))
+                .select(tag::id)
+                .first(conn)
+                .expect("Failed request");
+            let rows: Vec<MediaTag> = ids
+                .iter()
+                .map(|id| MediaTag {
+                    media_id: *id,
+                    tag_id,
+                    confidence: Some(CLUSTER_CONFIDENCE),
+                })
+                .collect();
+            // a media tagged by hand keep its tag
+            for row in &rows {
+                diesel::insert_into(media_tag::table)
+                    .values(row)
+                    .on_conflict((media_tag::media_id, media_tag::tag_id))
+                    .do_nothing()
+                    .execute(conn)
+                    .expect("Failed request");
+            }
+        }
+    });
+    groups
+        .into_iter()
+        .map(|(name, ids)| (name, ids.len()))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalkConfig {
+    pub enabled: bool,
+    // neighbors considered at each step
+    pub k: usize,
+}
+
+impl WalkConfig {
+    pub fn new(cfg: &Yaml) -> Self {
+        Self {
+            enabled: cfg["enabled"].as_bool().unwrap(),
+            k: cfg["k"].as_i64().unwrap() as usize,
+        }
+    }
+
+    pub fn start(&self) -> NeighborWalk<Embedding> {
+        // consecutive media share an aesthetic rather than colors
+        NeighborWalk::new(self.k, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_databases;
+    use rand::SeedableRng;
+
+    fn blobs(rng: &mut StdRng) -> Vec<Embedding> {
+        // ten points around each axis
+        (0..20)
+            .map(|i| {
+                let mut values: Vec<f32> = (0..3).map(|_| rng.gen_range(0. ..0.1)).collect();
+                values[i / 10] = 1.;
+                Embedding::new(values)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let embedding = Embedding::new(vec![3., 0., -4.]);
+        assert_eq!(embedding.values, [0.6, 0., -0.8]);
+        assert_eq!(
+            Embedding::from_bytes(&embedding.to_bytes()),
+            Some(embedding)
+        );
+        assert_eq!(Embedding::from_bytes(&[0; 6]), None);
+        assert_eq!(Embedding::from_bytes(&[]), None);
+    }
+
+    #[test]
+    fn kmeans_is_seeded() {
+        let points = blobs(&mut StdRng::seed_from_u64(1));
+        let run = |seed| kmeans(&points, 4, &mut StdRng::seed_from_u64(seed));
+        assert_eq!(run(2), run(2));
+        assert_eq!(run(3), run(3));
+    }
+
+    #[test]
+    fn kmeans_separate_the_blobs() {
+        let points = blobs(&mut StdRng::seed_from_u64(1));
+        for seed in 0..5 {
+            let assignments = kmeans(&points, 2, &mut StdRng::seed_from_u64(seed));
+            assert!(assignments[..10].iter().all(|a| *a == assignments[0]));
+            assert!(assignments[10..].iter().all(|a| *a == assignments[10]));
+            assert_ne!(assignments[0], assignments[10]);
+        }
+    }
+
+    #[test]
+    fn kmeans_with_empty_clusters() {
+        // more clusters than distinct points, some stay empty
+        let points = vec![Embedding::new(vec![1., 0.]); 4];
+        let assignments = kmeans(&points, 3, &mut StdRng::seed_from_u64(0));
+        assert_eq!(assignments.len(), 4);
+        assert!(assignments.iter().all(|a| *a < 3));
+        // more clusters than points
+        let assignments = kmeans(&points[..2], 5, &mut StdRng::seed_from_u64(0));
+        assert!(assignments.iter().all(|a| *a < 2));
+        assert!(kmeans(&[], 3, &mut StdRng::seed_from_u64(0)).is_empty());
+    }
+
+    fn add_media(connection: &mut DbConnection, url: &str, embedding: &Embedding) -> i32 {
+        with_connection!(connection, conn => {
+            diesel::insert_into(format::table)
+                .values(format::name.eq("PNG"))
+                .on_conflict(format::name)
+                .do_nothing()
+                .execute(conn)
+                .unwrap();
+            let format_id: i32 = format::table.select(format::id).first(conn).unwrap();
+            diesel::insert_into(media::table)
+                .values((
+                    media::url.eq(url),
+                    media::format_id.eq(format_id),
+                    media::embedding.eq(embedding.to_bytes()),
+                ))
+                .execute(conn)
+                .unwrap();
+            media::table
+                .filter(media::url.eq(url))
+                .select(media::id)
+                .first(conn)
+                .unwrap()
+        })
+    }
+
+    fn tag(connection: &mut DbConnection, media_id: i32, name: &str, confidence: Option<f32>) {
+        with_connection!(connection, conn => {
+            diesel::insert_into(tag::table)
+                .values(tag::name.eq(name))
+                .on_conflict(tag::name)
+                .do_nothing()
+                .execute(conn)
+                .unwrap();
+            let tag_id: i32 = tag::table
+                .filter(tag::name.eq(name))
+                .select(tag::id)
+                .first(conn)
+                .unwrap();
+            diesel::insert_into(media_tag::table)
+                .values(MediaTag {
+                    media_id,
+                    tag_id,
+                    confidence,
+                })
+                .execute(conn)
+                .unwrap();
+        });
+    }
+
+    fn tags(connection: &mut DbConnection) -> Vec<(String, String, Option<f32>)> {
+        with_connection!(connection, conn => {
+            media_tag::table
+                .inner_join(media::table)
+                .inner_join(tag::table)
+                .select((media::url, tag::name, media_tag::confidence))
+                .order((media::url, tag::name))
+                .load(conn)
+                .unwrap()
+        })
+    }
+
+    #[test]
+    fn cluster_keep_the_hand_tags() {
+        let points = blobs(&mut StdRng::seed_from_u64(1));
+        for mut connection in test_databases() {
+            let ids: Vec<i32> = points
+                .iter()
+                .enumerate()
+                .map(|(i, p)| add_media(&mut connection, &format!("{:02}", i), p))
+                .collect();
+            // an embedding of another model
+            add_media(&mut connection, "other", &Embedding::new(vec![1.; 5]));
+            for id in &ids[..10] {
+                tag(&mut connection, *id, "sea", Some(0.8));
+            }
+            tag(&mut connection, ids[0], "vibe-mine", None);
+            tag(&mut connection, ids[15], "vibe-sea", None);
+
+            let mut rng = StdRng::seed_from_u64(0);
+            let mut clusters = cluster(&mut connection, 2, &mut rng);
+            clusters.sort();
+            let other = clusters[0].0.clone();
+            assert_eq!(
+                clusters,
+                [(other.clone(), 10), (String::from("vibe-sea"), 10)]
+            );
+            let first = tags(&mut connection);
+
+            // the same clusters replace the previous ones
+            cluster(&mut connection, 2, &mut rng);
+            let second = tags(&mut connection);
+            assert_eq!(first, second);
+
+            let hand: Vec<&(String, String, Option<f32>)> =
+                second.iter().filter(|(_, _, c)| c.is_none()).collect();
+            assert_eq!(hand.len(), 2);
+            assert!(hand.contains(&&(String::from("00"), String::from("vibe-mine"), None)));
+            // the hand tag of a media outside the cluster stay
+            assert!(hand.contains(&&(String::from("15"), String::from("vibe-sea"), None)));
+            let clustered = |url: &str| {
+                second
+                    .iter()
+                    .filter(|(u, _, c)| u == url && *c == Some(CLUSTER_CONFIDENCE))
+                    .count()
+            };
+            assert_eq!(clustered("00"), 1);
+            assert_eq!(clustered("other"), 0);
+        }
+    }
+}
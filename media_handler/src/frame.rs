@@ -1,6 +1,9 @@
 use image::DynamicImage;
 use image::GenericImageView;
-use std::path::PathBuf;
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
+
+use crate::palette::{Palette, PALETTE_SIZE};
 
 #[derive(Debug)]
 pub struct Frame {
@@ -10,6 +13,14 @@ pub struct Frame {
     pub ratio: f32,
     pub path: PathBuf,
     pub data: DynamicImage,
+    // dominant colors, luminance and saturation computed at decode time
+    pub palette: Palette,
+    // glitch intensity of this media replacing the configured ones, set by the replay
+    pub glitch_override: Option<f32>,
+    // glitch intensity applied, none when the media is left clean
+    pub glitch: Option<f32>,
+    // tags of the filter that selected this media, its color tags without filter
+    pub tags: Vec<String>,
 }
 
 impl std::fmt::Display for Frame {
@@ -26,18 +37,35 @@ impl std::fmt::Display for Frame {
 
 impl Frame {
     pub fn new(p: PathBuf) -> Self {
-        let data = image::open(&p).expect(&format!(
-            "Image couldn't be open by 'image' package: {:?}",
-            p
-        ));
+        let data = image::open(&p)
+            .unwrap_or_else(|_| panic!("Image couldn't be open by 'image' package: {:?}", p));
+        Self::from_image(p, data)
+    }
+
+    pub fn is_image(p: &Path) -> bool {
+        // files of a folder worth opening, by their extension
+        p.is_file()
+            && ImageFormat::from_path(p)
+                .map(|f| f.reading_enabled())
+                .unwrap_or(false)
+    }
 
+    pub fn from_image(p: PathBuf, data: DynamicImage) -> Self {
+        /*
+            Frame from an already decoded or generated image
+        */
         let (width, height) = data.dimensions();
+        let palette = Palette::new(&data, PALETTE_SIZE);
         Self {
             width,
             height,
             ratio: width as f32 / height as f32,
             path: p,
             data,
+            palette,
+            glitch_override: None,
+            glitch: None,
+            tags: vec![],
         }
     }
 
@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{ColorType, DynamicImage, ImageFormat, RgbaImage};
+use rand::Rng;
+use serde::Serialize;
+use yaml_rust::Yaml;
+
+use crate::frame::Frame;
+
+// JPEG Start Of Scan marker, the entropy coded data (DCT blocks) follow its header
+const JPEG_SOS: [u8; 2] = [0xFF, 0xDA];
+const MACROBLOCK_SIZE: u32 = 16;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GlitchConfig {
+    // intensity of every media, between 0 and 1
+    pub intensity: f32,
+    // display mode name -> intensity replacing the one above
+    pub modes: HashMap<String, f32>,
+    // tag name -> intensity of the media carrying it, replace the mode one
+    pub tags: HashMap<String, f32>,
+    // intensity added at full audio level
+    pub audio: f32,
+}
+
+impl GlitchConfig {
+    fn intensities(cfg: &Yaml) -> HashMap<String, f32> {
+        cfg.as_hash()
+            .map(|h| {
+                h.iter()
+                    .map(|(k, v)| {
+                        let name = String::from(k.as_str().unwrap());
+                        (name, v.as_f64().unwrap() as f32)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn new(cfg: &Yaml) -> Self {
+        Self {
+            intensity: cfg["intensity"].as_f64().unwrap() as f32,
+            modes: Self::intensities(&cfg["modes"]),
+            tags: Self::intensities(&cfg["tags"]),
+            audio: cfg["audio"].as_f64().unwrap() as f32,
+        }
+    }
+
+    pub fn mode_intensity(&self, mode: &str) -> f32 {
+        // intensity of the display mode, the global one without override
+        self.modes
+            .get(mode)
+            .unwrap_or(&self.intensity)
+            .clamp(0., 1.)
+    }
+}
+
+pub struct Glitch {
+    pub config: GlitchConfig,
+    // intensity of the current mode, between 0 and 1
+    pub intensity: f32,
+    // level of the audio analysed by the graphic side, between 0 and 1
+    pub audio_level: f32,
+
+    previous: Option<RgbaImage>,
+}
+
+impl Glitch {
+    pub fn new(config: GlitchConfig) -> Self {
+        Self {
+            intensity: config.intensity.clamp(0., 1.),
+            config,
+            audio_level: 0.,
+            previous: None,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: &str) {
+        self.intensity = self.config.mode_intensity(mode);
+    }
+
+    pub fn set_audio_level(&mut self, level: f32) {
+        self.audio_level = level.clamp(0., 1.);
+    }
+
+    pub fn intensity_for(&self, frame: &Frame) -> f32 {
+        /*
+            Per media intensity override the mode one:
+            the override of the frame, else the highest of its tags
+            Audio level is added on top of it
+        */
+        let base = frame
+            .glitch_override
+            .or_else(|| {
+                frame
+                    .tags
+                    .iter()
+                    .filter_map(|t| self.config.tags.get(t).copied())
+                    .reduce(f32::max)
+            })
+            .unwrap_or(self.intensity);
+        (base + self.audio_level * self.config.audio).clamp(0., 1.)
+    }
+
+    pub fn jpeg_crush(image: &RgbaImage, quality: u8) -> Vec<u8> {
+        /*
+            Re-encode the image as a low quality JPEG and return the encoded bytes
+        */
+        let rgb = DynamicImage::ImageRgba8(image.clone()).into_rgb8();
+        let mut buffer = vec![];
+        JpegEncoder::new_with_quality(&mut buffer, quality.clamp(1, 100))
+            .encode(rgb.as_raw(), rgb.width(), rgb.height(), ColorType::Rgb8)
+            .expect("JPEG encoding failed");
+        buffer
+    }
+
+    pub fn corrupt_bytes<R: Rng>(jpeg: &mut [u8], count: usize, rng: &mut R) {
+        /*
+            Overwrite random bytes of the entropy coded segment to break DCT blocks
+            Headers and markers are kept intact so the image can still be decoded
+        */
+        let scan_start = match jpeg.windows(2).position(|w| w == JPEG_SOS) {
+            // a truncated stream may end in the middle of the SOS header
+            Some(p) if p + 3 < jpeg.len() => {
+                // skip the SOS header, its length is stored just after the marker
+                let header_len = ((jpeg[p + 2] as usize) << 8) | jpeg[p + 3] as usize;
+                p + 2 + header_len
+            }
+            _ => return,
+        };
+        // keep the End Of Image marker
+        let scan_end = jpeg.len().saturating_sub(2);
+        if scan_start >= scan_end {
+            return;
+        }
+
+        for _ in 0..count {
+            let i = rng.gen_range(scan_start..scan_end);
+            if jpeg[i] == 0xFF || jpeg[i - 1] == 0xFF {
+                continue;
+            }
+            let value = rng.gen_range(0..0xFF);
+            jpeg[i] = value;
+        }
+    }
+
+    pub fn decode_jpeg(jpeg: &[u8]) -> Option<RgbaImage> {
+        image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg)
+            .map(|i| i.into_rgba8())
+            .ok()
+    }
+
+    pub fn shift_channels(image: &mut RgbaImage, offset: i32) {
+        /*
+            Shift the red channel to the left and the blue channel to the right
+        */
+        let source = image.clone();
+        let (width, height) = image.dimensions();
+        let w = width as i32;
+
+        for y in 0..height {
+            for x in 0..width {
+                let red_x = (x as i32 + offset).clamp(0, w - 1) as u32;
+                let blue_x = (x as i32 - offset).clamp(0, w - 1) as u32;
+                let pixel = image.get_pixel_mut(x, y);
+                pixel[0] = source.get_pixel(red_x, y)[0];
+                pixel[2] = source.get_pixel(blue_x, y)[2];
+            }
+        }
+    }
+
+    pub fn smear_macroblocks<R: Rng>(
+        previous: &RgbaImage,
+        image: &mut RgbaImage,
+        probability: f32,
+        rng: &mut R,
+    ) {
+        /*
+            Datamosh: some macroblocks keep the content of the previous frame,
+            moved by a small random motion vector, instead of being refreshed
+        */
+        let (width, height) = image.dimensions();
+        let (prev_width, prev_height) = previous.dimensions();
+        let b = MACROBLOCK_SIZE as i32;
+
+        for by in (0..height).step_by(MACROBLOCK_SIZE as usize) {
+            for bx in (0..width).step_by(MACROBLOCK_SIZE as usize) {
+                if rng.gen::<f32>() >= probability {
+                    continue;
+                }
+                let (dx, dy) = (rng.gen_range(-b..=b), rng.gen_range(-b..=b));
+                for y in by..(by + MACROBLOCK_SIZE).min(height) {
+                    for x in bx..(bx + MACROBLOCK_SIZE).min(width) {
+                        let px = (x as i32 + dx).clamp(0, prev_width as i32 - 1) as u32;
+                        let py = (y as i32 + dy).clamp(0, prev_height as i32 - 1) as u32;
+                        image.put_pixel(x, y, *previous.get_pixel(px, py));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn apply<R: Rng>(&mut self, frame: &mut Frame, rng: &mut R) {
+        /*
+            Chain all the effects, each one scaled by the intensity:
+            low quality JPEG -> corrupted DCT blocks -> channel shift -> macroblock smear
+            The intensity applied is kept in the frame, snapshots and the session log record it
+        */
+        let intensity = self.intensity_for(frame);
+        if intensity <= 0. {
+            self.previous = None;
+            frame.glitch = None;
+            return;
+        }
+
+        let mut image = frame.data.to_rgba8();
+
+        let quality = (1. + (1. - intensity) * 29.) as u8;
+        let mut jpeg = Self::jpeg_crush(&image, quality);
+        let clean = Self::decode_jpeg(&jpeg);
+        Self::corrupt_bytes(&mut jpeg, (intensity * 32.) as usize, rng);
+        // corrupted streams can be undecodable, fallback to the clean low quality one
+        if let Some(crushed) = Self::decode_jpeg(&jpeg).or(clean) {
+            image = crushed;
+        }
+
+        let offset = (intensity * image.width() as f32 * 0.02) as i32;
+        Self::shift_channels(&mut image, offset);
+
+        if let Some(previous) = &self.previous {
+            Self::smear_macroblocks(previous, &mut image, intensity * 0.5, rng);
+        }
+        self.previous = Some(image.clone());
+
+        frame.data = DynamicImage::ImageRgba8(image);
+        frame.glitch = Some(intensity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::path::PathBuf;
+
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(64, 48, |x, y| {
+            Rgba([(x * 4) as u8, (y * 5) as u8, 128, 255])
+        })
+    }
+
+    fn frame() -> Frame {
+        Frame::from_image(
+            PathBuf::from("gradient"),
+            DynamicImage::ImageRgba8(gradient()),
+        )
+    }
+
+    #[test]
+    fn corrupt_keep_the_headers() {
+        let clean = Glitch::jpeg_crush(&gradient(), 50);
+        let mut jpeg = clean.clone();
+        Glitch::corrupt_bytes(&mut jpeg, 64, &mut StdRng::seed_from_u64(1));
+        assert_ne!(jpeg, clean);
+        let sos = clean.windows(2).position(|w| w == JPEG_SOS).unwrap();
+        assert_eq!(jpeg[..sos + 4], clean[..sos + 4]);
+        assert_eq!(jpeg[jpeg.len() - 2..], [0xFF, 0xD9]);
+    }
+
+    #[test]
+    fn corrupt_truncated_streams() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for bytes in [
+            &[][..],
+            &[0xFF, 0xDA],
+            &[0xFF, 0xDA, 0x00],
+            &[0x12, 0xFF, 0xDA, 0x00],
+        ] {
+            let mut jpeg = bytes.to_vec();
+            Glitch::corrupt_bytes(&mut jpeg, 8, &mut rng);
+            assert_eq!(jpeg, bytes);
+        }
+        // the header length goes past the end
+        let mut jpeg = vec![0xFF, 0xDA, 0x00, 0xFF, 0x01, 0x02];
+        Glitch::corrupt_bytes(&mut jpeg, 8, &mut rng);
+        assert_eq!(jpeg, [0xFF, 0xDA, 0x00, 0xFF, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn shift_the_red_and_blue_channels() {
+        let mut image = gradient();
+        Glitch::shift_channels(&mut image, 2);
+        assert_eq!(*image.get_pixel(10, 3), Rgba([48, 15, 128, 255]));
+        // clamped on the edges
+        assert_eq!(image.get_pixel(63, 0)[0], 252);
+        assert_eq!(image.get_pixel(0, 0)[2], 128);
+    }
+
+    #[test]
+    fn smear_from_the_previous_frame() {
+        let previous = RgbaImage::from_pixel(64, 48, Rgba([255, 0, 0, 255]));
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut image = gradient();
+        Glitch::smear_macroblocks(&previous, &mut image, 0., &mut rng);
+        assert_eq!(image, gradient());
+        Glitch::smear_macroblocks(&previous, &mut image, 1., &mut rng);
+        assert_eq!(image, previous);
+    }
+
+    fn glitch(intensity: f32) -> Glitch {
+        Glitch::new(GlitchConfig {
+            intensity,
+            ..Default::default()
+        })
+    }
+
+    fn intensities(values: &[(&str, f32)]) -> HashMap<String, f32> {
+        values.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+    }
+
+    fn tagged(tags: &[&str]) -> Frame {
+        let mut media = frame();
+        media.tags = tags.iter().map(|t| t.to_string()).collect();
+        media
+    }
+
+    #[test]
+    fn intensity_of_the_mode() {
+        let mut glitch = Glitch::new(GlitchConfig {
+            intensity: 0.2,
+            modes: intensities(&[("tv_screen", 0.6), ("slideshow", 3.)]),
+            ..Default::default()
+        });
+        assert_eq!(glitch.intensity_for(&frame()), 0.2);
+        glitch.set_mode("tv_screen");
+        assert_eq!(glitch.intensity_for(&frame()), 0.6);
+        glitch.set_mode("slideshow");
+        assert_eq!(glitch.intensity_for(&frame()), 1.);
+        glitch.set_mode("kaleidoscope");
+        assert_eq!(glitch.intensity_for(&frame()), 0.2);
+    }
+
+    #[test]
+    fn intensity_of_the_media() {
+        let glitch = Glitch::new(GlitchConfig {
+            intensity: 0.2,
+            tags: intensities(&[("night", 0.3), ("fire", 0.8)]),
+            ..Default::default()
+        });
+        assert_eq!(glitch.intensity_for(&tagged(&["sea"])), 0.2);
+        assert_eq!(glitch.intensity_for(&tagged(&["sea", "night"])), 0.3);
+        assert_eq!(glitch.intensity_for(&tagged(&["fire", "night"])), 0.8);
+        // set on the frame, over its tags
+        let mut media = tagged(&["fire"]);
+        media.glitch_override = Some(0.);
+        assert_eq!(glitch.intensity_for(&media), 0.);
+    }
+
+    #[test]
+    fn intensity_with_audio() {
+        let mut glitch = Glitch::new(GlitchConfig {
+            intensity: 0.2,
+            audio: 0.5,
+            ..Default::default()
+        });
+        glitch.set_audio_level(0.4);
+        assert!((glitch.intensity_for(&frame()) - 0.4).abs() < 1e-6);
+        glitch.set_audio_level(5.);
+        assert!((glitch.intensity_for(&frame()) - 0.7).abs() < 1e-6);
+        glitch.config.audio = 2.;
+        assert_eq!(glitch.intensity_for(&frame()), 1.);
+    }
+
+    #[test]
+    fn apply_without_intensity() {
+        let mut media = frame();
+        glitch(0.).apply(&mut media, &mut StdRng::seed_from_u64(1));
+        assert_eq!(media.data.to_rgba8(), gradient());
+        assert_eq!(media.glitch, None);
+        // a clean media replayed stay clean, its override is kept
+        media.glitch_override = Some(0.);
+        glitch(1.).apply(&mut media, &mut StdRng::seed_from_u64(1));
+        assert_eq!(media.data.to_rgba8(), gradient());
+        assert_eq!((media.glitch_override, media.glitch), (Some(0.), None));
+    }
+
+    #[test]
+    fn apply_the_same_glitch_for_a_seed() {
+        let glitched = || {
+            let mut glitch = glitch(2.);
+            let mut rng = StdRng::seed_from_u64(1);
+            let (mut first, mut second) = (frame(), frame());
+            glitch.apply(&mut first, &mut rng);
+            glitch.apply(&mut second, &mut rng);
+            second
+        };
+        let media = glitched();
+        assert_eq!(media.glitch, Some(1.));
+        assert_eq!((media.width, media.height), (64, 48));
+        assert_eq!(media.data.width(), 64);
+        assert_ne!(media.data.to_rgba8(), gradient());
+        assert_eq!(media.data, glitched().data);
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{Duration, NaiveDateTime, Timelike, Utc};
+use diesel::dsl::{max, sum};
+use diesel::prelude::*;
+use yaml_rust::Yaml;
+
+use crate::database::{with_connection, DbConnection};
+use crate::schema::*;
+use crate::sql_models::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryConfig {
+    // weight added per like
+    pub like_boost: f32,
+    // weight multiplied per dislike
+    pub dislike_factor: f32,
+    // seconds before a shown media can come back
+    pub cooldown: f32,
+}
+
+impl HistoryConfig {
+    pub fn new(cfg: &Yaml) -> Self {
+        Self {
+            like_boost: cfg["like_boost"].as_f64().unwrap() as f32,
+            dislike_factor: cfg["dislike_factor"].as_f64().unwrap() as f32,
+            cooldown: cfg["cooldown"].as_f64().unwrap() as f32,
+        }
+    }
+}
+
+pub fn stats(connection: &mut DbConnection) -> HashMap<i32, (i64, Option<NaiveDateTime>)> {
+    // rating sum and last display of every media shown at least once
+    with_connection!(connection, conn => {
+        history::table
+            .group_by(history::media_id)
+            .select((history::media_id, sum(history::rating), max(history::shown_at)))
+            .load::<(i32, Option<i64>, Option<NaiveDateTime>)>(conn)
+            .expect("Failed request")
+    })
+    .into_iter()
+    .map(|(id, score, last)| (id, (score.unwrap_or(0), last)))
+    .collect()
+}
+
+pub struct HistoryTracker {
+    config: HistoryConfig,
+    // media of the last batch sent, timed by the next one
+    last_batch: Vec<i32>,
+    last_shown: Option<NaiveDateTime>,
+    // last media sent, on top of the screen, the one a rating apply to
+    last_media: Option<i32>,
+}
+
+impl HistoryTracker {
+    pub fn new(config: &HistoryConfig) -> Self {
+        Self {
+            config: config.clone(),
+            last_batch: vec![],
+            last_shown: None,
+            last_media: None,
+        }
+    }
+
+    fn now() -> NaiveDateTime {
+        // PostgreSQL keep microseconds, the rows are found back by their exact time
+        let now = Utc::now().naive_utc();
+        now.with_nanosecond(now.nanosecond() / 1000 * 1000).unwrap()
+    }
+
+    pub fn record(&mut self, connection: &mut DbConnection, paths: &[PathBuf]) {
+        /*
+            A row per media sent to the display,
+            the previous batch get its display duration
+        */
+        let now = Self::now();
+        let urls: Vec<String> = paths
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        let last_batch = &self.last_batch;
+        let last_shown = self.last_shown;
+
+        self.last_batch = with_connection!(connection, conn => {
+            if let Some(shown_at) = last_shown {
+                let duration = (now - shown_at).num_milliseconds() as f32 / 1000.;
+                diesel::update(
+                    history::table
+                        .filter(history::media_id.eq_any(last_batch))
+                        .filter(history::shown_at.eq(shown_at)),
+                )
+                .set(history::duration.eq(duration))
+                .execute(conn)
+                .expect("Failed request");
+            }
+
+            let ids: HashMap<String, i32> = media::table
+                .filter(media::url.eq_any(&urls))
+                .select((media::url, media::id))
+                .load::<(String, i32)>(conn)
+                .expect("Failed request")
+                .into_iter()
+                .collect();
+            self.last_media = urls.last().and_then(|u| ids.get(u)).copied();
+            let ids: Vec<i32> = ids.into_values().collect();
+            let rows: Vec<NewHistory> = ids
+                .iter()
+                .map(|id| NewHistory {
+                    media_id: *id,
+                    shown_at: now,
+                })
+                .collect();
+            diesel::insert_into(history::table)
+                .values(&rows)
+                .execute(conn)
+                .expect("Failed request");
+            ids
+        });
+        self.last_shown = Some(now);
+    }
+
+    pub fn rate(&self, connection: &mut DbConnection, rating: i32) {
+        // the last media sent, not its whole batch
+        let (media_id, shown_at) = match (self.last_media, self.last_shown) {
+            (Some(m), Some(t)) => (m, t),
+            _ => return,
+        };
+        with_connection!(connection, conn => {
+            diesel::update(
+                history::table
+                    .filter(history::media_id.eq(media_id))
+                    .filter(history::shown_at.eq(shown_at)),
+            )
+            .set(history::rating.eq(rating))
+            .execute(conn)
+            .expect("Failed request")
+        });
+    }
+
+    pub fn weights(&self, connection: &mut DbConnection, media: &[Media]) -> Vec<(f32, bool)> {
+        /*
+            Selection weight of each media from its ratings,
+            and whether it is still cooling down since its last display
+        */
+        let stats = stats(connection);
+
+        let cooldown_start =
+            Self::now() - Duration::milliseconds((self.config.cooldown * 1000.) as i64);
+        media
+            .iter()
+            .map(|m| {
+                let (score, last) = stats.get(&m.id).copied().unwrap_or((0, None));
+                let score = score as i32;
+                let weight = if score >= 0 {
+                    1. + self.config.like_boost * score as f32
+                } else {
+                    self.config.dislike_factor.powi(-score)
+                };
+                let cooling = last.is_some_and(|t| t > cooldown_start);
+                (weight, cooling)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_databases;
+
+    fn config(cooldown: f32) -> HistoryConfig {
+        HistoryConfig {
+            like_boost: 0.5,
+            dislike_factor: 0.25,
+            cooldown,
+        }
+    }
+
+    fn add_media(connection: &mut DbConnection, urls: &[&str]) -> Vec<Media> {
+        with_connection!(connection, conn => {
+            diesel::insert_into(format::table)
+                .values(format::name.eq("PNG"))
+                .execute(conn)
+                .unwrap();
+            let format_id: i32 = format::table.select(format::id).first(conn).unwrap();
+            for url in urls {
+                diesel::insert_into(media::table)
+                    .values((media::url.eq(url), media::format_id.eq(format_id)))
+                    .execute(conn)
+                    .unwrap();
+            }
+            media::table
+                .select(Media::as_select())
+                .order(media::url)
+                .load(conn)
+                .unwrap()
+        })
+    }
+
+    fn paths(urls: &[&str]) -> Vec<PathBuf> {
+        urls.iter().map(PathBuf::from).collect()
+    }
+
+    fn ratings(connection: &mut DbConnection) -> HashMap<i32, i64> {
+        stats(connection)
+            .into_iter()
+            .map(|(id, (rating, _))| (id, rating))
+            .collect()
+    }
+
+    #[test]
+    fn rate_the_last_media() {
+        for mut connection in test_databases() {
+            let media = add_media(&mut connection, &["a", "b", "c"]);
+            let mut tracker = HistoryTracker::new(&config(0.));
+            // nothing shown, nothing rated
+            tracker.rate(&mut connection, 1);
+            assert!(ratings(&mut connection).is_empty());
+
+            tracker.record(&mut connection, &paths(&["a", "b"]));
+            tracker.rate(&mut connection, 1);
+            let rated = ratings(&mut connection);
+            assert_eq!((rated[&media[0].id], rated[&media[1].id]), (0, 1));
+
+            // a rating replace the previous one of the same display
+            tracker.record(&mut connection, &paths(&["c", "a"]));
+            tracker.rate(&mut connection, 1);
+            tracker.rate(&mut connection, -1);
+            let rated = ratings(&mut connection);
+            assert_eq!(rated[&media[0].id], -1);
+            assert_eq!(rated[&media[2].id], 0);
+        }
+    }
+
+    #[test]
+    fn weights_of_the_ratings() {
+        for mut connection in test_databases() {
+            let media = add_media(&mut connection, &["a", "b", "c", "d"]);
+            let mut tracker = HistoryTracker::new(&config(0.));
+            for (url, rating) in [("a", 1), ("a", 1), ("b", -1), ("b", -1), ("c", 0)] {
+                tracker.record(&mut connection, &paths(&[url]));
+                tracker.rate(&mut connection, rating);
+            }
+            let weights: Vec<f32> = tracker
+                .weights(&mut connection, &media)
+                .iter()
+                .map(|(w, _)| *w)
+                .collect();
+            // two likes, two dislikes, shown without rating, never shown
+            assert_eq!(weights, [2., 0.0625, 1., 1.]);
+        }
+    }
+
+    #[test]
+    fn cooldown_of_the_shown_media() {
+        for mut connection in test_databases() {
+            let media = add_media(&mut connection, &["a", "b"]);
+            let mut tracker = HistoryTracker::new(&config(60.));
+            tracker.record(&mut connection, &paths(&["a"]));
+            let cooling: Vec<bool> = tracker
+                .weights(&mut connection, &media)
+                .iter()
+                .map(|(_, c)| *c)
+                .collect();
+            assert_eq!(cooling, [true, false]);
+
+            let tracker = HistoryTracker::new(&config(0.));
+            assert!(tracker
+                .weights(&mut connection, &media)
+                .iter()
+                .all(|(_, c)| !c));
+        }
+    }
+}
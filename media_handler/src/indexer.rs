@@ -0,0 +1,450 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use diesel::prelude::*;
+use image::ImageFormat;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::database::{with_connection, DbConnection};
+use crate::media_config::MediaConfig;
+use crate::palette::{Palette, COLOR_TAGS, PALETTE_SIZE};
+use crate::perceptual::dhash;
+use crate::schema::*;
+use crate::similarity::Histogram;
+use crate::sql_models::*;
+
+// urls deleted per request, under the bind parameters limit of SQLite
+const DELETE_CHUNK: usize = 500;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct IndexReport {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    // files that aren't images or can't be decoded
+    pub skipped: usize,
+}
+
+impl fmt::Display for IndexReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} removed, {} unchanged, {} skipped",
+            self.added, self.updated, self.removed, self.unchanged, self.skipped
+        )
+    }
+}
+
+pub struct Indexer {
+    connection: DbConnection,
+    // format and tag ids by name, created when missing
+    formats: HashMap<String, i32>,
+    tags: HashMap<String, i32>,
+}
+
+impl Indexer {
+    pub fn new(config: &MediaConfig) -> Self {
+        Self {
+            connection: DbConnection::establish(&config.database_url),
+            formats: HashMap::new(),
+            tags: HashMap::new(),
+        }
+    }
+
+    fn format_name(format: ImageFormat) -> String {
+        match format {
+            ImageFormat::Jpeg => String::from("JPEG"),
+            f => format!("{:?}", f).to_uppercase(),
+        }
+    }
+
+    fn format_id(&mut self, name: &str) -> i32 {
+        if let Some(id) = self.formats.get(name) {
+            return *id;
+        }
+        let id = with_connection!(&mut self.connection, conn => {
+            diesel::insert_into(format::table)
+                .values(format::name.eq(name))
+                .on_conflict(format::name)
+                .do_nothing()
+                .execute(conn)
+                .expect("Failed request");
+            format::table
+                .filter(format::name.eq(name))
+                .select(format::id)
+                .first(conn)
+                .expect("Failed request")
+        });
+        self.formats.insert(name.to_string(), id);
+        id
+    }
+
+    fn tag_id(&mut self, name: &str) -> i32 {
+        if let Some(id) = self.tags.get(name) {
+            return *id;
+        }
+        let id = with_connection!(&mut self.connection, conn => {
+            diesel::insert_into(tag::table)
+                .values(tag::name.eq(name))
+                .on_conflict(tag::name)
+                .do_nothing()
+                .execute(conn)
+                .expect("Failed request");
+            tag::table
+                .filter(tag::name.eq(name))
+                .select(tag::id)
+                .first(conn)
+                .expect("Failed request")
+        });
+        self.tags.insert(name.to_string(), id);
+        id
+    }
+
+    fn set_color_tags(&mut self, url: &str, names: &[String]) {
+        // the color tags of the previous content go, the other tags stay
+        let tag_ids: Vec<i32> = names.iter().map(|n| self.tag_id(n)).collect();
+        with_connection!(&mut self.connection, conn => {
+            let media_id: i32 = media::table
+                .filter(media::url.eq(url))
+                .select(media::id)
+                .first(conn)
+                .expect("Failed request");
+            let color_tags = tag::table
+                .filter(tag::name.eq_any(COLOR_TAGS.to_vec()))
+                .select(tag::id);
+            diesel::delete(
+                media_tag::table
+                    .filter(media_tag::media_id.eq(media_id))
+                    .filter(media_tag::tag_id.eq_any(color_tags)),
+            )
+            .execute(conn)
+            .expect("Failed request");
+            let rows: Vec<MediaTag> = tag_ids
+                .iter()
+                .map(|tag_id| MediaTag {
+                    media_id,
+                    tag_id: *tag_id,
+                    confidence: None,
+                })
+                .collect();
+            diesel::insert_into(media_tag::table)
+                .values(&rows)
+                .execute(conn)
+                .expect("Failed request");
+        });
+    }
+
+    fn hash(bytes: &[u8]) -> String {
+        Sha256::digest(bytes)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn describe(
+        &mut self,
+        url: String,
+        bytes: &[u8],
+        hash: String,
+    ) -> Option<(NewMedia, Vec<String>)> {
+        /*
+            Format from the file content, the extension can lie
+            Return the row and the color tags, none when the file isn't a decodable image
+        */
+        let format = image::guess_format(bytes).ok()?;
+        let image = image::load_from_memory_with_format(bytes, format).ok()?;
+        let palette = Palette::new(&image, PALETTE_SIZE);
+        let colors = palette
+            .colors
+            .iter()
+            .map(|[r, g, b]| format!("#{:02x}{:02x}{:02x}", r, g, b))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let new_media = NewMedia {
+            url,
+            format_id: self.format_id(&Self::format_name(format)),
+            width: image.width() as i32,
+            height: image.height() as i32,
+            hash,
+            palette: colors,
+            histogram: Histogram::from_image(&image).to_bytes(),
+            phash: dhash(&image),
+        };
+        Some((new_media, palette.color_tags()))
+    }
+
+    pub fn run(&mut self, folder: &Path) -> IndexReport {
+        /*
+            Walk the folder and upsert every image, keyed by its absolute path,
+            `data`, `./data` and `data/` index the same rows
+            Files already indexed with the same content hash aren't decoded again,
+            a new content drop the embedding and the tags of a model,
+            rows of the folder whose file disappeared are removed
+        */
+        let folder = &fs::canonicalize(folder)
+            .unwrap_or_else(|_| panic!("Unable to open the folder {}", folder.display()));
+        let mut report = IndexReport::default();
+        // hash of the indexed media, and whether the row has its histogram and phash
+        let known: HashMap<String, (Option<String>, bool)> =
+            with_connection!(&mut self.connection, conn => {
+                media::table
+                    .select((
+                        media::url,
+                        media::hash,
+                        media::histogram.is_not_null().and(media::phash.is_not_null()),
+                    ))
+                    .load::<(String, Option<String>, bool)>(conn)
+                    .expect("Failed request")
+            })
+            .into_iter()
+            .map(|(url, hash, complete)| (url, (hash, complete)))
+            .collect();
+
+        let mut seen: HashSet<String> = HashSet::new();
+        let files = WalkDir::new(folder)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file());
+        for entry in files {
+            // links are followed, a linked file is indexed under its real path
+            let url = match fs::canonicalize(entry.path()) {
+                Ok(p) => p.to_string_lossy().into_owned(),
+                Err(_) => {
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            let bytes = match fs::read(entry.path()) {
+                Ok(b) => b,
+                Err(_) => {
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            let hash = Self::hash(&bytes);
+
+            let previous = known.get(&url);
+            if previous == Some(&(Some(hash.clone()), true)) {
+                seen.insert(url);
+                report.unchanged += 1;
+                continue;
+            }
+            let content_changed = previous.is_some_and(|(h, _)| h.as_ref() != Some(&hash));
+            let (new_media, color_tags) = match self.describe(url.clone(), &bytes, hash) {
+                Some(d) => d,
+                None => {
+                    println!("Not an image: {}", entry.path().display());
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            with_connection!(&mut self.connection, conn => {
+                diesel::insert_into(media::table)
+                    .values(&new_media)
+                    .on_conflict(media::url)
+                    .do_update()
+                    // the embedding of the previous content is stale
+                    .set((&new_media, media::embedding.eq(None::<Vec<u8>>)))
+                    .execute(conn)
+                    .expect("Failed request");
+                if content_changed {
+                    // so are the tags of a model or a cluster, the tags without confidence stay
+                    let id = media::table.filter(media::url.eq(&url)).select(media::id);
+                    diesel::delete(
+                        media_tag::table
+                            .filter(media_tag::media_id.eq_any(id))
+                            .filter(media_tag::confidence.is_not_null()),
+                    )
+                    .execute(conn)
+                    .expect("Failed request");
+                }
+            });
+            self.set_color_tags(&url, &color_tags);
+            seen.insert(url);
+            match previous {
+                Some(_) => report.updated += 1,
+                None => report.added += 1,
+            }
+        }
+
+        // rows of this folder only, the others may come from another folder
+        let removed: Vec<&String> = known
+            .keys()
+            .filter(|url| Path::new(url).starts_with(folder) && !seen.contains(*url))
+            .collect();
+        for chunk in removed.chunks(DELETE_CHUNK) {
+            report.removed += with_connection!(&mut self.connection, conn => {
+                diesel::delete(media::table.filter(media::url.eq_any(chunk)))
+                    .execute(conn)
+                    .expect("Failed request")
+            });
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_databases;
+    use image::{Rgb, RgbImage};
+    use std::path::PathBuf;
+
+    fn folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("cudi_indexer_{}", name));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(folder.join("sub")).unwrap();
+        save(&folder.join("red.png"), [255, 0, 0]);
+        save(&folder.join("sub/blue.png"), [0, 0, 255]);
+        // not an image
+        fs::write(folder.join("notes.txt"), "").unwrap();
+        folder
+    }
+
+    fn save(path: &Path, color: [u8; 3]) {
+        RgbImage::from_pixel(8, 8, Rgb(color)).save(path).unwrap();
+    }
+
+    fn indexers() -> Vec<Indexer> {
+        test_databases()
+            .into_iter()
+            .map(|connection| Indexer {
+                connection,
+                formats: HashMap::new(),
+                tags: HashMap::new(),
+            })
+            .collect()
+    }
+
+    fn rows(indexer: &mut Indexer) -> Vec<(String, Option<String>)> {
+        with_connection!(&mut indexer.connection, conn => {
+            media::table
+                .select((media::url, media::hash))
+                .order(media::url)
+                .load(conn)
+                .unwrap()
+        })
+    }
+
+    fn tags(indexer: &mut Indexer, path: &Path) -> Vec<String> {
+        let url = fs::canonicalize(path)
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        with_connection!(&mut indexer.connection, conn => {
+            media_tag::table
+                .inner_join(media::table)
+                .inner_join(tag::table)
+                .filter(media::url.eq(url))
+                .select(tag::name)
+                .order(tag::name)
+                .load(conn)
+                .unwrap()
+        })
+    }
+
+    fn report(added: usize, updated: usize, removed: usize, unchanged: usize) -> IndexReport {
+        // the text file is always skipped
+        IndexReport {
+            added,
+            updated,
+            removed,
+            unchanged,
+            skipped: 1,
+        }
+    }
+
+    #[test]
+    fn index_the_changes() {
+        let folder = folder("changes");
+        for mut indexer in indexers() {
+            assert_eq!(indexer.run(&folder), report(2, 0, 0, 0));
+            assert_eq!(indexer.run(&folder), report(0, 0, 0, 2));
+
+            save(&folder.join("red.png"), [0, 255, 0]);
+            assert_eq!(indexer.run(&folder), report(0, 1, 0, 1));
+            let colors = tags(&mut indexer, &folder.join("red.png"));
+            assert!(colors.contains(&String::from("green")));
+            assert!(!colors.contains(&String::from("red")));
+
+            fs::remove_file(folder.join("sub/blue.png")).unwrap();
+            assert_eq!(indexer.run(&folder), report(0, 0, 1, 1));
+            assert_eq!(rows(&mut indexer).len(), 1);
+
+            // back to the first state for the next backend
+            save(&folder.join("red.png"), [255, 0, 0]);
+            save(&folder.join("sub/blue.png"), [0, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn same_rows_for_the_same_folder() {
+        let folder = folder("spelling");
+        let spellings = [
+            folder.clone(),
+            folder.join("."),
+            PathBuf::from(format!("{}/", folder.display())),
+            folder.join("sub/.."),
+        ];
+        for mut indexer in indexers() {
+            indexer.run(&spellings[0]);
+            let indexed = rows(&mut indexer);
+            assert_eq!(indexed.len(), 2);
+            for spelling in &spellings[1..] {
+                assert_eq!(indexer.run(spelling), report(0, 0, 0, 2));
+                assert_eq!(rows(&mut indexer), indexed);
+            }
+        }
+    }
+
+    #[test]
+    fn changed_content_drop_the_model_tags() {
+        let folder = folder("model_tags");
+        let red = folder.join("red.png");
+        for mut indexer in indexers() {
+            indexer.run(&folder);
+            let url = fs::canonicalize(&red)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            let (model, hand) = (indexer.tag_id("cat"), indexer.tag_id("mine"));
+            with_connection!(&mut indexer.connection, conn => {
+                let media_id: i32 = media::table
+                    .filter(media::url.eq(&url))
+                    .select(media::id)
+                    .first(conn)
+                    .unwrap();
+                diesel::insert_into(media_tag::table)
+                    .values(&vec![
+                        MediaTag {
+                            media_id,
+                            tag_id: model,
+                            confidence: Some(0.9),
+                        },
+                        MediaTag {
+                            media_id,
+                            tag_id: hand,
+                            confidence: None,
+                        },
+                    ])
+                    .execute(conn)
+                    .unwrap();
+            });
+            let has = |tags: &[String], name: &str| tags.iter().any(|t| t == name);
+            let before = tags(&mut indexer, &red);
+            assert!(has(&before, "cat") && has(&before, "mine") && has(&before, "red"));
+
+            save(&red, [0, 255, 0]);
+            indexer.run(&folder);
+            let after = tags(&mut indexer, &red);
+            assert!(has(&after, "green") && has(&after, "mine"));
+            assert!(!has(&after, "cat") && !has(&after, "red"));
+            save(&red, [255, 0, 0]);
+        }
+    }
+}
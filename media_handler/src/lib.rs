@@ -1,42 +1,132 @@
+pub mod database;
+pub mod embedding;
 pub mod frame;
+pub mod glitch;
+pub mod history;
+pub mod indexer;
 pub mod media_config;
+pub mod media_query;
 pub mod media_source_api;
+pub mod palette;
+pub mod perceptual;
+pub mod replay;
 pub mod schema;
+pub mod search;
+pub mod sequence;
+pub mod session;
+pub mod similarity;
 pub mod sql_models;
+pub mod tagger;
 
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use frame::Frame;
+use glitch::Glitch;
 use media_config::MediaConfig;
-use media_source_api::{LocalMedia, MediaSource, PostgreSQLMedia};
+use media_query::QueryCommand;
+use media_source_api::{DatabaseMedia, LocalMedia, MediaSource};
+use session::{Event, Session, GLITCH_STREAM, SHUFFLE_STREAM};
+
+// pause before asking again a source without media
+const EMPTY_SOURCE_RETRY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaCommand {
+    Query(QueryCommand),
+    // rating of the last media sent, 1 liked and -1 disliked
+    Rate(i32),
+    // level of the analysed audio, between 0 and 1, raise the glitch
+    AudioLevel(f32),
+}
+
+impl MediaCommand {
+    pub fn parse(line: &str) -> Option<Self> {
+        match line.trim() {
+            "like" => Some(Self::Rate(1)),
+            "dislike" => Some(Self::Rate(-1)),
+            _ => QueryCommand::parse(line).map(Self::Query),
+        }
+    }
+}
 
 pub struct MediaHandler {
     pub config: Arc<MediaConfig>,
     pub media_source: Arc<MediaSource>,
-    pub path_queue: Vec<PathBuf>,
-    pub media_queue: Vec<Frame>,
+    // both in display order, consumed from the front
+    pub path_queue: VecDeque<PathBuf>,
+    pub media_queue: VecDeque<Frame>,
+    pub glitch: Glitch,
+    pub session: Session,
 
+    rng: StdRng,
     thread_counter: Arc<Mutex<i32>>,
     tx_graphic: Sender<Frame>,
     rx_graphic: Receiver<u8>,
-    tx_downloader: Sender<Frame>,
-    rx_downloader: Receiver<Frame>,
+    tx_command: Sender<MediaCommand>,
+    rx_command: Receiver<MediaCommand>,
+    tx_downloader: Sender<(usize, Frame)>,
+    rx_downloader: Receiver<(usize, Frame)>,
     tx_path_handler: Sender<Vec<PathBuf>>,
     rx_path_handler: Receiver<Vec<PathBuf>>,
+    // a media list is being built by the source
+    list_pending: bool,
+    // the pending list follow a query replaced since
+    list_stale: bool,
+    // the source had no media, it is asked again after this
+    retry_at: Option<Instant>,
+    // last media sent, shown again while the source is empty
+    shown: Vec<PathBuf>,
+    // media asked by the graphic side and not sent yet
+    owed: u8,
 }
 
 impl MediaHandler {
-    fn get_next_media(thread_counter: Arc<Mutex<i32>>, tx: Sender<Frame>, media_path: PathBuf) {
+    fn get_next_media(
+        thread_counter: Arc<Mutex<i32>>,
+        tx: Sender<(usize, Frame)>,
+        slot: usize,
+        media_path: PathBuf,
+    ) {
         thread::spawn(move || {
             let mut num = thread_counter.lock().unwrap();
             *num += 1;
-            tx.send(Frame::new(media_path)).unwrap();
+            tx.send((slot, Frame::new(media_path))).unwrap();
         });
     }
 
+    fn download(
+        paths: Vec<PathBuf>,
+        thread_counter: &Arc<Mutex<i32>>,
+        tx: &Sender<(usize, Frame)>,
+        rx: &Receiver<(usize, Frame)>,
+    ) -> Vec<Frame> {
+        /*
+            Decode the media in parallel, threads finish in any order
+            so each frame is put back in the slot of its path
+        */
+        let count = paths.len();
+        for (slot, path) in paths.into_iter().enumerate() {
+            Self::get_next_media(Arc::clone(thread_counter), tx.clone(), slot, path);
+        }
+        let mut slots: Vec<Option<Frame>> = (0..count).map(|_| None).collect();
+        for _ in 0..count {
+            if let Ok((slot, f)) = rx.recv() {
+                let mut num = thread_counter.lock().unwrap();
+                *num -= 1;
+                slots[slot] = Some(f);
+            }
+        }
+        slots.into_iter().flatten().collect()
+    }
+
     fn query_path_queue(
         tx: Sender<Vec<PathBuf>>,
         media_source: &Arc<MediaSource>,
@@ -45,27 +135,67 @@ impl MediaHandler {
         /*
         Spawn a thread to request a new media list than will extend the current one
         */
-        let c = Arc::clone(&config);
-        let ms = Arc::clone(&media_source);
+        let c = Arc::clone(config);
+        let ms = Arc::clone(media_source);
 
         thread::spawn(move || {
-            tx.send(ms.get_media_list(&*c)).unwrap();
+            tx.send(ms.get_media_list(&c)).unwrap();
         });
     }
 
-    fn get_async_path_queue(&self) -> Vec<PathBuf> {
-        let min_paths = 2 * ((self.config.max_threads as usize) - self.media_queue.len());
-        if self.path_queue.len() < min_paths {
+    fn source_resting(&self) -> bool {
+        self.retry_at.is_some_and(|t| Instant::now() < t)
+    }
+
+    fn get_async_path_queue(&mut self, wait: bool) -> bool {
+        /*
+            A new list is asked only once the current one is drained and none is pending,
+            sources like the drift move on at each list and mustn't be asked for nothing
+            Wait for the pending list when the media queue can't serve the display
+            False when the list received was dropped, built for a previous query
+        */
+        if self.path_queue.is_empty() && !self.list_pending && !self.source_resting() {
             Self::query_path_queue(
                 self.tx_path_handler.clone(),
                 &self.media_source,
                 &self.config,
             );
+            self.list_pending = true;
+        }
+        let received = match wait && self.list_pending {
+            true => self.rx_path_handler.recv().ok(),
+            false => self.rx_path_handler.try_recv().ok(),
+        };
+        if let Some(paths) = received {
+            self.list_pending = false;
+            if self.list_stale {
+                // built for the previous query
+                self.list_stale = false;
+                return false;
+            }
+            if paths.is_empty() {
+                if self.retry_at.is_none() {
+                    println!("No media from the source, the current media stay on screen");
+                }
+                self.retry_at = Some(Instant::now() + EMPTY_SOURCE_RETRY);
+            } else {
+                self.retry_at = None;
+            }
+            self.path_queue.extend(paths);
         }
-        match self.rx_path_handler.try_recv() {
-            Ok(p) => p,
-            Err(_) => vec![],
+        true
+    }
+
+    fn refill_path_queue(&mut self) -> bool {
+        /*
+            Wait for a list of the source, false if it has no media
+            An empty source is asked again only after a pause or a new query
+        */
+        if self.source_resting() {
+            return false;
         }
+        while !self.get_async_path_queue(true) {}
+        !self.path_queue.is_empty()
     }
 
     fn get_sync_path_queue(
@@ -74,26 +204,39 @@ impl MediaHandler {
         media_source: &Arc<MediaSource>,
         config: &Arc<MediaConfig>,
     ) -> Vec<PathBuf> {
-        Self::query_path_queue(tx.clone(), &media_source, config);
-        match rx.recv() {
-            Ok(p) => p,
-            Err(_) => vec![],
-        }
+        Self::query_path_queue(tx.clone(), media_source, config);
+        rx.recv().unwrap_or_default()
     }
 
-    pub fn new(config: MediaConfig, tx_graphic: Sender<Frame>, rx_graphic: Receiver<u8>) -> Self {
-        let media_source = Arc::new(MediaSource::Local(LocalMedia::new(&config)));
-        // let mut media_source = MediaSource::DB(PostgreSQLMedia::new(&config));
+    pub fn new(
+        config: MediaConfig,
+        tx_graphic: Sender<Frame>,
+        rx_graphic: Receiver<u8>,
+        session: Session,
+    ) -> Self {
+        let media_source = Arc::new(if config.use_database {
+            MediaSource::DB(Box::new(DatabaseMedia::new(
+                &config,
+                session.rng(SHUFFLE_STREAM),
+            )))
+        } else {
+            MediaSource::Local(Box::new(LocalMedia::new(
+                &config,
+                session.rng(SHUFFLE_STREAM),
+            )))
+        });
 
+        let glitch = Glitch::new(config.glitch.clone());
         let c = Arc::new(config);
         let thread_counter = Arc::new(Mutex::new(0));
         let (tx_downloader, rx_downloader) = mpsc::channel();
         let (tx_path_handler, rx_path_handler) = mpsc::channel();
+        let (tx_command, rx_command) = mpsc::channel();
 
-        let mut media_queue: Vec<Frame> = vec![];
-        let mut path_queue = vec![];
+        let mut path_queue = VecDeque::new();
+        let mut first_paths = vec![];
         for _ in 0..c.max_threads {
-            if path_queue.len() == 0 {
+            if path_queue.is_empty() {
                 path_queue.extend(Self::get_sync_path_queue(
                     tx_path_handler.clone(),
                     &rx_path_handler,
@@ -101,93 +244,139 @@ impl MediaHandler {
                     &c,
                 ));
             }
-            Self::get_next_media(
-                Arc::clone(&thread_counter),
-                tx_downloader.clone(),
-                path_queue.pop().unwrap(),
-            );
-        }
-        for _ in 0..c.max_threads {
-            match rx_downloader.recv() {
-                Ok(f) => {
-                    let mut num = thread_counter.lock().unwrap();
-                    *num -= 1;
-                    media_queue.push(f)
-                }
-                Err(_) => (),
-            };
+            match path_queue.pop_front() {
+                Some(path) => first_paths.push(path),
+                None => break,
+            }
         }
+        // filters matching nothing, the display wait for a new query
+        let retry_at = first_paths.is_empty().then(|| {
+            println!("No media from the source, waiting for a query matching some");
+            Instant::now() + EMPTY_SOURCE_RETRY
+        });
+        let media_queue: VecDeque<Frame> =
+            Self::download(first_paths, &thread_counter, &tx_downloader, &rx_downloader).into();
 
-        path_queue.extend(Self::get_sync_path_queue(
-            tx_path_handler.clone(),
-            &rx_path_handler,
-            &media_source,
-            &c,
-        ));
         MediaHandler {
             config: c,
             media_source,
             path_queue,
             media_queue,
+            glitch,
+            rng: session.rng(GLITCH_STREAM),
+            session,
             thread_counter,
             tx_graphic,
             rx_graphic,
+            tx_command,
+            rx_command,
             tx_downloader,
             rx_downloader,
             tx_path_handler,
             rx_path_handler,
+            list_pending: false,
+            list_stale: false,
+            retry_at,
+            shown: vec![],
+            owed: 0,
+        }
+    }
+
+    pub fn command_sender(&self) -> Sender<MediaCommand> {
+        self.tx_command.clone()
+    }
+
+    fn handle_command(&mut self, command: MediaCommand) {
+        // the audio level follow the logged audio events
+        if !matches!(command, MediaCommand::AudioLevel(_)) {
+            self.session.log(Event::Command {
+                command: command.clone(),
+            });
+        }
+        match command {
+            MediaCommand::Query(query) => {
+                // queued paths follow the previous query, the downloaded media are still shown
+                if self.media_source.apply_command(query) {
+                    self.path_queue.clear();
+                    self.list_stale = self.list_pending;
+                    self.retry_at = None;
+                }
+            }
+            MediaCommand::Rate(rating) => self.media_source.rate(rating),
+            MediaCommand::AudioLevel(level) => self.glitch.set_audio_level(level),
         }
     }
 
     fn handle_signal(&mut self, signal: u8) {
+        /*
+            The display is faster than the source, better late than a missing media
+            Without media from the source the last ones are sent again,
+            or the display wait if none was sent yet
+        */
+        while self.media_queue.len() < signal as usize {
+            if self.path_queue.is_empty() && !self.refill_path_queue() {
+                if self.shown.is_empty() {
+                    self.owed = signal;
+                    return;
+                }
+                let again = self.shown.iter().cycle().take(signal as usize).cloned();
+                self.path_queue.extend(again);
+            }
+            self.fill_media_queue((signal as usize).max(self.config.max_threads as usize));
+        }
+        self.owed = 0;
+
+        let mut shown = vec![];
         for _ in 0..signal {
-            self.tx_graphic
-                .send(self.media_queue.pop().unwrap())
-                .unwrap();
+            // glitch is applied in display order to smear consecutive frames
+            let mut media = self.media_queue.pop_front().unwrap();
+            media.tags = self.media_source.active_tags(&media);
+            self.glitch.apply(&mut media, &mut self.rng);
+            self.session.log(Event::Media {
+                path: media.path.clone(),
+                tags: media.tags.clone(),
+                glitch: media.glitch,
+            });
+            shown.push(media.path.clone());
+            self.tx_graphic.send(media).unwrap();
         }
+        self.media_source.record_shown(&shown);
+        self.shown = shown;
     }
 
-    fn fill_media_queue(&mut self) {
+    fn fill_media_queue(&mut self, wanted: usize) {
         let num = *Arc::clone(&self.thread_counter).lock().unwrap();
         let media_needed = std::cmp::min(
             self.path_queue.len(),
-            (self.config.max_threads as usize) - self.media_queue.len(),
+            wanted.saturating_sub(self.media_queue.len()),
         );
         // if too many threads or no need of new media
         if num > self.config.max_threads as i32 || media_needed == 0 {
             return;
         }
 
-        for _ in 0..media_needed {
-            let p = self.path_queue.pop().unwrap();
-            Self::get_next_media(
-                Arc::clone(&self.thread_counter),
-                self.tx_downloader.clone(),
-                p,
-            );
-        }
-        for _ in 0..media_needed {
-            match self.rx_downloader.recv() {
-                Ok(f) => {
-                    let mut num = self.thread_counter.lock().unwrap();
-                    *num -= 1;
-                    self.media_queue.push(f)
-                }
-                Err(_) => (),
-            };
-        }
+        let paths: Vec<PathBuf> = self.path_queue.drain(..media_needed).collect();
+        let frames = Self::download(
+            paths,
+            &self.thread_counter,
+            &self.tx_downloader,
+            &self.rx_downloader,
+        );
+        self.media_queue.extend(frames);
     }
 
     pub fn run(&mut self) {
         loop {
-            match self.rx_graphic.try_recv() {
-                Ok(v) => self.handle_signal(v),
-                Err(_) => (),
-            };
-            if self.path_queue.len() < 2 * (self.config.max_threads as usize) {
-                self.path_queue.extend(self.get_async_path_queue());
+            if let Ok(v) = self.rx_graphic.try_recv() {
+                self.handle_signal(v);
+            } else if self.owed > 0 && !self.source_resting() {
+                self.handle_signal(self.owed);
+            }
+            while let Ok(command) = self.rx_command.try_recv() {
+                self.handle_command(command);
             }
-            self.fill_media_queue();
+            self.get_async_path_queue(false);
+            self.fill_media_queue(self.config.max_threads as usize);
         }
     }
 }
@@ -3,11 +3,32 @@ use std::path::PathBuf;
 
 use yaml_rust::YamlLoader;
 
+use crate::embedding::{EmbedderConfig, WalkConfig};
+use crate::glitch::GlitchConfig;
+use crate::history::HistoryConfig;
+use crate::media_query::MediaQuery;
+use crate::perceptual::DuplicateConfig;
+use crate::search::SearchQuery;
+use crate::similarity::DriftConfig;
+use crate::tagger::TaggerConfig;
+
 #[derive(Debug)]
 pub struct MediaConfig {
     pub data_folder: PathBuf,
     pub max_threads: u32,
     pub database_url: String,
+    // database instead of the data folder
+    pub use_database: bool,
+    pub query: MediaQuery,
+    pub history: HistoryConfig,
+    // search played in order instead of the query
+    pub playlist: Option<SearchQuery>,
+    pub drift: DriftConfig,
+    pub walk: WalkConfig,
+    pub duplicates: DuplicateConfig,
+    pub tagger: TaggerConfig,
+    pub embedder: EmbedderConfig,
+    pub glitch: GlitchConfig,
 }
 
 impl MediaConfig {
@@ -54,6 +75,18 @@ impl MediaConfig {
             data_folder: Self::folder_exist(cfg["data_folder"].as_str().unwrap()),
             max_threads: cfg["max_threads"].as_i64().unwrap() as u32,
             database_url: String::from(cfg["database_url"].as_str().unwrap()),
+            use_database: cfg["use_database"].as_bool().unwrap(),
+            query: MediaQuery::new(&cfg["query"]),
+            history: HistoryConfig::new(&cfg["history"]),
+            drift: DriftConfig::new(&cfg["drift"]),
+            walk: WalkConfig::new(&cfg["walk"]),
+            duplicates: DuplicateConfig::new(&cfg["duplicates"]),
+            tagger: TaggerConfig::new(&cfg["tagger"]),
+            embedder: EmbedderConfig::new(&cfg["embedder"]),
+            playlist: cfg["playlist"].as_str().map(|q| {
+                SearchQuery::parse(q).unwrap_or_else(|e| panic!("Invalid playlist search: {}", e))
+            }),
+            glitch: GlitchConfig::new(&cfg["glitch"]),
         }
     }
 }
@@ -0,0 +1,475 @@
+use diesel::dsl::not;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use yaml_rust::Yaml;
+
+use crate::database::{with_connection, DbConnection};
+use crate::schema::*;
+use crate::sql_models::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagMatch {
+    // media carrying one of the included tags
+    Any,
+    // media carrying every included tag
+    All,
+}
+
+impl TagMatch {
+    pub fn new(name: &str) -> Self {
+        match name {
+            "any" => Self::Any,
+            "all" => Self::All,
+            _ => panic!("Unknown tag match '{}'", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+    Square,
+}
+
+impl Orientation {
+    pub fn new(name: &str) -> Self {
+        match name {
+            "landscape" => Self::Landscape,
+            "portrait" => Self::Portrait,
+            "square" => Self::Square,
+            _ => panic!("Unknown orientation '{}'", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryCommand {
+    Include(String),
+    Exclude(String),
+    // drop a tag from the included and excluded ones
+    Remove(String),
+    Match(TagMatch),
+    Formats(Vec<String>),
+    MinResolution(i32, i32),
+    Orientation(Option<Orientation>),
+    // back to the configured query
+    Reset,
+}
+
+impl QueryCommand {
+    pub fn parse(line: &str) -> Option<Self> {
+        /*
+            One command per line:
+            include <tag>, exclude <tag>, remove <tag>, match any|all,
+            formats PNG JPEG .., min <width>x<height>, orientation landscape|portrait|square|any, reset
+        */
+        let mut words = line.split_whitespace();
+        let command = words.next()?;
+        let args: Vec<&str> = words.collect();
+        let command = match (command, args.as_slice()) {
+            ("include", [tag]) => Self::Include(tag.to_string()),
+            ("exclude", [tag]) => Self::Exclude(tag.to_string()),
+            ("remove", [tag]) => Self::Remove(tag.to_string()),
+            ("match", ["any"]) => Self::Match(TagMatch::Any),
+            ("match", ["all"]) => Self::Match(TagMatch::All),
+            ("formats", formats) => {
+                Self::Formats(formats.iter().map(|f| f.to_uppercase()).collect())
+            }
+            ("min", [resolution]) => {
+                let (w, h) = resolution.split_once('x')?;
+                Self::MinResolution(w.parse().ok()?, h.parse().ok()?)
+            }
+            ("orientation", ["any"]) => Self::Orientation(None),
+            ("orientation", [o @ ("landscape" | "portrait" | "square")]) => {
+                Self::Orientation(Some(Orientation::new(o)))
+            }
+            ("reset", []) => Self::Reset,
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaQuery {
+    pub include_tags: Vec<String>,
+    pub exclude_tags: Vec<String>,
+    pub tag_match: TagMatch,
+    // every format when empty
+    pub formats: Vec<String>,
+    pub min_width: i32,
+    pub min_height: i32,
+    pub orientation: Option<Orientation>,
+}
+
+impl MediaQuery {
+    pub fn new(cfg: &Yaml) -> Self {
+        let strings = |key: &str| -> Vec<String> {
+            cfg[key]
+                .as_vec()
+                .unwrap()
+                .iter()
+                .map(|v| String::from(v.as_str().unwrap()))
+                .collect()
+        };
+        Self {
+            include_tags: strings("include_tags"),
+            exclude_tags: strings("exclude_tags"),
+            tag_match: TagMatch::new(cfg["match"].as_str().unwrap()),
+            formats: strings("formats"),
+            min_width: cfg["min_width"].as_i64().unwrap() as i32,
+            min_height: cfg["min_height"].as_i64().unwrap() as i32,
+            orientation: cfg["orientation"].as_str().map(Orientation::new),
+        }
+    }
+
+    pub fn apply(&mut self, command: QueryCommand, default: &MediaQuery) {
+        match command {
+            QueryCommand::Include(tag) => {
+                self.exclude_tags.retain(|t| *t != tag);
+                if !self.include_tags.contains(&tag) {
+                    self.include_tags.push(tag);
+                }
+            }
+            QueryCommand::Exclude(tag) => {
+                self.include_tags.retain(|t| *t != tag);
+                if !self.exclude_tags.contains(&tag) {
+                    self.exclude_tags.push(tag);
+                }
+            }
+            QueryCommand::Remove(tag) => {
+                self.include_tags.retain(|t| *t != tag);
+                self.exclude_tags.retain(|t| *t != tag);
+            }
+            QueryCommand::Match(tag_match) => self.tag_match = tag_match,
+            QueryCommand::Formats(formats) => self.formats = formats,
+            QueryCommand::MinResolution(w, h) => {
+                self.min_width = w;
+                self.min_height = h;
+            }
+            QueryCommand::Orientation(orientation) => self.orientation = orientation,
+            QueryCommand::Reset => *self = default.clone(),
+        }
+    }
+
+    pub fn load(&self, connection: &mut DbConnection) -> Vec<Media> {
+        /*
+            Tags are matched with subqueries on media_tag,
+            a media is returned once whatever its number of tags
+            Media without known size are dropped by the resolution and orientation filters
+        */
+        let with_tags = |tags: Vec<String>| {
+            media_tag::table
+                .inner_join(tag::table)
+                .filter(tag::name.eq_any(tags))
+                .select(media_tag::media_id)
+        };
+
+        with_connection!(connection, conn => {
+            let mut query = media::table
+                .inner_join(format::table)
+                .select(Media::as_select())
+                .into_boxed();
+
+            if !self.include_tags.is_empty() {
+                match self.tag_match {
+                    TagMatch::Any => {
+                        query =
+                            query.filter(media::id.eq_any(with_tags(self.include_tags.clone())));
+                    }
+                    TagMatch::All => {
+                        for tag in &self.include_tags {
+                            query = query.filter(media::id.eq_any(with_tags(vec![tag.clone()])));
+                        }
+                    }
+                }
+            }
+            if !self.exclude_tags.is_empty() {
+                query =
+                    query.filter(not(media::id.eq_any(with_tags(self.exclude_tags.clone()))));
+            }
+            if !self.formats.is_empty() {
+                query = query.filter(format::name.eq_any(self.formats.clone()));
+            }
+            if self.min_width > 0 {
+                query = query.filter(media::width.ge(self.min_width));
+            }
+            if self.min_height > 0 {
+                query = query.filter(media::height.ge(self.min_height));
+            }
+            query = match self.orientation {
+                Some(Orientation::Landscape) => query.filter(media::width.gt(media::height)),
+                Some(Orientation::Portrait) => query.filter(media::width.lt(media::height)),
+                Some(Orientation::Square) => query.filter(media::width.eq(media::height)),
+                None => query,
+            };
+
+            query.load(conn).expect("Failed request")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_databases;
+
+    // url, format, width, height and tags of each media
+    const MEDIA: [(&str, &str, i32, i32, &[&str]); 5] = [
+        ("beach.jpeg", "JPEG", 1920, 1080, &["sea", "sun"]),
+        ("cliff.png", "PNG", 1080, 1920, &["sea"]),
+        ("desert.jpeg", "JPEG", 800, 800, &["sun", "sand"]),
+        ("forest.png", "PNG", 640, 480, &["tree"]),
+        ("untagged.jpeg", "JPEG", 300, 400, &[]),
+    ];
+
+    fn fill(mut connection: DbConnection) -> DbConnection {
+        with_connection!(&mut connection, conn => {
+            for (url, format_name, width, height, tags) in MEDIA {
+                diesel::insert_into(format::table)
+                    .values(format::name.eq(format_name))
+                    .on_conflict(format::name)
+                    .do_nothing()
+                    .execute(conn)
+                    .unwrap();
+                let format_id: i32 = format::table
+                    .filter(format::name.eq(format_name))
+                    .select(format::id)
+                    .first(conn)
+                    .unwrap();
+                diesel::insert_into(media::table)
+                    .values((
+                        media::url.eq(url),
+                        media::format_id.eq(format_id),
+                        media::width.eq(width),
+                        media::height.eq(height),
+                    ))
+                    .execute(conn)
+                    .unwrap();
+                let media_id: i32 = media::table
+                    .filter(media::url.eq(url))
+                    .select(media::id)
+                    .first(conn)
+                    .unwrap();
+                for name in tags {
+                    diesel::insert_into(tag::table)
+                        .values(tag::name.eq(name))
+                        .on_conflict(tag::name)
+                        .do_nothing()
+                        .execute(conn)
+                        .unwrap();
+                    let tag_id: i32 = tag::table
+                        .filter(tag::name.eq(name))
+                        .select(tag::id)
+                        .first(conn)
+                        .unwrap();
+                    diesel::insert_into(media_tag::table)
+                        .values(MediaTag {
+                            media_id,
+                            tag_id,
+                            confidence: None,
+                        })
+                        .execute(conn)
+                        .unwrap();
+                }
+            }
+        });
+        connection
+    }
+
+    fn unfiltered() -> MediaQuery {
+        MediaQuery {
+            include_tags: vec![],
+            exclude_tags: vec![],
+            tag_match: TagMatch::Any,
+            formats: vec![],
+            min_width: 0,
+            min_height: 0,
+            orientation: None,
+        }
+    }
+
+    fn urls(query: &MediaQuery) -> Vec<String> {
+        // the same media on every backend
+        let mut results = test_databases().into_iter().map(|connection| {
+            let mut urls: Vec<String> = query
+                .load(&mut fill(connection))
+                .into_iter()
+                .map(|m| m.url)
+                .collect();
+            urls.sort();
+            urls
+        });
+        let urls = results.next().unwrap();
+        for other in results {
+            assert_eq!(other, urls);
+        }
+        urls
+    }
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            QueryCommand::parse("include sea"),
+            Some(QueryCommand::Include(String::from("sea")))
+        );
+        assert_eq!(
+            QueryCommand::parse("  exclude   sun "),
+            Some(QueryCommand::Exclude(String::from("sun")))
+        );
+        assert_eq!(
+            QueryCommand::parse("remove sea"),
+            Some(QueryCommand::Remove(String::from("sea")))
+        );
+        assert_eq!(
+            QueryCommand::parse("match all"),
+            Some(QueryCommand::Match(TagMatch::All))
+        );
+        assert_eq!(
+            QueryCommand::parse("formats png Jpeg"),
+            Some(QueryCommand::Formats(tags(&["PNG", "JPEG"])))
+        );
+        assert_eq!(
+            QueryCommand::parse("formats"),
+            Some(QueryCommand::Formats(vec![]))
+        );
+        assert_eq!(
+            QueryCommand::parse("min 1920x1080"),
+            Some(QueryCommand::MinResolution(1920, 1080))
+        );
+        assert_eq!(
+            QueryCommand::parse("orientation portrait"),
+            Some(QueryCommand::Orientation(Some(Orientation::Portrait)))
+        );
+        assert_eq!(
+            QueryCommand::parse("orientation any"),
+            Some(QueryCommand::Orientation(None))
+        );
+        assert_eq!(QueryCommand::parse("reset"), Some(QueryCommand::Reset));
+    }
+
+    #[test]
+    fn parse_invalid_commands() {
+        for line in [
+            "",
+            "include",
+            "include sea sun",
+            "match some",
+            "min 1920",
+            "min widexhigh",
+            "orientation diagonal",
+            "reset now",
+            "like",
+        ] {
+            assert_eq!(QueryCommand::parse(line), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn load_every_media() {
+        assert_eq!(urls(&unfiltered()).len(), MEDIA.len());
+    }
+
+    #[test]
+    fn load_any_tag() {
+        let query = MediaQuery {
+            include_tags: tags(&["sand", "tree"]),
+            ..unfiltered()
+        };
+        assert_eq!(urls(&query), ["desert.jpeg", "forest.png"]);
+    }
+
+    #[test]
+    fn load_all_tags() {
+        let query = MediaQuery {
+            include_tags: tags(&["sea", "sun"]),
+            tag_match: TagMatch::All,
+            ..unfiltered()
+        };
+        assert_eq!(urls(&query), ["beach.jpeg"]);
+    }
+
+    #[test]
+    fn load_media_once() {
+        // beach carries both tags
+        let query = MediaQuery {
+            include_tags: tags(&["sea", "sun"]),
+            ..unfiltered()
+        };
+        assert_eq!(urls(&query), ["beach.jpeg", "cliff.png", "desert.jpeg"]);
+    }
+
+    #[test]
+    fn load_without_excluded_tags() {
+        let query = MediaQuery {
+            exclude_tags: tags(&["sea", "tree"]),
+            ..unfiltered()
+        };
+        assert_eq!(urls(&query), ["desert.jpeg", "untagged.jpeg"]);
+    }
+
+    #[test]
+    fn load_included_and_excluded_tags() {
+        let query = MediaQuery {
+            include_tags: tags(&["sun"]),
+            exclude_tags: tags(&["sea"]),
+            ..unfiltered()
+        };
+        assert_eq!(urls(&query), ["desert.jpeg"]);
+    }
+
+    #[test]
+    fn load_formats_and_resolution() {
+        let query = MediaQuery {
+            formats: tags(&["PNG"]),
+            ..unfiltered()
+        };
+        assert_eq!(urls(&query), ["cliff.png", "forest.png"]);
+        let query = MediaQuery {
+            min_width: 800,
+            min_height: 800,
+            ..unfiltered()
+        };
+        assert_eq!(urls(&query), ["beach.jpeg", "cliff.png", "desert.jpeg"]);
+    }
+
+    #[test]
+    fn load_orientation() {
+        let oriented = |orientation| MediaQuery {
+            orientation: Some(orientation),
+            ..unfiltered()
+        };
+        assert_eq!(
+            urls(&oriented(Orientation::Landscape)),
+            ["beach.jpeg", "forest.png"]
+        );
+        assert_eq!(
+            urls(&oriented(Orientation::Portrait)),
+            ["cliff.png", "untagged.jpeg"]
+        );
+        assert_eq!(urls(&oriented(Orientation::Square)), ["desert.jpeg"]);
+    }
+
+    #[test]
+    fn apply_commands() {
+        let default = unfiltered();
+        let mut query = unfiltered();
+        query.apply(QueryCommand::Include(String::from("sea")), &default);
+        query.apply(QueryCommand::Exclude(String::from("sun")), &default);
+        query.apply(QueryCommand::Exclude(String::from("sea")), &default);
+        assert!(query.include_tags.is_empty());
+        assert_eq!(query.exclude_tags, tags(&["sun", "sea"]));
+        query.apply(QueryCommand::Remove(String::from("sun")), &default);
+        assert_eq!(query.exclude_tags, tags(&["sea"]));
+        query.apply(QueryCommand::Reset, &default);
+        assert_eq!(query, default);
+    }
+}
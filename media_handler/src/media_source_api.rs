@@ -1,22 +1,25 @@
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
 
-use diesel;
-
-use crate::schema::*;
-use crate::sql_models::*;
-use diesel::pg::PgConnection;
-use diesel::prelude::*;
-
+use crate::database::DbConnection;
+use crate::embedding::Embedding;
+use crate::frame::Frame;
+use crate::history::HistoryTracker;
 use crate::media_config::MediaConfig;
+use crate::media_query::{MediaQuery, QueryCommand};
+use crate::perceptual::DuplicateSpacer;
+use crate::search::SearchQuery;
+use crate::similarity::{Histogram, NeighborWalk};
+use crate::sql_models::Media;
 
 pub enum MediaSource {
-    Local(LocalMedia),
-    DB(PostgreSQLMedia),
+    // boxed, both carry a random generator and the database one much more
+    Local(Box<LocalMedia>),
+    DB(Box<DatabaseMedia>),
 }
 
 /*
@@ -34,14 +37,54 @@ pub enum MediaSource {
 impl MediaSource {
     pub fn get_media_list(&self, config: &MediaConfig) -> Vec<PathBuf> {
         match self {
-            Self::Local(m) => m.get_media_list(&config),
-            Self::DB(m) => m.get_media_list(&config),
+            Self::Local(m) => m.get_media_list(config),
+            Self::DB(m) => m.get_media_list(config),
+        }
+    }
+
+    pub fn active_tags(&self, media: &Frame) -> Vec<String> {
+        /*
+            Tags filtering the media currently sent,
+            without filter the color tags of the media stand for them
+        */
+        match self {
+            Self::Local(_) => media.palette.color_tags(),
+            Self::DB(m) => match &m.playlist {
+                Some(playlist) => playlist.tags(),
+                None => m.query.lock().unwrap().include_tags.clone(),
+            },
+        }
+    }
+
+    pub fn record_shown(&self, paths: &[PathBuf]) {
+        match self {
+            Self::Local(_) => (),
+            Self::DB(m) => m.record_shown(paths),
+        }
+    }
+
+    pub fn rate(&self, rating: i32) {
+        match self {
+            Self::Local(_) => println!("Ratings need the database source"),
+            Self::DB(m) => m.rate(rating),
+        }
+    }
+
+    pub fn apply_command(&self, command: QueryCommand) -> bool {
+        match self {
+            Self::Local(_) => {
+                println!("Media queries need the database source: {:?}", command);
+                false
+            }
+            Self::DB(m) => m.apply_command(command),
         }
     }
 }
 
 pub struct LocalMedia {
     media_paths: Vec<PathBuf>,
+    // session random stream, lists are asked from several threads
+    rng: Mutex<StdRng>,
 }
 
 impl LocalMedia {
@@ -49,64 +92,152 @@ impl LocalMedia {
         fs::read_dir(folder_path)
             .unwrap()
             .map(|p| p.unwrap().path())
-            .filter(|f| f.is_file())
+            .filter(|f| Frame::is_image(f))
             .collect()
     }
 
-    pub fn new(config: &MediaConfig) -> Self {
+    pub fn new(config: &MediaConfig, rng: StdRng) -> Self {
         Self {
             media_paths: Self::get_media_paths(&config.data_folder),
+            rng: Mutex::new(rng),
         }
     }
 
-    pub fn get_media_list(&self, config: &MediaConfig) -> Vec<PathBuf> {
-        let mut rng = thread_rng();
+    pub fn get_media_list(&self, _config: &MediaConfig) -> Vec<PathBuf> {
         let mut x = self.media_paths.clone();
-        x.shuffle(&mut rng);
+        x.shuffle(&mut *self.rng.lock().unwrap());
         x
     }
 }
 
-pub struct PostgreSQLMedia {
-    connection: Arc<Mutex<PgConnection>>,
-    // connection: PgConnection,
+pub struct DatabaseMedia {
+    // changed by the live commands, lists are asked from several threads
+    pub query: Mutex<MediaQuery>,
+    default_query: MediaQuery,
+    playlist: Option<SearchQuery>,
+    // walk through the colors among the query media
+    drift: Option<Mutex<NeighborWalk<Histogram>>>,
+    // walk through the aesthetics, between neighbors of the embeddings
+    walk: Option<Mutex<NeighborWalk<Embedding>>>,
+    history: Mutex<HistoryTracker>,
+    // keep near-duplicates apart, across the lists
+    spacer: Mutex<DuplicateSpacer>,
+    rng: Mutex<StdRng>,
+    connection: Arc<Mutex<DbConnection>>,
 }
 
-impl PostgreSQLMedia {
+impl DatabaseMedia {
     fn query_data(&self) -> Vec<PathBuf> {
+        /*
+            The playlist search in order, a drift through the colors, a walk between neighbors,
+            or a weighted random order from the history: liked media come more often,
+            disliked ones rarely, and the recently shown ones wait for the cool-down
+            Near-duplicates are kept apart except in the playlist, the drift and the walk
+            Lists are in display order
+        */
         let mut conn = self.connection.lock().unwrap();
+        if let Some(playlist) = &self.playlist {
+            return Self::playlist_data(playlist, &mut conn);
+        }
+        let query = self.query.lock().unwrap().clone();
+        let media = query.load(&mut conn);
+        let steps = {
+            let mut rng = self.rng.lock().unwrap();
+            match (&self.drift, &self.walk) {
+                (Some(drift), _) => Some(drift.lock().unwrap().next_media(&media, &mut rng)),
+                (None, Some(walk)) => Some(walk.lock().unwrap().next_media(&media, &mut rng)),
+                (None, None) => None,
+            }
+        };
+        if let Some(steps) = steps {
+            // consecutive steps are neighbors, the spacer would break the chain
+            return steps.iter().map(|m| PathBuf::from(&m.url)).collect();
+        }
+        let weights = self.history.lock().unwrap().weights(&mut conn, &media);
 
-        let formats = vec!["PNG", "JPEG"];
-        let wanted_formats = format::table
-            .filter(format::name.eq_any(formats))
-            .select(Format::as_select())
-            .load(&mut *conn)
-            .expect("Failed request");
-
-        let tags = vec!["TEST", "oUI"];
-        // media with a specific format AND a specific tag
-        let medias_queue: Vec<PathBuf> = Media::belonging_to(&wanted_formats)
-            .inner_join(tag::table.on(tag::name.eq_any(tags)))
-            .select(Media::as_select())
-            .load(&mut *conn)
-            .expect("Failed request")
-            .into_iter()
-            .map(|m| PathBuf::from(m.url))
+        let mut candidates: Vec<(&Media, f32)> = media
+            .iter()
+            .zip(&weights)
+            .filter(|(_, (_, cooling))| !cooling)
+            .map(|(m, (w, _))| (m, *w))
+            .collect();
+        if candidates.is_empty() {
+            // everything was shown recently, better a repeat than nothing
+            candidates = media.iter().zip(weights.iter().map(|w| w.0)).collect();
+        }
+
+        let mut rng = self.rng.lock().unwrap();
+        let ordered: Vec<&Media> = candidates
+            .choose_multiple_weighted(&mut *rng, candidates.len(), |(_, w)| *w)
+            .expect("Invalid media weights")
+            .map(|(m, _)| *m)
             .collect();
+        self.spaced_paths(ordered)
+    }
 
-        medias_queue
+    fn spaced_paths(&self, ordered: Vec<&Media>) -> Vec<PathBuf> {
+        // near-duplicates pushed apart
+        self.spacer
+            .lock()
+            .unwrap()
+            .spread(ordered)
+            .iter()
+            .map(|m| PathBuf::from(&m.url))
+            .collect()
+    }
+
+    fn playlist_data(playlist: &SearchQuery, conn: &mut DbConnection) -> Vec<PathBuf> {
+        // search results in order, the whole list is played before the search runs again
+        playlist
+            .run(conn)
+            .into_iter()
+            .map(|r| PathBuf::from(r.media.url))
+            .collect()
     }
 
-    pub fn new(config: &MediaConfig) -> Self {
-        let connection = PgConnection::establish(&config.database_url)
-            .unwrap_or_else(|_| panic!("Error connecting to {}", config.database_url));
+    pub fn new(config: &MediaConfig, rng: StdRng) -> Self {
+        let connection = DbConnection::establish(&config.database_url);
 
         Self {
+            query: Mutex::new(config.query.clone()),
+            default_query: config.query.clone(),
+            playlist: config.playlist.clone(),
+            drift: config
+                .drift
+                .enabled
+                .then(|| Mutex::new(config.drift.start())),
+            walk: config.walk.enabled.then(|| Mutex::new(config.walk.start())),
+            history: Mutex::new(HistoryTracker::new(&config.history)),
+            spacer: Mutex::new(DuplicateSpacer::new(&config.duplicates)),
+            rng: Mutex::new(rng),
             connection: Arc::new(Mutex::new(connection)),
         }
     }
 
-    pub fn get_media_list(&self, config: &MediaConfig) -> Vec<PathBuf> {
+    pub fn apply_command(&self, command: QueryCommand) -> bool {
+        // a query without any media would starve the display, it is refused
+        let mut query = self.query.lock().unwrap().clone();
+        query.apply(command, &self.default_query);
+        if query.load(&mut self.connection.lock().unwrap()).is_empty() {
+            println!("No media match {:?}, the query is unchanged", query);
+            return false;
+        }
+        println!("Media query: {:?}", query);
+        *self.query.lock().unwrap() = query;
+        true
+    }
+
+    pub fn record_shown(&self, paths: &[PathBuf]) {
+        let mut conn = self.connection.lock().unwrap();
+        self.history.lock().unwrap().record(&mut conn, paths);
+    }
+
+    pub fn rate(&self, rating: i32) {
+        let mut conn = self.connection.lock().unwrap();
+        self.history.lock().unwrap().rate(&mut conn, rating);
+    }
+
+    pub fn get_media_list(&self, _config: &MediaConfig) -> Vec<PathBuf> {
         self.query_data()
     }
 }
@@ -0,0 +1,245 @@
+use image::DynamicImage;
+
+// number of colors kept per frame, must match the shaders `palette` uniform size
+pub const PALETTE_SIZE: usize = 5;
+// images are downscaled before the extraction, no need of every pixel
+const THUMBNAIL_SIZE: u32 = 64;
+// every tag `color_tags` can give, the indexer replace them when a file change
+pub const COLOR_TAGS: [&str; 12] = [
+    "dark",
+    "bright",
+    "monochrome",
+    "saturated",
+    "red",
+    "orange",
+    "yellow",
+    "green",
+    "cyan",
+    "blue",
+    "purple",
+    "pink",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Palette {
+    // dominant colors, sorted by population
+    pub colors: Vec<[u8; 3]>,
+    // average luminance between 0 and 1
+    pub luminance: f32,
+    // average saturation between 0 and 1
+    pub saturation: f32,
+}
+
+impl Palette {
+    fn channel_range(pixels: &[[u8; 3]], channel: usize) -> u8 {
+        let (min, max) = pixels.iter().fold((u8::MAX, u8::MIN), |(min, max), p| {
+            (min.min(p[channel]), max.max(p[channel]))
+        });
+        max.saturating_sub(min)
+    }
+
+    fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+        (0..3)
+            .map(|c| (c, Self::channel_range(pixels, c)))
+            .max_by_key(|(_, range)| *range)
+            .unwrap()
+    }
+
+    fn average(pixels: &[[u8; 3]]) -> [u8; 3] {
+        let mut sum = [0_u64; 3];
+        for p in pixels {
+            for c in 0..3 {
+                sum[c] += p[c] as u64;
+            }
+        }
+        let n = pixels.len().max(1) as u64;
+        [(sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8]
+    }
+
+    pub fn median_cut(mut pixels: Vec<[u8; 3]>, size: usize) -> Vec<[u8; 3]> {
+        /*
+            Split recursively the box with the widest channel range at its median
+            until there is `size` boxes, each box average is a palette color
+        */
+        if pixels.is_empty() {
+            return vec![];
+        }
+        let mut boxes = vec![std::mem::take(&mut pixels)];
+        while boxes.len() < size {
+            let candidate = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.len() > 1)
+                .map(|(i, b)| (i, Self::widest_channel(b)))
+                .max_by_key(|(_, (_, range))| *range);
+
+            let (i, channel) = match candidate {
+                Some((i, (channel, range))) if range > 0 => (i, channel),
+                _ => break,
+            };
+            let mut b = boxes.swap_remove(i);
+            b.sort_unstable_by_key(|p| p[channel]);
+            let upper = b.split_off(b.len() / 2);
+            boxes.push(b);
+            boxes.push(upper);
+        }
+
+        // a median inside a run of one color split it in boxes of the same average, merged back
+        let mut colors: Vec<([u8; 3], usize)> = vec![];
+        for b in &boxes {
+            let color = Self::average(b);
+            match colors.iter_mut().find(|(c, _)| *c == color) {
+                Some((_, n)) => *n += b.len(),
+                None => colors.push((color, b.len())),
+            }
+        }
+        colors.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        colors.into_iter().map(|(c, _)| c).collect()
+    }
+
+    pub fn luminance(p: &[u8; 3]) -> f32 {
+        (0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32) / 255.
+    }
+
+    pub fn saturation(p: &[u8; 3]) -> f32 {
+        let max = *p.iter().max().unwrap() as f32;
+        let min = *p.iter().min().unwrap() as f32;
+        if max == 0. {
+            0.
+        } else {
+            (max - min) / max
+        }
+    }
+
+    fn hue(p: &[u8; 3]) -> f32 {
+        let [r, g, b] = p.map(|c| c as f32 / 255.);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        if delta == 0. {
+            return 0.;
+        }
+        let h = if max == r {
+            ((g - b) / delta).rem_euclid(6.)
+        } else if max == g {
+            (b - r) / delta + 2.
+        } else {
+            (r - g) / delta + 4.
+        };
+        h * 60.
+    }
+
+    pub fn new(image: &DynamicImage, size: usize) -> Self {
+        let pixels: Vec<[u8; 3]> = image
+            .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+            .into_rgb8()
+            .pixels()
+            .map(|p| p.0)
+            .collect();
+        let n = pixels.len().max(1) as f32;
+
+        Self {
+            luminance: pixels.iter().map(Self::luminance).sum::<f32>() / n,
+            saturation: pixels.iter().map(Self::saturation).sum::<f32>() / n,
+            colors: Self::median_cut(pixels, size),
+        }
+    }
+
+    pub fn dominant(&self) -> [u8; 3] {
+        self.colors.first().copied().unwrap_or([0, 0, 0])
+    }
+
+    pub fn normalized(&self) -> Vec<[f32; 3]> {
+        /*
+            Colors as float for the shaders uniforms, padded to PALETTE_SIZE
+            by repeating the colors found, black only for an empty palette
+        */
+        if self.colors.is_empty() {
+            return vec![[0., 0., 0.]; PALETTE_SIZE];
+        }
+        (0..PALETTE_SIZE)
+            .map(|i| self.colors[i % self.colors.len()].map(|v| v as f32 / 255.))
+            .collect()
+    }
+
+    pub fn color_tags(&self) -> Vec<String> {
+        /*
+            Human readable tags: tone, saturation and dominant hue
+            Stored by the indexer, local media carry them instead of query tags
+        */
+        let mut tags = vec![];
+        if self.luminance < 0.25 {
+            tags.push("dark");
+        } else if self.luminance > 0.75 {
+            tags.push("bright");
+        }
+        if self.saturation < 0.15 {
+            tags.push("monochrome");
+        } else if self.saturation > 0.6 {
+            tags.push("saturated");
+        }
+
+        let dominant = self.dominant();
+        if Self::saturation(&dominant) >= 0.15 {
+            tags.push(match Self::hue(&dominant) as u32 {
+                0..=14 | 346..=360 => "red",
+                15..=44 => "orange",
+                45..=69 => "yellow",
+                70..=159 => "green",
+                160..=199 => "cyan",
+                200..=259 => "blue",
+                260..=289 => "purple",
+                _ => "pink",
+            });
+        }
+        tags.into_iter().map(String::from).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn two_colors() -> DynamicImage {
+        // left blue, right yellow, the blue part larger, at the thumbnail size not to blend them
+        DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, _| match x < 40 {
+            true => Rgb([0, 0, 255]),
+            false => Rgb([255, 255, 0]),
+        }))
+    }
+
+    #[test]
+    fn two_colors_found() {
+        let palette = Palette::new(&two_colors(), PALETTE_SIZE);
+        assert_eq!(palette.colors, [[0, 0, 255], [255, 255, 0]]);
+        assert_eq!(palette.dominant(), [0, 0, 255]);
+        assert_eq!(palette.color_tags(), ["saturated", "blue"]);
+    }
+
+    #[test]
+    fn pure_red_tags() {
+        let red = DynamicImage::ImageRgb8(RgbImage::from_pixel(10, 10, Rgb([255, 0, 0])));
+        let palette = Palette::new(&red, PALETTE_SIZE);
+        assert_eq!(palette.colors, [[255, 0, 0]]);
+        assert_eq!(palette.saturation, 1.);
+        // red is a dark color for the luminance
+        assert_eq!(palette.color_tags(), ["dark", "saturated", "red"]);
+
+        let gray = DynamicImage::ImageRgb8(RgbImage::from_pixel(10, 10, Rgb([20, 20, 20])));
+        assert_eq!(
+            Palette::new(&gray, PALETTE_SIZE).color_tags(),
+            ["dark", "monochrome"]
+        );
+    }
+
+    #[test]
+    fn normalized_repeat_the_colors() {
+        let palette = Palette::new(&two_colors(), PALETTE_SIZE);
+        let (blue, yellow) = ([0., 0., 1.], [1., 1., 0.]);
+        assert_eq!(palette.normalized(), [blue, yellow, blue, yellow, blue]);
+        assert_eq!(
+            Palette::default().normalized(),
+            [[0., 0., 0.]; PALETTE_SIZE]
+        );
+    }
+}
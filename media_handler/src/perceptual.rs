@@ -0,0 +1,236 @@
+use std::collections::VecDeque;
+
+use diesel::prelude::*;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use yaml_rust::Yaml;
+
+use crate::database::{with_connection, DbConnection};
+use crate::schema::*;
+use crate::sql_models::Media;
+
+pub fn dhash(image: &DynamicImage) -> i64 {
+    /*
+        Difference hash: one bit per horizontal neighbors of a 9x8 grayscale thumbnail,
+        re-encoded or resized copies keep most of their bits
+    */
+    let small = image.resize_exact(9, 8, FilterType::Triangle).into_luma8();
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x + 1, y)[0] > small.get_pixel(x, y)[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    // stored in a signed database column
+    hash as i64
+}
+
+pub fn hamming(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+pub fn load_hashed(connection: &mut DbConnection) -> Vec<Media> {
+    // every media of the database with a hash, whatever the query
+    with_connection!(connection, conn => {
+        media::table
+            .filter(media::phash.is_not_null())
+            .select(Media::as_select())
+            .order(media::url)
+            .load(conn)
+            .expect("Failed request")
+    })
+}
+
+pub fn duplicate_groups(media: &[Media], max_distance: u32) -> Vec<Vec<&Media>> {
+    /*
+        Media linked by a hash distance under the limit, directly or through others
+        Groups of a single media are left out
+    */
+    let hashed: Vec<(&Media, i64)> = media
+        .iter()
+        .filter_map(|m| m.phash.map(|h| (m, h)))
+        .collect();
+    let mut group_of: Vec<usize> = (0..hashed.len()).collect();
+    let root = |group_of: &Vec<usize>, mut i: usize| {
+        while group_of[i] != i {
+            i = group_of[i];
+        }
+        i
+    };
+    for i in 0..hashed.len() {
+        for j in i + 1..hashed.len() {
+            if hamming(hashed[i].1, hashed[j].1) <= max_distance {
+                let (a, b) = (root(&group_of, i), root(&group_of, j));
+                group_of[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<&Media>> = vec![vec![]; hashed.len()];
+    for (i, (m, _)) in hashed.iter().enumerate() {
+        groups[root(&group_of, i)].push(m);
+    }
+    groups.retain(|g| g.len() > 1);
+    groups
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateConfig {
+    // hash distance under which two media are near-duplicates
+    pub distance: u32,
+    // media shown between two near-duplicates
+    pub spacing: usize,
+}
+
+impl DuplicateConfig {
+    pub fn new(cfg: &Yaml) -> Self {
+        Self {
+            distance: cfg["distance"].as_i64().unwrap() as u32,
+            spacing: cfg["spacing"].as_i64().unwrap() as usize,
+        }
+    }
+}
+
+pub struct DuplicateSpacer {
+    config: DuplicateConfig,
+    // hashes of the last media placed, from one list to the next
+    recent: VecDeque<i64>,
+}
+
+impl DuplicateSpacer {
+    pub fn new(config: &DuplicateConfig) -> Self {
+        Self {
+            config: config.clone(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn is_spaced(&self, media: &Media) -> bool {
+        match media.phash {
+            Some(h) => self
+                .recent
+                .iter()
+                .all(|r| hamming(*r, h) > self.config.distance),
+            None => true,
+        }
+    }
+
+    pub fn spread<'a>(&mut self, media: Vec<&'a Media>) -> Vec<&'a Media> {
+        /*
+            Keep the display order but push back a media
+            while a near-duplicate was placed less than `spacing` media before
+        */
+        let mut pending: VecDeque<&Media> = media.into();
+        let mut ordered = vec![];
+        while !pending.is_empty() {
+            // nothing fit when only duplicates are left, they are placed anyway
+            let next = pending.iter().position(|m| self.is_spaced(m)).unwrap_or(0);
+            let m = pending.remove(next).unwrap();
+            if let Some(h) = m.phash {
+                self.recent.push_back(h);
+                if self.recent.len() > self.config.spacing {
+                    self.recent.pop_front();
+                }
+            }
+            ordered.push(m);
+        }
+        ordered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn pattern(width: u32, height: u32) -> DynamicImage {
+        // smooth shapes, the same picture at every size
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let (u, v) = (x as f32 / width as f32, y as f32 / height as f32);
+            let wave = ((u * 7.).sin() * (v * 5.).cos() + 1.) * 127.;
+            Rgb([wave as u8, (u * 255.) as u8, (v * 255.) as u8])
+        }))
+    }
+
+    fn media(url: &str, phash: i64) -> Media {
+        Media {
+            id: 0,
+            url: url.to_string(),
+            format_id: 0,
+            width: None,
+            height: None,
+            hash: None,
+            added_at: Default::default(),
+            palette: None,
+            histogram: None,
+            phash: Some(phash),
+            embedding: None,
+        }
+    }
+
+    fn urls(media: &[&Media]) -> Vec<String> {
+        media.iter().map(|m| m.url.clone()).collect()
+    }
+
+    #[test]
+    fn dhash_stable_across_resize() {
+        let hash = dhash(&pattern(320, 240));
+        for (w, h) in [(640, 480), (97, 73), (320, 200)] {
+            let resized = pattern(320, 240).resize_exact(w, h, FilterType::Lanczos3);
+            assert!(hamming(hash, dhash(&resized)) <= 4, "{}x{}", w, h);
+        }
+        // an other picture is far
+        let flipped = pattern(320, 240).fliph();
+        assert!(hamming(hash, dhash(&flipped)) > 10);
+    }
+
+    #[test]
+    fn hamming_distance() {
+        let hash = dhash(&pattern(64, 48));
+        assert_eq!(hamming(hash, hash), 0);
+        assert_eq!(hamming(hash, !hash), 64);
+        assert_eq!(hamming(0b1010, 0b0110), 2);
+    }
+
+    #[test]
+    fn group_transitively() {
+        // b is close to a and c, a and c are far apart
+        let media = [
+            media("a", 0),
+            media("b", 0b1111),
+            media("c", 0xff),
+            media("d", -1),
+            media("e", !0b111),
+        ];
+        let groups = duplicate_groups(&media, 4);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(urls(&groups[0]), ["a", "b", "c"]);
+        assert_eq!(urls(&groups[1]), ["d", "e"]);
+        // under the distance of a and b, only d and e are left
+        let groups = duplicate_groups(&media, 3);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(urls(&groups[0]), ["d", "e"]);
+    }
+
+    #[test]
+    fn spread_the_duplicates() {
+        let mut spacer = DuplicateSpacer::new(&DuplicateConfig {
+            distance: 2,
+            spacing: 2,
+        });
+        let batch = [
+            media("a1", 0),
+            media("a2", 0b11),
+            media("b", 0xff00),
+            media("c", 0xff_0000),
+        ];
+        let spread = spacer.spread(batch.iter().collect());
+        assert_eq!(urls(&spread), ["a1", "b", "c", "a2"]);
+
+        // only duplicates left, placed anyway in their order
+        let batch = [media("x1", -1), media("x2", -1)];
+        let spread = spacer.spread(batch.iter().collect());
+        assert_eq!(urls(&spread), ["x1", "x2"]);
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender};
+
+use rand::rngs::StdRng;
+
+use crate::frame::Frame;
+use crate::glitch::Glitch;
+use crate::media_config::MediaConfig;
+use crate::session::{Entry, Event, Session, GLITCH_STREAM};
+
+pub struct MediaReplay {
+    // media of the log with their tags and glitch intensity, in display order
+    pub media: VecDeque<(PathBuf, Vec<String>, Option<f32>)>,
+    pub glitch: Glitch,
+
+    rng: StdRng,
+    tx_graphic: Sender<Frame>,
+    rx_graphic: Receiver<u8>,
+}
+
+impl MediaReplay {
+    pub fn new(
+        config: &MediaConfig,
+        entries: &[Entry],
+        session: &Session,
+        tx_graphic: Sender<Frame>,
+        rx_graphic: Receiver<u8>,
+    ) -> Self {
+        /*
+            Replace the media handler during a replay:
+            send the logged media and glitch them with the same intensity and random stream
+        */
+        let media = entries
+            .iter()
+            .filter_map(|e| match &e.event {
+                Event::Media { path, tags, glitch } => Some((path.clone(), tags.clone(), *glitch)),
+                _ => None,
+            })
+            .collect();
+        Self {
+            media,
+            glitch: Glitch::new(config.glitch.clone()),
+            rng: session.rng(GLITCH_STREAM),
+            tx_graphic,
+            rx_graphic,
+        }
+    }
+
+    pub fn run(&mut self) {
+        while let Ok(count) = self.rx_graphic.recv() {
+            for _ in 0..count {
+                let (path, tags, glitch) = match self.media.pop_front() {
+                    Some(m) => m,
+                    None => {
+                        println!("End of the replay");
+                        return;
+                    }
+                };
+                let mut media = Frame::new(path);
+                media.tags = tags;
+                // the logged intensity already hold the mode, the tags and the audio
+                media.glitch_override = Some(glitch.unwrap_or(0.));
+                self.glitch.apply(&mut media, &mut self.rng);
+                self.tx_graphic.send(media).unwrap();
+            }
+        }
+    }
+}
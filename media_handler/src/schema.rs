@@ -8,10 +8,36 @@ diesel::table! {
 }
 
 diesel::table! {
-    media (format_id, tag_id) {
+    history (id) {
+        id -> Int4,
+        media_id -> Int4,
+        shown_at -> Timestamp,
+        duration -> Nullable<Float4>,
+        rating -> Int4,
+    }
+}
+
+diesel::table! {
+    media (id) {
+        id -> Int4,
         url -> Varchar,
         format_id -> Int4,
+        width -> Nullable<Int4>,
+        height -> Nullable<Int4>,
+        hash -> Nullable<Varchar>,
+        added_at -> Timestamp,
+        palette -> Nullable<Varchar>,
+        histogram -> Nullable<Binary>,
+        phash -> Nullable<Int8>,
+        embedding -> Nullable<Binary>,
+    }
+}
+
+diesel::table! {
+    media_tag (media_id, tag_id) {
+        media_id -> Int4,
         tag_id -> Int4,
+        confidence -> Nullable<Float4>,
     }
 }
 
@@ -22,7 +48,9 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(history -> media (media_id));
 diesel::joinable!(media -> format (format_id));
-diesel::joinable!(media -> tag (tag_id));
+diesel::joinable!(media_tag -> media (media_id));
+diesel::joinable!(media_tag -> tag (tag_id));
 
-diesel::allow_tables_to_appear_in_same_query!(format, media, tag,);
+diesel::allow_tables_to_appear_in_same_query!(format, history, media, media_tag, tag,);
@@ -0,0 +1,478 @@
+use std::fmt;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use diesel::dsl::not;
+use diesel::prelude::*;
+
+use crate::database::{with_connection, DbConnection};
+use crate::history::stats;
+use crate::schema::*;
+use crate::sql_models::*;
+
+// RGB distance under which a palette color is similar to the searched one
+const COLOR_DISTANCE: f32 = 80.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Before,
+    On,
+    After,
+}
+
+impl Comparison {
+    fn split(value: &str) -> (Self, &str) {
+        match value.chars().next() {
+            Some('<') => (Self::Before, &value[1..]),
+            Some('>') => (Self::After, &value[1..]),
+            Some('=') => (Self::On, &value[1..]),
+            _ => (Self::On, value),
+        }
+    }
+
+    fn matches<T: PartialOrd>(&self, value: T, reference: T) -> bool {
+        match self {
+            Self::Before => value < reference,
+            Self::On => value == reference,
+            Self::After => value > reference,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    // the bool negate the term
+    Tag(String, bool),
+    Format(String, bool),
+    Path(String, bool),
+    Color([u8; 3]),
+    Shown(Comparison, NaiveDate),
+    Rating(Comparison, i64),
+}
+
+impl Term {
+    fn parse(word: &str) -> Result<Self, String> {
+        let (negated, word) = match word.strip_prefix('-') {
+            Some(w) => (true, w),
+            None => (false, word),
+        };
+        let (key, value) = word
+            .split_once(':')
+            .ok_or_else(|| format!("'{}' isn't a key:value term", word))?;
+        if value.is_empty() {
+            return Err(format!("'{}' has no value", word));
+        }
+        if negated && !matches!(key, "tag" | "format" | "path") {
+            return Err(format!("'{}' can't be negated", key));
+        }
+
+        match key {
+            "tag" => Ok(Self::Tag(value.to_string(), negated)),
+            "format" => Ok(Self::Format(value.to_uppercase(), negated)),
+            "path" => Ok(Self::Path(value.to_string(), negated)),
+            "color" => parse_hex(value)
+                .map(Self::Color)
+                .ok_or_else(|| format!("'{}' isn't a #rrggbb color", value)),
+            "shown" => {
+                let (comparison, date) = Comparison::split(value);
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map(|d| Self::Shown(comparison, d))
+                    .map_err(|_| format!("'{}' isn't a YYYY-MM-DD date", date))
+            }
+            "rating" => {
+                let (comparison, rating) = Comparison::split(value);
+                rating
+                    .parse()
+                    .map(|r| Self::Rating(comparison, r))
+                    .map_err(|_| format!("'{}' isn't a rating", rating))
+            }
+            _ => Err(format!("Unknown search key '{}'", key)),
+        }
+    }
+}
+
+pub fn parse_hex(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn like_pattern(glob: &str) -> String {
+    // path glob to a LIKE pattern, * any characters and ? a single one
+    glob.chars()
+        .map(|c| match c {
+            '\\' | '%' | '_' => format!("\\{}", c),
+            '*' => String::from("%"),
+            '?' => String::from("_"),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct SearchResult {
+    pub media: Media,
+    pub rating: i64,
+    pub last_shown: Option<NaiveDateTime>,
+    // distance to the searched color, when there is one
+    pub color_distance: Option<f32>,
+}
+
+impl fmt::Display for SearchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = match (self.media.width, self.media.height) {
+            (Some(w), Some(h)) => format!("{}x{}", w, h),
+            _ => String::from("?"),
+        };
+        let last_shown = match self.last_shown {
+            Some(t) => t.format("%Y-%m-%d %H:%M").to_string(),
+            None => String::from("never"),
+        };
+        write!(
+            f,
+            "{}  {}  rating {}  shown {}",
+            self.media.url, size, self.rating, last_shown
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    pub terms: Vec<Term>,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        /*
+            Space separated terms, all of them must match:
+            tag:<name> format:<name> path:<glob> color:#rrggbb
+            shown:[<|>]YYYY-MM-DD rating:[<|>]<sum of the ratings>
+            tag, format and path are negated with a leading '-'
+        */
+        let terms = query
+            .split_whitespace()
+            .map(Term::parse)
+            .collect::<Result<Vec<Term>, String>>()?;
+        Ok(Self { terms })
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        self.terms
+            .iter()
+            .filter_map(|t| match t {
+                Term::Tag(tag, false) => Some(tag.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn load(&self, connection: &mut DbConnection) -> Vec<Media> {
+        // terms answered by the database, the others are checked on the results
+        with_connection!(connection, conn => {
+            let mut query = media::table
+                .inner_join(format::table)
+                .select(Media::as_select())
+                .order(media::url)
+                .into_boxed();
+
+            for term in &self.terms {
+                query = match term {
+                    Term::Tag(tag, negated) => {
+                        let tagged = media_tag::table
+                            .inner_join(tag::table)
+                            .filter(tag::name.eq(tag.clone()))
+                            .select(media_tag::media_id);
+                        if *negated {
+                            query.filter(not(media::id.eq_any(tagged)))
+                        } else {
+                            query.filter(media::id.eq_any(tagged))
+                        }
+                    }
+                    Term::Format(name, negated) => {
+                        if *negated {
+                            query.filter(format::name.ne(name.clone()))
+                        } else {
+                            query.filter(format::name.eq(name.clone()))
+                        }
+                    }
+                    Term::Path(glob, negated) => {
+                        let pattern = like_pattern(glob);
+                        if *negated {
+                            query.filter(media::url.not_like(pattern).escape('\\'))
+                        } else {
+                            query.filter(media::url.like(pattern).escape('\\'))
+                        }
+                    }
+                    Term::Shown(comparison, date) => {
+                        let day = date.and_hms_opt(0, 0, 0).unwrap();
+                        let next_day = day + Duration::days(1);
+                        let shown = history::table.select(history::media_id).into_boxed();
+                        let shown = match comparison {
+                            Comparison::Before => shown.filter(history::shown_at.lt(day)),
+                            Comparison::On => shown
+                                .filter(history::shown_at.ge(day))
+                                .filter(history::shown_at.lt(next_day)),
+                            Comparison::After => shown.filter(history::shown_at.ge(next_day)),
+                        };
+                        query.filter(media::id.eq_any(shown))
+                    }
+                    Term::Color(_) | Term::Rating(..) => query,
+                };
+            }
+
+            query.load(conn).expect("Failed request")
+        })
+    }
+
+    fn color_distance(media: &Media, color: [u8; 3]) -> Option<f32> {
+        // closest color of the palette
+        media
+            .palette
+            .as_deref()?
+            .split_whitespace()
+            .filter_map(parse_hex)
+            .map(|c| {
+                (0..3)
+                    .map(|i| (c[i] as f32 - color[i] as f32).powi(2))
+                    .sum::<f32>()
+                    .sqrt()
+            })
+            .min_by(|a, b| a.total_cmp(b))
+    }
+
+    pub fn run(&self, connection: &mut DbConnection) -> Vec<SearchResult> {
+        /*
+            Matching media sorted by path,
+            or from the closest to the farthest when a color is searched
+        */
+        let stats = stats(connection);
+        let mut results: Vec<SearchResult> = vec![];
+        for media in self.load(connection) {
+            let (rating, last_shown) = stats.get(&media.id).copied().unwrap_or((0, None));
+            let mut color_distance = None;
+            let mut matched = true;
+            for term in &self.terms {
+                match term {
+                    Term::Color(color) => match Self::color_distance(&media, *color) {
+                        Some(d) if d < COLOR_DISTANCE => {
+                            color_distance = Some(color_distance.map_or(d, |c: f32| c.max(d)));
+                        }
+                        _ => matched = false,
+                    },
+                    Term::Rating(comparison, reference) => {
+                        matched &= comparison.matches(rating, *reference);
+                    }
+                    _ => (),
+                }
+            }
+            if matched {
+                results.push(SearchResult {
+                    media,
+                    rating,
+                    last_shown,
+                    color_distance,
+                });
+            }
+        }
+        if results.iter().any(|r| r.color_distance.is_some()) {
+            results.sort_by(|a, b| {
+                a.color_distance
+                    .unwrap()
+                    .total_cmp(&b.color_distance.unwrap())
+            });
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{test_databases, with_connection};
+
+    #[test]
+    fn parse_the_terms() {
+        let query = SearchQuery::parse("tag:night -format:gif path:*.png color:#1040ff").unwrap();
+        assert_eq!(
+            query.terms,
+            [
+                Term::Tag(String::from("night"), false),
+                Term::Format(String::from("GIF"), true),
+                Term::Path(String::from("*.png"), false),
+                Term::Color([0x10, 0x40, 0xff]),
+            ]
+        );
+        assert_eq!(query.tags(), ["night"]);
+        assert_eq!(
+            Term::parse("-tag:sea"),
+            Ok(Term::Tag(String::from("sea"), true))
+        );
+        assert_eq!(
+            Term::parse("shown:<2024-03-01"),
+            Ok(Term::Shown(
+                Comparison::Before,
+                NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()
+            ))
+        );
+        assert_eq!(
+            Term::parse("rating:>-2"),
+            Ok(Term::Rating(Comparison::After, -2))
+        );
+        assert_eq!(
+            Term::parse("rating:=3"),
+            Ok(Term::Rating(Comparison::On, 3))
+        );
+    }
+
+    #[test]
+    fn reject_the_bad_terms() {
+        for word in [
+            "night",
+            "tag:",
+            "-color:#ffffff",
+            "-rating:1",
+            "color:#fff",
+            "color:#gg0000",
+            "shown:yesterday",
+            "rating:high",
+            "size:big",
+        ] {
+            assert!(Term::parse(word).is_err(), "{}", word);
+        }
+        assert!(SearchQuery::parse("tag:sea size:big").is_err());
+    }
+
+    #[test]
+    fn escape_the_like_pattern() {
+        assert_eq!(like_pattern("*/beach?.jpeg"), "%/beach_.jpeg");
+        assert_eq!(like_pattern("100%_done\\"), "100\\%\\_done\\\\");
+    }
+
+    #[test]
+    fn compare() {
+        assert!(Comparison::Before.matches(1, 2));
+        assert!(!Comparison::Before.matches(2, 2));
+        assert!(Comparison::On.matches(2, 2));
+        assert!(Comparison::After.matches(3, 2));
+        assert!(!Comparison::After.matches(2, 2));
+        assert_eq!(Comparison::split("<5"), (Comparison::Before, "5"));
+        assert_eq!(Comparison::split("5"), (Comparison::On, "5"));
+    }
+
+    // url, format, palette, tags and ratings of a media, shown on the 2024-03-10
+    type Row = (
+        &'static str,
+        &'static str,
+        &'static str,
+        &'static [&'static str],
+        &'static [i32],
+    );
+
+    const MEDIA: [Row; 4] = [
+        ("a_b.jpeg", "JPEG", "#ff0000 #000000", &["sea"], &[1, 1]),
+        ("axb.jpeg", "JPEG", "#0000ff", &["sea"], &[]),
+        ("cliff.png", "PNG", "#f01010", &["sea", "sun"], &[-1]),
+        ("forest.png", "PNG", "#00ff00", &[], &[]),
+    ];
+
+    fn fill(mut connection: DbConnection) -> DbConnection {
+        let shown_at = NaiveDate::from_ymd_opt(2024, 3, 10)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        with_connection!(&mut connection, conn => {
+            for (url, format_name, palette, tags, ratings) in MEDIA {
+                diesel::insert_into(format::table)
+                    .values(format::name.eq(format_name))
+                    .on_conflict(format::name)
+                    .do_nothing()
+                    .execute(conn)
+                    .unwrap();
+                let format_id: i32 = format::table
+                    .filter(format::name.eq(format_name))
+                    .select(format::id)
+                    .first(conn)
+                    .unwrap();
+                diesel::insert_into(media::table)
+                    .values((
+                        media::url.eq(url),
+                        media::format_id.eq(format_id),
+                        media::palette.eq(palette),
+                    ))
+                    .execute(conn)
+                    .unwrap();
+                let media_id: i32 = media::table
+                    .filter(media::url.eq(url))
+                    .select(media::id)
+                    .first(conn)
+                    .unwrap();
+                for name in tags {
+                    diesel::insert_into(tag::table)
+                        .values(tag::name.eq(name))
+                        .on_conflict(tag::name)
+                        .do_nothing()
+                        .execute(conn)
+                        .unwrap();
+                    let tag_id: i32 = tag::table
+                        .filter(tag::name.eq(name))
+                        .select(tag::id)
+                        .first(conn)
+                        .unwrap();
+                    diesel::insert_into(media_tag::table)
+                        .values(MediaTag {
+                            media_id,
+                            tag_id,
+                            confidence: None,
+                        })
+                        .execute(conn)
+                        .unwrap();
+                }
+                for rating in ratings.iter() {
+                    diesel::insert_into(history::table)
+                        .values((
+                            history::media_id.eq(media_id),
+                            history::shown_at.eq(shown_at),
+                            history::rating.eq(rating),
+                        ))
+                        .execute(conn)
+                        .unwrap();
+                }
+            }
+        });
+        connection
+    }
+
+    fn urls(query: &str) -> Vec<String> {
+        // the same results on every backend
+        let query = SearchQuery::parse(query).unwrap();
+        let mut results = test_databases().into_iter().map(|connection| {
+            query
+                .run(&mut fill(connection))
+                .into_iter()
+                .map(|r| r.media.url)
+                .collect::<Vec<String>>()
+        });
+        let urls = results.next().unwrap();
+        for other in results {
+            assert_eq!(other, urls);
+        }
+        urls
+    }
+
+    #[test]
+    fn run_the_queries() {
+        assert_eq!(urls("tag:sea -format:png"), ["a_b.jpeg", "axb.jpeg"]);
+        assert_eq!(urls("-tag:sea"), ["forest.png"]);
+        // '_' is a literal character of the glob
+        assert_eq!(urls("path:a_b*"), ["a_b.jpeg"]);
+        assert_eq!(urls("path:a?b.*"), ["a_b.jpeg", "axb.jpeg"]);
+        assert_eq!(urls("rating:>0"), ["a_b.jpeg"]);
+        assert_eq!(urls("rating:0"), ["axb.jpeg", "forest.png"]);
+        assert_eq!(urls("shown:2024-03-10"), ["a_b.jpeg", "cliff.png"]);
+        assert_eq!(urls("shown:>2024-03-10"), Vec::<String>::new());
+        // closest palette color first
+        assert_eq!(urls("color:#ff0000"), ["a_b.jpeg", "cliff.png"]);
+    }
+}
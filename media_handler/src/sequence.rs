@@ -0,0 +1,322 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use image::{DynamicImage, RgbaImage};
+
+use crate::frame::Frame;
+
+// frames decoded ahead of the display
+const PREFETCH: usize = 2;
+// video frames read through before seeking with a new ffmpeg
+const SEEK_AHEAD: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VideoInfo {
+    pub width: u32,
+    pub height: u32,
+    // frames at the decoded frame rate
+    pub frames: usize,
+}
+
+impl VideoInfo {
+    pub fn probe(path: &Path, fps: f32) -> Option<Self> {
+        // first video stream of the file, none for another file or without ffprobe
+        let output = Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "v:0"])
+            .args(["-show_entries", "stream=width,height:format=duration"])
+            .args(["-of", "default=noprint_wrappers=1"])
+            .arg(path)
+            .stdin(Stdio::null())
+            .output()
+            .ok()?;
+        Self::parse(&String::from_utf8_lossy(&output.stdout), fps)
+    }
+
+    fn parse(probe: &str, fps: f32) -> Option<Self> {
+        // `key=value` lines of ffprobe
+        let value = |key: &str| {
+            probe
+                .lines()
+                .find_map(|l| l.trim().strip_prefix(key)?.strip_prefix('='))
+        };
+        let duration: f32 = value("duration")?.parse().ok()?;
+        let info = Self {
+            width: value("width")?.parse().ok()?,
+            height: value("height")?.parse().ok()?,
+            frames: (duration * fps) as usize,
+        };
+        (info.width > 0 && info.height > 0 && info.frames > 0).then_some(info)
+    }
+}
+
+struct VideoStream {
+    child: Child,
+    // index of the next frame read
+    next: usize,
+}
+
+impl VideoStream {
+    fn start(path: &Path, fps: f32, index: usize) -> Option<Self> {
+        // raw RGBA frames from `index`, resampled at `fps`
+        let child = Command::new("ffmpeg")
+            .args(["-loglevel", "error"])
+            .args(["-ss", &(index as f32 / fps).to_string(), "-i"])
+            .arg(path)
+            .args(["-vf", &format!("fps={}", fps)])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        Some(Self { child, next: index })
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> bool {
+        self.next += 1;
+        match self.child.stdout.as_mut() {
+            Some(stdout) => stdout.read_exact(buffer).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl Drop for VideoStream {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub struct FrameSequence {
+    rx: Receiver<Frame>,
+}
+
+impl FrameSequence {
+    pub fn new(path: &PathBuf) -> Self {
+        /*
+            Image sequence played in loop, from a folder of numbered frames
+            A single image is a sequence of one frame
+            Frames are decoded by a thread, it stop with the sequence
+        */
+        let mut paths: Vec<PathBuf> = if path.is_dir() {
+            fs::read_dir(path)
+                .unwrap()
+                .map(|p| p.unwrap().path())
+                .filter(|f| Frame::is_image(f))
+                .collect()
+        } else {
+            vec![path.clone()]
+        };
+        paths.sort();
+        if paths.is_empty() {
+            panic!("Frame sequence {:?} is empty", path);
+        }
+
+        let (tx, rx) = mpsc::sync_channel(PREFETCH);
+        thread::spawn(move || {
+            // a single frame never change, it is decoded once
+            let count = if paths.len() == 1 { 1 } else { usize::MAX };
+            for p in paths.iter().cycle().take(count) {
+                if tx.send(Frame::new(p.clone())).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { rx }
+    }
+
+    pub fn on_request(requests: Receiver<PathBuf>) -> Self {
+        /*
+            Frames of the paths sent, decoded by a thread in the order asked
+            Paths waiting behind a newer one are dropped, files that can't be decoded skipped
+        */
+        let (tx, rx) = mpsc::sync_channel(PREFETCH);
+        thread::spawn(move || {
+            while let Ok(mut path) = requests.recv() {
+                while let Ok(newer) = requests.try_recv() {
+                    path = newer;
+                }
+                let data = match image::open(&path) {
+                    Ok(data) => data,
+                    Err(_) => continue,
+                };
+                if tx.send(Frame::from_image(path, data)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { rx }
+    }
+
+    pub fn video_on_request(
+        path: &Path,
+        fps: f32,
+        info: VideoInfo,
+        requests: Receiver<usize>,
+    ) -> Self {
+        /*
+            Frames of a video by their index at `fps`, decoded by ffmpeg in a thread
+            The stream is read through the frames skipped and seek again for the ones behind or far ahead
+            Indexes waiting behind a newer one are dropped, frames that can't be decoded skipped
+        */
+        let path = path.to_path_buf();
+        let (tx, rx) = mpsc::sync_channel(PREFETCH);
+        thread::spawn(move || {
+            let mut stream: Option<VideoStream> = None;
+            let mut buffer = vec![0; (info.width * info.height * 4) as usize];
+            while let Ok(mut index) = requests.recv() {
+                while let Ok(newer) = requests.try_recv() {
+                    index = newer;
+                }
+                if !matches!(&stream, Some(s) if s.next <= index && index <= s.next + SEEK_AHEAD) {
+                    stream = VideoStream::start(&path, fps, index);
+                }
+                let read = match stream.as_mut() {
+                    Some(s) => {
+                        let mut read = true;
+                        while read && s.next <= index {
+                            read = s.read(&mut buffer);
+                        }
+                        read
+                    }
+                    None => false,
+                };
+                if !read {
+                    // end of the video or decoding error, the next request seek again
+                    stream = None;
+                    continue;
+                }
+                let image = RgbaImage::from_raw(info.width, info.height, buffer.clone()).unwrap();
+                let frame = Frame::from_image(path.clone(), DynamicImage::ImageRgba8(image));
+                if tx.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { rx }
+    }
+
+    pub fn wait_frame(&mut self) -> Frame {
+        // first frame of the sequence, before the display start
+        self.rx.recv().expect("Frame sequence decoder stopped")
+    }
+
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        // none while the next frame is decoded, or for a single frame already shown
+        self.rx.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn folder(name: &str, colors: &[u8]) -> PathBuf {
+        // one folder per test and per run, removed by the test
+        let folder =
+            std::env::temp_dir().join(format!("cudi_sequence_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        for (i, c) in colors.iter().enumerate() {
+            let image = RgbImage::from_pixel(2, 2, Rgb([*c, 0, 0]));
+            image.save(folder.join(format!("{:03}.png", i))).unwrap();
+        }
+        // not a frame
+        fs::write(folder.join("notes.txt"), "").unwrap();
+        folder
+    }
+
+    fn red(frame: &Frame) -> u8 {
+        frame.data.to_rgb8().get_pixel(0, 0)[0]
+    }
+
+    #[test]
+    fn loop_over_the_frames_in_order() {
+        let folder = folder("loop", &[10, 20, 30]);
+        let mut sequence = FrameSequence::new(&folder);
+        let reds: Vec<u8> = (0..7).map(|_| red(&sequence.wait_frame())).collect();
+        assert_eq!(reds, [10, 20, 30, 10, 20, 30, 10]);
+        drop(sequence);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn single_frame_decoded_once() {
+        let folder = folder("single", &[40]);
+        let mut sequence = FrameSequence::new(&folder.join("000.png"));
+        assert_eq!(red(&sequence.wait_frame()), 40);
+        assert!(sequence.next_frame().is_none());
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn decode_the_frames_asked() {
+        let folder = folder("request", &[50, 60]);
+        fs::write(folder.join("broken.png"), "not a png").unwrap();
+        let (tx, rx) = mpsc::channel();
+        let mut sequence = FrameSequence::on_request(rx);
+        tx.send(folder.join("001.png")).unwrap();
+        assert_eq!(red(&sequence.wait_frame()), 60);
+        // the broken frame is skipped, not shown
+        tx.send(folder.join("broken.png")).unwrap();
+        tx.send(folder.join("000.png")).unwrap();
+        assert_eq!(red(&sequence.wait_frame()), 50);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn parse_the_video_probe() {
+        let probe = "width=320\nheight=240\nduration=2.5\n";
+        let info = VideoInfo::parse(probe, 10.).unwrap();
+        assert_eq!(
+            info,
+            VideoInfo {
+                width: 320,
+                height: 240,
+                frames: 25
+            }
+        );
+        // an image has no duration, a file without video stream no size
+        assert_eq!(
+            VideoInfo::parse("width=320\nheight=240\nduration=N/A\n", 10.),
+            None
+        );
+        assert_eq!(VideoInfo::parse("duration=2.5\n", 10.), None);
+    }
+
+    #[test]
+    fn decode_the_video_frames_asked() {
+        // skipped without ffmpeg
+        let path = std::env::temp_dir().join(format!("cudi_sequence_{}.mp4", std::process::id()));
+        let made = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-y", "-f", "lavfi"])
+            .args(["-i", "color=c=red:s=16x16:d=2:r=10"])
+            .args(["-pix_fmt", "yuv420p"])
+            .arg(&path)
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+        if !made {
+            println!("No ffmpeg, the video decoding is not tested");
+            return;
+        }
+        let info = VideoInfo::probe(&path, 10.).unwrap();
+        assert_eq!((info.width, info.height, info.frames), (16, 16, 20));
+
+        let (tx, rx) = mpsc::channel();
+        let mut sequence = FrameSequence::video_on_request(&path, 10., info, rx);
+        // read through, then seek back
+        for index in [3, 12, 1] {
+            tx.send(index).unwrap();
+            let frame = sequence.wait_frame();
+            assert_eq!((frame.width, frame.height), (16, 16));
+            assert!(red(&frame) > 200);
+        }
+        drop(sequence);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use std::fs::{self, File};
+use std::io::{LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::MediaCommand;
+
+// independent random streams of the session, one per consumer
+pub const SHUFFLE_STREAM: u64 = 1;
+pub const GLITCH_STREAM: u64 = 2;
+pub const CLUSTER_STREAM: u64 = 3;
+// one more per window, from these ones
+pub const LAYOUT_STREAM: u64 = 16;
+pub const CUT_STREAM: u64 = 48;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Start {
+        seed: u64,
+    },
+    // media sent to the graphic side, in display order, with the glitch intensity applied
+    Media {
+        path: PathBuf,
+        tags: Vec<String>,
+        #[serde(default)]
+        glitch: Option<f32>,
+    },
+    // media requested by the graphic side
+    Tick {
+        count: u8,
+    },
+    // control changes
+    Param {
+        name: String,
+        value: f32,
+    },
+    Background {
+        color: [f32; 4],
+    },
+    // media query and rating commands, from the terminal or the controls
+    Command {
+        command: MediaCommand,
+    },
+    // spectrum band energies of an audio window
+    Audio {
+        energies: Vec<f32>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    // milliseconds since the session start
+    pub t: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Clone)]
+pub struct Session {
+    pub seed: u64,
+    start: Instant,
+    log: Option<Arc<Mutex<LineWriter<File>>>>,
+}
+
+impl Session {
+    pub fn new(seed: Option<u64>, log_path: Option<&Path>) -> Self {
+        /*
+            Every random choice of the show derive from the seed,
+            the log keep what the seed can't reproduce: media order and user inputs
+        */
+        let seed = seed.unwrap_or_else(rand::random);
+        let log = log_path.map(|p| {
+            if let Some(folder) = p.parent() {
+                fs::create_dir_all(folder).expect("Unable to create the session log folder");
+            }
+            let file = File::create(p).expect("Unable to create the session log");
+            Arc::new(Mutex::new(LineWriter::new(file)))
+        });
+        println!("Session seed: {}", seed);
+
+        let session = Self {
+            seed,
+            start: Instant::now(),
+            log,
+        };
+        session.log(Event::Start { seed });
+        session
+    }
+
+    pub fn rng(&self, stream: u64) -> StdRng {
+        StdRng::seed_from_u64(self.seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    pub fn elapsed(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    pub fn log(&self, event: Event) {
+        if let Some(log) = &self.log {
+            let entry = Entry {
+                t: self.elapsed(),
+                event,
+            };
+            let line = serde_json::to_string(&entry).unwrap();
+            writeln!(log.lock().unwrap(), "{}", line).expect("Unable to write the session log");
+        }
+    }
+
+    pub fn read_log(path: &Path) -> (u64, Vec<Entry>) {
+        /*
+            Seed of the first line and the events following it
+            The last line is cut when the show was killed while writing it, it is skipped
+        */
+        let content = fs::read_to_string(path).expect("Unable to read the session log");
+        let lines: Vec<&str> = content.lines().filter(|l| !l.trim().is_empty()).collect();
+        let mut entries: Vec<Entry> = vec![];
+        for (i, l) in lines.iter().enumerate() {
+            match serde_json::from_str(l) {
+                Ok(entry) => entries.push(entry),
+                Err(e) if i + 1 == lines.len() && i > 0 => {
+                    println!("Skipped the last session log line {}: {}", i + 1, e)
+                }
+                Err(e) => panic!("Invalid session log line {}: {}", i + 1, e),
+            }
+        }
+        match entries.first() {
+            Some(Entry {
+                event: Event::Start { seed },
+                ..
+            }) => (*seed, entries[1..].to_vec()),
+            _ => panic!("Session log {:?} doesn't start with a seed", path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryCommand;
+    use rand::RngCore;
+
+    fn events() -> Vec<Event> {
+        vec![
+            Event::Media {
+                path: PathBuf::from("/data/a.png"),
+                tags: vec!["night".to_string()],
+                glitch: Some(0.5),
+            },
+            Event::Tick { count: 2 },
+            Event::Param {
+                name: "speed".to_string(),
+                value: 1.5,
+            },
+            Event::Background {
+                color: [0., 0.5, 1., 1.],
+            },
+            Event::Command {
+                command: MediaCommand::Query(QueryCommand::parse("include night").unwrap()),
+            },
+            Event::Command {
+                command: MediaCommand::Rate(-1),
+            },
+            Event::Audio {
+                energies: vec![0.25, 0.75],
+            },
+        ]
+    }
+
+    fn write_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("cudi_session_{}.jsonl", name));
+        let session = Session::new(Some(42), Some(&path));
+        for event in events() {
+            session.log(event);
+        }
+        path
+    }
+
+    #[test]
+    fn replay_the_log() {
+        let path = write_log("round_trip");
+        let (seed, entries) = Session::read_log(&path);
+        assert_eq!(seed, 42);
+        let replayed: Vec<Event> = entries.iter().map(|e| e.event.clone()).collect();
+        assert_eq!(replayed, events());
+        assert!(entries.windows(2).all(|w| w[0].t <= w[1].t));
+        // same seed, same random streams
+        let replay = Session::new(Some(seed), None);
+        let session = Session::new(Some(42), None);
+        assert_eq!(
+            replay.rng(SHUFFLE_STREAM).next_u64(),
+            session.rng(SHUFFLE_STREAM).next_u64()
+        );
+        assert_ne!(
+            replay.rng(SHUFFLE_STREAM).next_u64(),
+            replay.rng(GLITCH_STREAM).next_u64()
+        );
+    }
+
+    #[test]
+    fn skip_the_cut_last_line() {
+        let path = write_log("cut");
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &content[..content.len() - 10]).unwrap();
+        let (seed, entries) = Session::read_log(&path);
+        assert_eq!(seed, 42);
+        assert_eq!(entries.len(), events().len() - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid session log line 3")]
+    fn reject_a_broken_line() {
+        let path = write_log("broken");
+        let content = fs::read_to_string(&path).unwrap();
+        let mut lines: Vec<&str> = content.lines().collect();
+        lines[2] = "{\"t\": 3, \"event\": \"unknown\"}";
+        fs::write(&path, lines.join("\n")).unwrap();
+        Session::read_log(&path);
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use image::DynamicImage;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use yaml_rust::Yaml;
+
+use crate::search::parse_hex;
+use crate::sql_models::Media;
+
+// bins per channel, 4 * 4 * 4 bins in total
+const CHANNEL_BINS: usize = 4;
+const BINS: usize = CHANNEL_BINS * CHANNEL_BINS * CHANNEL_BINS;
+// images are downscaled before counting, no need of every pixel
+const THUMBNAIL_SIZE: u32 = 64;
+// media visited by a neighbor walk that can't come back
+const WALK_MEMORY: usize = 64;
+// steps of a neighbor walk per media list
+const WALK_STEPS: usize = 32;
+
+pub trait Feature: Sized {
+    // none for the media indexed without it
+    fn of(media: &Media) -> Option<Self>;
+    // 0 for identical features, infinite when they can't be compared
+    fn distance(&self, other: &Self) -> f32;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    // share of the pixels in each bin, summing to 1
+    pub bins: Vec<f32>,
+}
+
+impl Histogram {
+    fn bin(c: &[u8; 3]) -> usize {
+        let b = |v: u8| v as usize * CHANNEL_BINS / 256;
+        (b(c[0]) * CHANNEL_BINS + b(c[1])) * CHANNEL_BINS + b(c[2])
+    }
+
+    pub fn from_colors(colors: &[[u8; 3]]) -> Self {
+        let mut bins = vec![0.; BINS];
+        for c in colors {
+            bins[Self::bin(c)] += 1. / colors.len() as f32;
+        }
+        Self { bins }
+    }
+
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let pixels: Vec<[u8; 3]> = image
+            .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+            .into_rgb8()
+            .pixels()
+            .map(|p| p.0)
+            .collect();
+        Self::from_colors(&pixels)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bins.iter().flat_map(|b| b.to_le_bytes()).collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != BINS * 4 {
+            return None;
+        }
+        let bins = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Some(Self { bins })
+    }
+
+    pub fn distance(&self, other: &Histogram) -> f32 {
+        // 1 minus the histograms intersection, 0 for the same colors
+        let shared: f32 = self
+            .bins
+            .iter()
+            .zip(&other.bins)
+            .map(|(a, b)| a.min(*b))
+            .sum();
+        1. - shared
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Seed {
+    Colors(Vec<[u8; 3]>),
+    Image(PathBuf),
+}
+
+impl Seed {
+    pub fn new(value: &str) -> Result<Self, String> {
+        // hex colors separated by spaces or commas, or an image path
+        if value.starts_with('#') {
+            let colors = value
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|c| !c.is_empty())
+                .map(|c| parse_hex(c).ok_or_else(|| format!("'{}' isn't a #rrggbb color", c)))
+                .collect::<Result<Vec<[u8; 3]>, String>>()?;
+            if colors.is_empty() {
+                return Err(format!("'{}' has no color", value));
+            }
+            Ok(Self::Colors(colors))
+        } else if Path::new(value).is_file() {
+            Ok(Self::Image(PathBuf::from(value)))
+        } else {
+            Err(format!(
+                "'{}' is neither #rrggbb colors nor an image",
+                value
+            ))
+        }
+    }
+
+    pub fn histogram(&self) -> Result<Histogram, String> {
+        match self {
+            Self::Colors(colors) => Ok(Histogram::from_colors(colors)),
+            Self::Image(path) => image::open(path)
+                .map(|i| Histogram::from_image(&i))
+                .map_err(|e| format!("Unable to open {}: {}", path.display(), e)),
+        }
+    }
+}
+
+impl Feature for Histogram {
+    fn of(media: &Media) -> Option<Self> {
+        Self::from_bytes(media.histogram.as_deref()?)
+    }
+
+    fn distance(&self, other: &Self) -> f32 {
+        Histogram::distance(self, other)
+    }
+}
+
+pub fn nearest<'a, F: Feature>(
+    target: &F,
+    media: impl IntoIterator<Item = &'a Media>,
+    k: usize,
+) -> Vec<(&'a Media, f32)> {
+    /*
+        K nearest media by feature distance, closest first
+        Media without the feature are ignored
+    */
+    let mut neighbors: Vec<(&Media, f32)> = media
+        .into_iter()
+        .filter_map(|m| Some((m, target.distance(&F::of(m)?))))
+        .filter(|(_, d)| d.is_finite())
+        .collect();
+    neighbors.sort_by(|a, b| a.1.total_cmp(&b.1));
+    neighbors.truncate(k);
+    neighbors
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriftConfig {
+    pub enabled: bool,
+    // start of the drift, a random media when none
+    pub seed: Option<Seed>,
+    // neighbors considered at each step
+    pub k: usize,
+}
+
+impl DriftConfig {
+    pub fn new(cfg: &Yaml) -> Self {
+        Self {
+            enabled: cfg["enabled"].as_bool().unwrap(),
+            seed: cfg["seed"]
+                .as_str()
+                .map(|s| Seed::new(s).unwrap_or_else(|e| panic!("Invalid drift seed: {}", e))),
+            k: cfg["k"].as_i64().unwrap() as usize,
+        }
+    }
+}
+
+pub struct NeighborWalk<F: Feature> {
+    k: usize,
+    current: Option<F>,
+    visited: VecDeque<i32>,
+}
+
+impl<F: Feature> NeighborWalk<F> {
+    pub fn new(k: usize, start: Option<F>) -> Self {
+        // a random media is the first step without start
+        Self {
+            k,
+            current: start,
+            visited: VecDeque::new(),
+        }
+    }
+
+    pub fn next_media<'a>(&mut self, media: &'a [Media], rng: &mut StdRng) -> Vec<&'a Media> {
+        /*
+            Each step jump to one of the K media closest to the current one,
+            the sequence move slowly through the feature space instead of jumping around
+            Return the media in display order
+        */
+        let mut steps = vec![];
+        for _ in 0..WALK_STEPS {
+            let candidates: Vec<&Media> = media
+                .iter()
+                .filter(|m| !self.visited.contains(&m.id))
+                .collect();
+            let step = match &self.current {
+                Some(current) => nearest(current, candidates, self.k)
+                    .choose(rng)
+                    .map(|(m, _)| *m),
+                None => candidates
+                    .into_iter()
+                    .filter(|m| F::of(m).is_some())
+                    .collect::<Vec<&Media>>()
+                    .choose(rng)
+                    .copied(),
+            };
+            let step = match step {
+                Some(m) => m,
+                None if self.visited.is_empty() => break,
+                None => {
+                    // every media was visited, the walk can go back
+                    self.visited.clear();
+                    continue;
+                }
+            };
+
+            self.visited.push_back(step.id);
+            if self.visited.len() > WALK_MEMORY {
+                self.visited.pop_front();
+            }
+            self.current = F::of(step);
+            steps.push(step);
+        }
+        steps
+    }
+}
+
+impl DriftConfig {
+    pub fn start(&self) -> NeighborWalk<Histogram> {
+        let start = self.seed.as_ref().map(|s| {
+            s.histogram()
+                .unwrap_or_else(|e| panic!("Invalid drift seed: {}", e))
+        });
+        NeighborWalk::new(self.k, start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn media(id: i32, colors: &[[u8; 3]]) -> Media {
+        Media {
+            id,
+            url: id.to_string(),
+            format_id: 0,
+            width: None,
+            height: None,
+            hash: None,
+            added_at: Default::default(),
+            palette: None,
+            histogram: Some(Histogram::from_colors(colors).to_bytes()),
+            phash: None,
+            embedding: None,
+        }
+    }
+
+    #[test]
+    fn histogram_distance() {
+        let red = Histogram::from_colors(&[[255, 0, 0]]);
+        let mixed = Histogram::from_colors(&[[255, 0, 0], [0, 0, 255], [0, 0, 250]]);
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([255, 0, 0])));
+        assert_eq!(Histogram::from_image(&image), red);
+        assert_eq!(red.distance(&red), 0.);
+        assert_eq!(mixed.distance(&mixed), 0.);
+        assert_eq!(red.distance(&mixed), mixed.distance(&red));
+        assert!((red.distance(&mixed) - 2. / 3.).abs() < 1e-6);
+        let blue = Histogram::from_colors(&[[0, 0, 255]]);
+        assert_eq!(red.distance(&blue), 1.);
+        assert_eq!(Histogram::from_bytes(&red.to_bytes()), Some(red));
+    }
+
+    #[test]
+    fn nearest_first() {
+        let (red, blue) = ([255, 0, 0], [0, 0, 255]);
+        let media = [
+            media(1, &[blue]),
+            media(2, &[red, blue, blue]),
+            media(3, &[red]),
+            media(4, &[red, blue]),
+        ];
+        let target = Histogram::from_colors(&[red]);
+        let ids = |k| {
+            nearest(&target, &media, k)
+                .iter()
+                .map(|(m, _)| m.id)
+                .collect::<Vec<i32>>()
+        };
+        assert_eq!(ids(4), [3, 4, 2, 1]);
+        assert_eq!(ids(2), [3, 4]);
+        let distances: Vec<f32> = nearest(&target, &media, 4).iter().map(|n| n.1).collect();
+        assert!(distances.windows(2).all(|d| d[0] <= d[1]));
+    }
+
+    #[test]
+    fn parse_the_seed() {
+        assert_eq!(
+            Seed::new("#ff0000, #0000ff"),
+            Ok(Seed::Colors(vec![[255, 0, 0], [0, 0, 255]]))
+        );
+        assert!(Seed::new("#ff0000 #blue").is_err());
+        assert!(Seed::new("#").is_err());
+        assert!(Seed::new("missing.png").is_err());
+        let path = std::env::temp_dir().join("cudi_similarity_seed.png");
+        RgbImage::from_pixel(4, 4, Rgb([255, 0, 0]))
+            .save(&path)
+            .unwrap();
+        let seed = Seed::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(seed.histogram(), Ok(Histogram::from_colors(&[[255, 0, 0]])));
+    }
+}
@@ -1,15 +1,67 @@
-use crate::schema::{format, media, tag};
+use crate::schema::{format, history, media, media_tag, tag};
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 
 #[derive(Queryable, Identifiable, Selectable, Associations, Debug, PartialEq)]
 #[diesel(belongs_to(Format))]
-#[diesel(belongs_to(Tag))]
-#[diesel(primary_key(format_id, tag_id))]
 #[diesel(table_name = media)]
 pub struct Media {
+    pub id: i32,
+    pub url: String,
+    pub format_id: i32,
+    // unknown until the media is indexed
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub hash: Option<String>,
+    pub added_at: NaiveDateTime,
+    pub palette: Option<String>,
+    pub histogram: Option<Vec<u8>>,
+    pub phash: Option<i64>,
+    pub embedding: Option<Vec<u8>>,
+}
+
+#[derive(Insertable, AsChangeset, Debug, PartialEq)]
+#[diesel(table_name = media)]
+pub struct NewMedia {
     pub url: String,
     pub format_id: i32,
+    pub width: i32,
+    pub height: i32,
+    pub hash: String,
+    pub palette: String,
+    pub histogram: Vec<u8>,
+    pub phash: i64,
+}
+
+#[derive(Queryable, Identifiable, Selectable, Associations, Insertable, Debug, PartialEq)]
+#[diesel(belongs_to(Media))]
+#[diesel(belongs_to(Tag))]
+#[diesel(primary_key(media_id, tag_id))]
+#[diesel(table_name = media_tag)]
+pub struct MediaTag {
+    pub media_id: i32,
     pub tag_id: i32,
+    // set by the tagger, none for the tags set by hand
+    pub confidence: Option<f32>,
+}
+
+#[derive(Queryable, Identifiable, Selectable, Associations, Debug, PartialEq)]
+#[diesel(belongs_to(Media))]
+#[diesel(table_name = history)]
+pub struct History {
+    pub id: i32,
+    pub media_id: i32,
+    pub shown_at: NaiveDateTime,
+    // seconds until the next media, unknown for the last one
+    pub duration: Option<f32>,
+    pub rating: i32,
+}
+
+#[derive(Insertable, Debug, PartialEq)]
+#[diesel(table_name = history)]
+pub struct NewHistory {
+    pub media_id: i32,
+    pub shown_at: NaiveDateTime,
 }
 
 #[derive(Queryable, Identifiable, Selectable, Debug, PartialEq)]
@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use diesel::dsl::not;
+use diesel::prelude::*;
+#[cfg(feature = "onnx")]
+use image::imageops::FilterType;
+use image::DynamicImage;
+#[cfg(feature = "onnx")]
+use tract_onnx::prelude::*;
+use yaml_rust::Yaml;
+
+use crate::database::{with_connection, DbConnection};
+use crate::media_config::MediaConfig;
+use crate::schema::*;
+use crate::sql_models::*;
+
+// ImageNet normalization, the one most image classifiers are trained with
+#[cfg(feature = "onnx")]
+const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+#[cfg(feature = "onnx")]
+const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub confidence: f32,
+}
+
+pub trait Tagger {
+    // every label the model knows with its confidence for the image, in any order
+    fn labels(&self, image: &DynamicImage) -> Vec<Label>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaggerConfig {
+    // ONNX model with a 1x3xSxS float input, nothing is downloaded
+    pub model: Option<PathBuf>,
+    // one label per line, in the order of the model outputs
+    pub labels: Option<PathBuf>,
+    pub input_size: u32,
+    // outputs are logits to turn into confidences
+    pub softmax: bool,
+    // labels kept per media
+    pub top_k: usize,
+    pub min_confidence: f32,
+}
+
+impl TaggerConfig {
+    pub fn new(cfg: &Yaml) -> Self {
+        Self {
+            model: cfg["model"].as_str().map(PathBuf::from),
+            labels: cfg["labels"].as_str().map(PathBuf::from),
+            input_size: cfg["input_size"].as_i64().unwrap() as u32,
+            softmax: cfg["softmax"].as_bool().unwrap(),
+            top_k: cfg["top_k"].as_i64().unwrap() as usize,
+            min_confidence: cfg["min_confidence"].as_f64().unwrap() as f32,
+        }
+    }
+}
+
+#[cfg(feature = "onnx")]
+pub struct OnnxModel {
+    plan: TypedRunnableModel<TypedModel>,
+    input_size: u32,
+}
+
+#[cfg(feature = "onnx")]
+impl OnnxModel {
+    pub fn new(path: &PathBuf, input_size: u32) -> Self {
+        let size = input_size as usize;
+        let plan = tract_onnx::onnx()
+            .model_for_path(path)
+            .and_then(|m| m.with_input_fact(0, f32::fact([1, 3, size, size]).into()))
+            .and_then(|m| m.into_optimized())
+            .and_then(|m| m.into_runnable())
+            .unwrap_or_else(|e| panic!("Unable to load the model {:?}: {}", path, e));
+        Self { plan, input_size }
+    }
+
+    pub fn run(&self, image: &DynamicImage) -> Vec<f32> {
+        // first output of the model, flattened
+        let size = self.input_size;
+        let rgb = image
+            .resize_exact(size, size, FilterType::Triangle)
+            .into_rgb8();
+        let input: Tensor = tract_ndarray::Array4::from_shape_fn(
+            (1, 3, size as usize, size as usize),
+            |(_, c, y, x)| {
+                let v = rgb.get_pixel(x as u32, y as u32)[c] as f32 / 255.;
+                (v - MEAN[c]) / STD[c]
+            },
+        )
+        .into();
+        let outputs = self
+            .plan
+            .run(tvec!(input.into()))
+            .expect("Model inference failed");
+        outputs[0]
+            .to_array_view::<f32>()
+            .expect("Model output isn't float")
+            .iter()
+            .copied()
+            .collect()
+    }
+}
+
+// built without tract, a configured model can't be loaded
+#[cfg(not(feature = "onnx"))]
+pub struct OnnxModel;
+
+#[cfg(not(feature = "onnx"))]
+impl OnnxModel {
+    pub fn new(path: &PathBuf, _input_size: u32) -> Self {
+        panic!(
+            "Unable to load the model {:?}: cudi is built without the onnx feature",
+            path
+        );
+    }
+
+    pub fn run(&self, _image: &DynamicImage) -> Vec<f32> {
+        unreachable!()
+    }
+}
+
+fn softmax(scores: &mut [f32]) {
+    // logits to confidences summing to 1, shifted by the top one to not overflow
+    let top = scores.iter().copied().fold(f32::MIN, f32::max);
+    scores.iter_mut().for_each(|s| *s = (*s - top).exp());
+    let total: f32 = scores.iter().sum();
+    scores.iter_mut().for_each(|s| *s /= total);
+}
+
+pub struct OnnxTagger {
+    model: OnnxModel,
+    labels: Vec<String>,
+    softmax: bool,
+}
+
+impl OnnxTagger {
+    pub fn new(config: &TaggerConfig) -> Self {
+        let model = config
+            .model
+            .as_ref()
+            .expect("No tagger model in the media config");
+        let labels = config
+            .labels
+            .as_ref()
+            .expect("No tagger labels in the media config");
+        let labels = fs::read_to_string(labels)
+            .unwrap_or_else(|_| panic!("Unable to read the labels {:?}", labels))
+            .lines()
+            .map(|l| l.trim().to_string())
+            .collect();
+        Self {
+            model: OnnxModel::new(model, config.input_size),
+            labels,
+            softmax: config.softmax,
+        }
+    }
+}
+
+impl Tagger for OnnxTagger {
+    fn labels(&self, image: &DynamicImage) -> Vec<Label> {
+        let mut scores = self.model.run(image);
+        if scores.len() != self.labels.len() {
+            panic!(
+                "The model gives {} scores for {} labels",
+                scores.len(),
+                self.labels.len()
+            );
+        }
+        if self.softmax {
+            softmax(&mut scores);
+        }
+        self.labels
+            .iter()
+            .zip(scores)
+            .map(|(name, confidence)| Label {
+                name: name.clone(),
+                confidence,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct TagReport {
+    pub tagged: usize,
+    pub labels: usize,
+    // files missing or that can't be decoded
+    pub skipped: usize,
+}
+
+impl fmt::Display for TagReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} media tagged with {} labels, {} skipped",
+            self.tagged, self.labels, self.skipped
+        )
+    }
+}
+
+pub struct TagWriter {
+    connection: DbConnection,
+    // tag ids by name, created when missing
+    tags: HashMap<String, i32>,
+    top_k: usize,
+    min_confidence: f32,
+}
+
+impl TagWriter {
+    pub fn new(config: &MediaConfig) -> Self {
+        Self {
+            connection: DbConnection::establish(&config.database_url),
+            tags: HashMap::new(),
+            top_k: config.tagger.top_k,
+            min_confidence: config.tagger.min_confidence,
+        }
+    }
+
+    fn tag_id(&mut self, name: &str) -> i32 {
+        if let Some(id) = self.tags.get(name) {
+            return *id;
+        }
+        let id = with_connection!(&mut self.connection, conn => {
+            diesel::insert_into(tag::table)
+                .values(tag::name.eq(name))
+                .on_conflict(tag::name)
+                .do_nothing()
+                .execute(conn)
+                .expect("Failed request");
+            tag::table
+                .filter(tag::name.eq(name))
+                .select(tag::id)
+                .first(conn)
+                .expect("Failed request")
+        });
+        self.tags.insert(name.to_string(), id);
+        id
+    }
+
+    fn best(&self, mut labels: Vec<Label>) -> Vec<Label> {
+        labels.retain(|l| l.confidence >= self.min_confidence);
+        labels.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        labels.truncate(self.top_k);
+        labels
+    }
+
+    pub fn run(&mut self, tagger: &dyn Tagger, retag: bool) -> TagReport {
+        /*
+            Store the top K labels of the media as tags with their confidence
+            Media already tagged by a model are left alone unless retag is set,
+            the tags set by hand are never changed
+        */
+        let mut report = TagReport::default();
+        let media: Vec<Media> = with_connection!(&mut self.connection, conn => {
+            let mut query = media::table.select(Media::as_select()).into_boxed();
+            if !retag {
+                // clusters have a negative confidence
+                let tagged = media_tag::table
+                    .filter(media_tag::confidence.ge(0f32))
+                    .select(media_tag::media_id);
+                query = query.filter(not(media::id.eq_any(tagged)));
+            }
+            query.load(conn).expect("Failed request")
+        });
+
+        for m in media {
+            let image = match image::open(&m.url) {
+                Ok(i) => i,
+                Err(_) => {
+                    println!("Unable to open {}", m.url);
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            let rows: Vec<MediaTag> = self
+                .best(tagger.labels(&image))
+                .iter()
+                .map(|l| MediaTag {
+                    media_id: m.id,
+                    tag_id: self.tag_id(&l.name),
+                    confidence: Some(l.confidence),
+                })
+                .collect();
+            with_connection!(&mut self.connection, conn => {
+                diesel::delete(
+                    media_tag::table
+                        .filter(media_tag::media_id.eq(m.id))
+                        .filter(media_tag::confidence.ge(0f32)),
+                )
+                .execute(conn)
+                .expect("Failed request");
+                // a label already set by hand stay a hand tag
+                for row in &rows {
+                    diesel::insert_into(media_tag::table)
+                        .values(row)
+                        .on_conflict((media_tag::media_id, media_tag::tag_id))
+                        .do_nothing()
+                        .execute(conn)
+                        .expect("Failed request");
+                }
+            });
+            report.tagged += 1;
+            report.labels += rows.len();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // same labels whatever the image
+    struct StubTagger(Vec<Label>);
+
+    impl Tagger for StubTagger {
+        fn labels(&self, _image: &DynamicImage) -> Vec<Label> {
+            self.0.clone()
+        }
+    }
+
+    fn label(name: &str, confidence: f32) -> Label {
+        Label {
+            name: name.to_string(),
+            confidence,
+        }
+    }
+
+    fn writer(top_k: usize, min_confidence: f32) -> TagWriter {
+        TagWriter {
+            connection: DbConnection::establish("sqlite://:memory:"),
+            tags: HashMap::new(),
+            top_k,
+            min_confidence,
+        }
+    }
+
+    fn add_media(writer: &mut TagWriter, name: &str) -> i32 {
+        // a real image, the tagger open it
+        let path = std::env::temp_dir().join(format!("cudi_tagger_{}.png", name));
+        DynamicImage::new_rgb8(4, 4).save(&path).unwrap();
+        let url = path.to_string_lossy().into_owned();
+        with_connection!(&mut writer.connection, conn => {
+            diesel::insert_into(format::table)
+                .values(format::name.eq("PNG"))
+                .on_conflict(format::name)
+                .do_nothing()
+                .execute(conn)
+                .unwrap();
+            let format_id: i32 = format::table.select(format::id).first(conn).unwrap();
+            diesel::insert_into(media::table)
+                .values((media::url.eq(&url), media::format_id.eq(format_id)))
+                .execute(conn)
+                .unwrap();
+            media::table
+                .filter(media::url.eq(&url))
+                .select(media::id)
+                .first(conn)
+                .unwrap()
+        })
+    }
+
+    fn tag_by_hand(writer: &mut TagWriter, media_id: i32, name: &str) {
+        let tag_id = writer.tag_id(name);
+        with_connection!(&mut writer.connection, conn => {
+            diesel::insert_into(media_tag::table)
+                .values(MediaTag {
+                    media_id,
+                    tag_id,
+                    confidence: None,
+                })
+                .execute(conn)
+                .unwrap()
+        });
+    }
+
+    fn tags_of(writer: &mut TagWriter, media_id: i32) -> Vec<(String, Option<f32>)> {
+        with_connection!(&mut writer.connection, conn => {
+            media_tag::table
+                .inner_join(tag::table)
+                .filter(media_tag::media_id.eq(media_id))
+                .select((tag::name, media_tag::confidence))
+                .order(tag::name)
+                .load(conn)
+                .unwrap()
+        })
+    }
+
+    #[test]
+    fn softmax_sums_to_one() {
+        let mut scores = [1., 2., 3.];
+        softmax(&mut scores);
+        assert!((scores.iter().sum::<f32>() - 1.).abs() < 1e-6);
+        assert!(scores[0] < scores[1] && scores[1] < scores[2]);
+        assert!((scores[2] - 0.665241).abs() < 1e-5);
+        // large logits don't overflow
+        let mut scores = [1000., 1000.];
+        softmax(&mut scores);
+        assert_eq!(scores, [0.5, 0.5]);
+    }
+
+    #[test]
+    fn best_labels() {
+        let labels = vec![
+            label("sea", 0.2),
+            label("sun", 0.5),
+            label("sand", 0.05),
+            label("tree", 0.25),
+        ];
+        assert_eq!(
+            writer(2, 0.).best(labels.clone()),
+            [label("sun", 0.5), label("tree", 0.25)]
+        );
+        assert_eq!(
+            writer(10, 0.2).best(labels.clone()),
+            [label("sun", 0.5), label("tree", 0.25), label("sea", 0.2)]
+        );
+        assert!(writer(10, 0.9).best(labels).is_empty());
+    }
+
+    #[test]
+    fn run_stores_the_best_labels() {
+        let mut writer = writer(2, 0.1);
+        let media_id = add_media(&mut writer, "best");
+        let tagger = StubTagger(vec![
+            label("sea", 0.6),
+            label("sun", 0.3),
+            label("sand", 0.1),
+        ]);
+        let report = writer.run(&tagger, false);
+        assert_eq!(
+            report,
+            TagReport {
+                tagged: 1,
+                labels: 2,
+                skipped: 0
+            }
+        );
+        assert_eq!(
+            tags_of(&mut writer, media_id),
+            [
+                (String::from("sea"), Some(0.6)),
+                (String::from("sun"), Some(0.3))
+            ]
+        );
+        // already tagged by the model
+        assert_eq!(writer.run(&tagger, false).tagged, 0);
+    }
+
+    #[test]
+    fn retag_keeps_the_hand_tags() {
+        let mut writer = writer(2, 0.);
+        let media_id = add_media(&mut writer, "retag");
+        tag_by_hand(&mut writer, media_id, "sea");
+        tag_by_hand(&mut writer, media_id, "night");
+        writer.run(
+            &StubTagger(vec![label("sea", 0.9), label("sun", 0.1)]),
+            false,
+        );
+        writer.run(
+            &StubTagger(vec![label("sea", 0.2), label("sand", 0.8)]),
+            true,
+        );
+        assert_eq!(
+            tags_of(&mut writer, media_id),
+            [
+                (String::from("night"), None),
+                (String::from("sand"), Some(0.8)),
+                (String::from("sea"), None)
+            ]
+        );
+    }
+}